@@ -0,0 +1,105 @@
+mod common;
+
+use common::Distribution;
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use sketches_rust::{
+    CollapsingHighestDenseStore, CollapsingLowestDenseStore, CollapsingLowestSparseStore, DDSketch,
+    DDSketchBuilder, PaginatedStore, SparseStore, Store, StoreKind, UnboundedSizeDenseStore,
+};
+
+const RELATIVE_ACCURACY: f64 = 0.01;
+const MAX_NUM_BINS: usize = 2048;
+const NUM_VALUES: usize = 10_000;
+
+fn accept_all<P: Store, N: Store>(sketch: &mut DDSketch<P, N>, values: &[f64]) {
+    for value in values {
+        sketch.accept(black_box(*value));
+    }
+}
+
+fn bench_store<S: Store + Clone>(
+    criterion: &mut Criterion,
+    name: &str,
+    store_kind: StoreKind,
+    store: S,
+    values: &[f64],
+) {
+    let builder = DDSketchBuilder::new().relative_accuracy(RELATIVE_ACCURACY);
+    let boxed = builder.clone().stores(store_kind).build().unwrap();
+    let generic = builder
+        .build_with_stores(store.clone(), store.clone())
+        .unwrap();
+
+    let mut group = criterion.benchmark_group("accept");
+    group.throughput(Throughput::Elements(values.len() as u64));
+    group.bench_with_input(BenchmarkId::new("boxed", name), values, |b, values| {
+        b.iter_batched_ref(
+            || boxed.clone(),
+            |sketch| accept_all(sketch, values),
+            criterion::BatchSize::SmallInput,
+        )
+    });
+    group.bench_with_input(BenchmarkId::new("generic", name), values, |b, values| {
+        b.iter_batched_ref(
+            || generic.clone(),
+            |sketch| accept_all(sketch, values),
+            criterion::BatchSize::SmallInput,
+        )
+    });
+    group.finish();
+}
+
+fn bench_accept(criterion: &mut Criterion) {
+    let values = Distribution::LogNormal.values(NUM_VALUES);
+    bench_store(
+        criterion,
+        "unbounded_dense",
+        StoreKind::UnboundedDense,
+        UnboundedSizeDenseStore::new(),
+        &values,
+    );
+    bench_store(
+        criterion,
+        "paginated",
+        StoreKind::Paginated,
+        PaginatedStore::new(),
+        &values,
+    );
+    bench_store(
+        criterion,
+        "sparse",
+        StoreKind::Sparse,
+        SparseStore::new(),
+        &values,
+    );
+    bench_store(
+        criterion,
+        "collapsing_lowest_dense",
+        StoreKind::CollapsingLowestDense {
+            max_num_bins: MAX_NUM_BINS,
+        },
+        CollapsingLowestDenseStore::with_capacity(MAX_NUM_BINS).unwrap(),
+        &values,
+    );
+    bench_store(
+        criterion,
+        "collapsing_highest_dense",
+        StoreKind::CollapsingHighestDense {
+            max_num_bins: MAX_NUM_BINS,
+        },
+        CollapsingHighestDenseStore::with_capacity(MAX_NUM_BINS).unwrap(),
+        &values,
+    );
+    bench_store(
+        criterion,
+        "collapsing_lowest_sparse",
+        StoreKind::CollapsingLowestSparse {
+            max_num_bins: MAX_NUM_BINS,
+        },
+        CollapsingLowestSparseStore::with_capacity(MAX_NUM_BINS).unwrap(),
+        &values,
+    );
+}
+
+criterion_group!(benches, bench_accept);
+criterion_main!(benches);
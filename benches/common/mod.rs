@@ -0,0 +1,60 @@
+//! Inputs shared by the benchmarks. Values come from a fixed seed so that runs are comparable.
+
+// Each benchmark only uses some of the inputs.
+#![allow(dead_code)]
+
+#[derive(Clone, Copy, Debug)]
+pub enum Distribution {
+    /// Uniform between 0 and 1000.
+    Uniform,
+    /// Log-normal with a location of 0 and a scale of 2.
+    LogNormal,
+    /// Pareto with a scale of 1 and a shape of 1.
+    Pareto,
+}
+
+pub const DISTRIBUTIONS: [Distribution; 3] = [
+    Distribution::Uniform,
+    Distribution::LogNormal,
+    Distribution::Pareto,
+];
+
+impl Distribution {
+    pub fn name(self) -> &'static str {
+        match self {
+            Distribution::Uniform => "uniform",
+            Distribution::LogNormal => "lognormal",
+            Distribution::Pareto => "pareto",
+        }
+    }
+
+    pub fn values(self, num_values: usize) -> Vec<f64> {
+        let mut generator = XorShift(0x9E37_79B9_7F4A_7C15);
+        (0..num_values)
+            .map(|_| match self {
+                Distribution::Uniform => generator.next_f64() * 1000.0,
+                Distribution::LogNormal => f64::exp(2.0 * generator.next_gaussian()),
+                Distribution::Pareto => 1.0 / (1.0 - generator.next_f64()),
+            })
+            .collect()
+    }
+}
+
+struct XorShift(u64);
+
+impl XorShift {
+    /// Returns a value in `[0, 1)`.
+    fn next_f64(&mut self) -> f64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        (self.0 >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// Returns a standard normal value, using the Box-Muller transform.
+    fn next_gaussian(&mut self) -> f64 {
+        let u1 = 1.0 - self.next_f64();
+        let u2 = self.next_f64();
+        f64::sqrt(-2.0 * f64::ln(u1)) * f64::cos(2.0 * std::f64::consts::PI * u2)
+    }
+}
@@ -0,0 +1,228 @@
+mod common;
+
+use common::DISTRIBUTIONS;
+use criterion::{
+    black_box, criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion, Throughput,
+};
+use sketches_rust::{DDSketch, DDSketchBuilder, IndexMappingLayout};
+use std::time::Duration;
+
+const RELATIVE_ACCURACY: f64 = 0.01;
+const MAX_NUM_BINS: usize = 2048;
+const NUM_VALUES: usize = 10_000;
+const QUANTILES: [f64; 7] = [0.0, 0.1, 0.5, 0.9, 0.99, 0.999, 1.0];
+
+/// A named way of building an empty sketch: every factory constructor, then every index mapping
+/// layout with unbounded dense stores.
+struct Config {
+    name: String,
+    build: Box<dyn Fn() -> DDSketch>,
+}
+
+impl Config {
+    fn new(name: &str, build: impl Fn() -> DDSketch + 'static) -> Config {
+        Config {
+            name: name.to_string(),
+            build: Box::new(build),
+        }
+    }
+
+    fn filled(&self, values: &[f64]) -> DDSketch {
+        let mut sketch = (self.build)();
+        for value in values {
+            sketch.accept(*value);
+        }
+        sketch
+    }
+}
+
+fn configs() -> Vec<Config> {
+    let mut configs = vec![
+        Config::new("collapsing_lowest_dense", || {
+            DDSketch::collapsing_lowest_dense(RELATIVE_ACCURACY, MAX_NUM_BINS).unwrap()
+        }),
+        Config::new("collapsing_highest_dense", || {
+            DDSketch::collapsing_highest_dense(RELATIVE_ACCURACY, MAX_NUM_BINS).unwrap()
+        }),
+        Config::new("unbounded_dense", || {
+            DDSketch::unbounded_dense(RELATIVE_ACCURACY).unwrap()
+        }),
+        Config::new("paginated", || {
+            DDSketch::paginated(RELATIVE_ACCURACY).unwrap()
+        }),
+        Config::new("sparse", || DDSketch::sparse(RELATIVE_ACCURACY).unwrap()),
+        Config::new("collapsing_lowest_sparse", || {
+            DDSketch::collapsing_lowest_sparse(RELATIVE_ACCURACY, MAX_NUM_BINS).unwrap()
+        }),
+        Config::new("logarithmic_collapsing_lowest_dense", || {
+            DDSketch::logarithmic_collapsing_lowest_dense(RELATIVE_ACCURACY, MAX_NUM_BINS).unwrap()
+        }),
+        Config::new("logarithmic_collapsing_highest_dense", || {
+            DDSketch::logarithmic_collapsing_highest_dense(RELATIVE_ACCURACY, MAX_NUM_BINS).unwrap()
+        }),
+        Config::new("logarithmic_unbounded_size_dense_store", || {
+            DDSketch::logarithmic_unbounded_size_dense_store(RELATIVE_ACCURACY).unwrap()
+        }),
+        Config::new("logarithmic_paginated", || {
+            DDSketch::logarithmic_paginated(RELATIVE_ACCURACY).unwrap()
+        }),
+        Config::new("logarithmic_sparse", || {
+            DDSketch::logarithmic_sparse(RELATIVE_ACCURACY).unwrap()
+        }),
+        Config::new("logarithmic_collapsing_lowest_sparse", || {
+            DDSketch::logarithmic_collapsing_lowest_sparse(RELATIVE_ACCURACY, MAX_NUM_BINS).unwrap()
+        }),
+    ];
+    for layout in [
+        IndexMappingLayout::LOG,
+        IndexMappingLayout::LogLinear,
+        IndexMappingLayout::LogQuadratic,
+        IndexMappingLayout::LogCubic,
+        IndexMappingLayout::LogQuartic,
+    ] {
+        configs.push(Config::new(&format!("{:?}", layout), move || {
+            DDSketchBuilder::new()
+                .index_layout(layout)
+                .relative_accuracy(RELATIVE_ACCURACY)
+                .build()
+                .unwrap()
+        }));
+    }
+    configs
+}
+
+fn bench_accept(criterion: &mut Criterion) {
+    let mut group = criterion.benchmark_group("accept");
+    group.throughput(Throughput::Elements(NUM_VALUES as u64));
+    for distribution in DISTRIBUTIONS {
+        let values = distribution.values(NUM_VALUES);
+        for config in configs() {
+            let id = BenchmarkId::new(&config.name, distribution.name());
+            group.bench_with_input(id, &values, |b, values| {
+                b.iter_batched_ref(
+                    || (config.build)(),
+                    |sketch| {
+                        for value in values {
+                            sketch.accept(black_box(*value));
+                        }
+                    },
+                    BatchSize::SmallInput,
+                )
+            });
+        }
+    }
+    group.finish();
+}
+
+fn bench_get_value_at_quantile(criterion: &mut Criterion) {
+    let mut group = criterion.benchmark_group("get_value_at_quantile");
+    group.throughput(Throughput::Elements(QUANTILES.len() as u64));
+    for distribution in DISTRIBUTIONS {
+        let values = distribution.values(NUM_VALUES);
+        for config in configs() {
+            let sketch = config.filled(&values);
+            let id = BenchmarkId::new(&config.name, distribution.name());
+            group.bench_with_input(id, &sketch, |b, sketch| {
+                b.iter(|| {
+                    for quantile in QUANTILES {
+                        black_box(sketch.get_value_at_quantile(black_box(quantile)));
+                    }
+                })
+            });
+        }
+    }
+    group.finish();
+}
+
+fn bench_merge_with(criterion: &mut Criterion) {
+    let mut group = criterion.benchmark_group("merge_with");
+    for distribution in DISTRIBUTIONS {
+        let values = distribution.values(NUM_VALUES);
+        let (left, right) = values.split_at(NUM_VALUES / 2);
+        for config in configs() {
+            let sketch = config.filled(left);
+            let other = config.filled(right);
+            let id = BenchmarkId::new(&config.name, distribution.name());
+            group.bench_with_input(id, &other, |b, other| {
+                b.iter_batched_ref(
+                    || sketch.clone(),
+                    |sketch| sketch.merge_with(other).unwrap(),
+                    BatchSize::SmallInput,
+                )
+            });
+        }
+    }
+    group.finish();
+}
+
+fn bench_encode(criterion: &mut Criterion) {
+    let mut group = criterion.benchmark_group("encode");
+    for distribution in DISTRIBUTIONS {
+        let values = distribution.values(NUM_VALUES);
+        for config in configs() {
+            let sketch = config.filled(&values);
+            let id = BenchmarkId::new(&config.name, distribution.name());
+            group.bench_with_input(id, &sketch, |b, sketch| b.iter(|| sketch.encode().unwrap()));
+        }
+    }
+    group.finish();
+}
+
+fn bench_decode(criterion: &mut Criterion) {
+    let mut group = criterion.benchmark_group("decode");
+    for distribution in DISTRIBUTIONS {
+        let values = distribution.values(NUM_VALUES);
+        for config in configs() {
+            let bytes = config.filled(&values).encode().unwrap();
+            let id = BenchmarkId::new(&config.name, distribution.name());
+            group.bench_with_input(id, &bytes, |b, bytes| {
+                b.iter_batched_ref(
+                    || (config.build)(),
+                    |sketch| sketch.decode_and_merge_with(bytes).unwrap(),
+                    BatchSize::SmallInput,
+                )
+            });
+        }
+    }
+    group.finish();
+}
+
+/// Prints how much memory each sketch uses once filled, since criterion only measures time.
+fn report_memory_footprint(_: &mut Criterion) {
+    println!(
+        "\n{:<40} {:>12} {:>12} {:>12}",
+        "memory footprint (bytes)",
+        DISTRIBUTIONS[0].name(),
+        DISTRIBUTIONS[1].name(),
+        DISTRIBUTIONS[2].name()
+    );
+    let values: Vec<Vec<f64>> = DISTRIBUTIONS
+        .iter()
+        .map(|distribution| distribution.values(NUM_VALUES))
+        .collect();
+    for config in configs() {
+        let footprints: Vec<usize> = values
+            .iter()
+            .map(|values| config.filled(values).get_memory_footprint())
+            .collect();
+        println!(
+            "{:<40} {:>12} {:>12} {:>12}",
+            config.name, footprints[0], footprints[1], footprints[2]
+        );
+    }
+    println!();
+}
+
+criterion_group! {
+    name = benches;
+    config = Criterion::default()
+        .sample_size(20)
+        .measurement_time(Duration::from_secs(2));
+    targets = report_memory_footprint,
+        bench_accept,
+        bench_get_value_at_quantile,
+        bench_merge_with,
+        bench_encode,
+        bench_decode
+}
+criterion_main!(benches);
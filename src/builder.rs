@@ -0,0 +1,244 @@
+use crate::error::Error;
+use crate::index_mapping::{IndexMapping, IndexMappingLayout};
+use crate::sketch::DDSketch;
+use crate::store::{
+    CollapsingHighestDenseStore, CollapsingLowestDenseStore, CollapsingLowestSparseStore,
+    PaginatedStore, SparseStore, Store, UnboundedSizeDenseStore,
+};
+
+/// The implementation of the store that keeps the bins of one side of a sketch.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StoreKind {
+    /// A dense store that grows without limit.
+    UnboundedDense,
+    /// A dense store that allocates fixed-size pages lazily.
+    Paginated,
+    /// A store that only keeps non-empty bins.
+    Sparse,
+    /// A dense store that collapses its lowest bins beyond `max_num_bins`.
+    CollapsingLowestDense { max_num_bins: usize },
+    /// A dense store that collapses its highest bins beyond `max_num_bins`.
+    CollapsingHighestDense { max_num_bins: usize },
+    /// A sparse store that collapses its lowest bins beyond `max_num_bins`.
+    CollapsingLowestSparse { max_num_bins: usize },
+}
+
+impl StoreKind {
+    fn build(self) -> Result<Box<dyn Store>, Error> {
+        Ok(match self {
+            StoreKind::UnboundedDense => Box::new(UnboundedSizeDenseStore::new()),
+            StoreKind::Paginated => Box::new(PaginatedStore::new()),
+            StoreKind::Sparse => Box::new(SparseStore::new()),
+            StoreKind::CollapsingLowestDense { max_num_bins } => {
+                Box::new(CollapsingLowestDenseStore::with_capacity(max_num_bins)?)
+            }
+            StoreKind::CollapsingHighestDense { max_num_bins } => {
+                Box::new(CollapsingHighestDenseStore::with_capacity(max_num_bins)?)
+            }
+            StoreKind::CollapsingLowestSparse { max_num_bins } => {
+                Box::new(CollapsingLowestSparseStore::with_capacity(max_num_bins)?)
+            }
+        })
+    }
+}
+
+/// Configures a [`DDSketch`]. Either the relative accuracy or the gamma and index offset of the
+/// index mapping must be set; everything else has a default: the `LogCubic` layout, unbounded
+/// dense stores on both sides, the whole indexable range of the mapping and no exact summary
+/// statistics.
+///
+/// ```
+/// use sketches_rust::{DDSketchBuilder, IndexMappingLayout, StoreKind};
+/// let sketch = DDSketchBuilder::new()
+///     .index_layout(IndexMappingLayout::LOG)
+///     .relative_accuracy(0.01)
+///     .positive_store(StoreKind::CollapsingLowestDense { max_num_bins: 2048 })
+///     .negative_store(StoreKind::Sparse)
+///     .exact_summary_statistics(true)
+///     .build()
+///     .unwrap();
+/// assert!(sketch.has_exact_summary_statistics());
+/// ```
+#[derive(Clone, Debug)]
+pub struct DDSketchBuilder {
+    index_layout: IndexMappingLayout,
+    relative_accuracy: Option<f64>,
+    gamma_offset: Option<(f64, f64)>,
+    positive_store: StoreKind,
+    negative_store: StoreKind,
+    min_indexed_value: Option<f64>,
+    max_indexed_value: Option<f64>,
+    exact_summary_statistics: bool,
+}
+
+impl Default for DDSketchBuilder {
+    fn default() -> Self {
+        DDSketchBuilder {
+            index_layout: IndexMappingLayout::LogCubic,
+            relative_accuracy: None,
+            gamma_offset: None,
+            positive_store: StoreKind::UnboundedDense,
+            negative_store: StoreKind::UnboundedDense,
+            min_indexed_value: None,
+            max_indexed_value: None,
+            exact_summary_statistics: false,
+        }
+    }
+}
+
+impl DDSketchBuilder {
+    pub fn new() -> Self {
+        DDSketchBuilder::default()
+    }
+
+    pub fn index_layout(mut self, index_layout: IndexMappingLayout) -> Self {
+        self.index_layout = index_layout;
+        self
+    }
+
+    pub fn relative_accuracy(mut self, relative_accuracy: f64) -> Self {
+        self.relative_accuracy = Some(relative_accuracy);
+        self
+    }
+
+    /// Sets the gamma and index offset of the index mapping, for instance to match the mapping of
+    /// sketches produced elsewhere, instead of deriving them from the relative accuracy.
+    pub fn gamma_offset(mut self, gamma: f64, index_offset: f64) -> Self {
+        self.gamma_offset = Some((gamma, index_offset));
+        self
+    }
+
+    pub fn positive_store(mut self, store: StoreKind) -> Self {
+        self.positive_store = store;
+        self
+    }
+
+    pub fn negative_store(mut self, store: StoreKind) -> Self {
+        self.negative_store = store;
+        self
+    }
+
+    /// Uses the same kind of store for the positive and the negative values.
+    pub fn stores(self, store: StoreKind) -> Self {
+        self.positive_store(store).negative_store(store)
+    }
+
+    /// Values whose magnitude is at most `min_indexed_value` are counted as zeros. It is raised to
+    /// the smallest value the index mapping can index if needed.
+    pub fn min_indexed_value(mut self, min_indexed_value: f64) -> Self {
+        self.min_indexed_value = Some(min_indexed_value);
+        self
+    }
+
+    /// Values whose magnitude is above `max_indexed_value` are ignored. It is lowered to the
+    /// largest value the index mapping can index if needed.
+    pub fn max_indexed_value(mut self, max_indexed_value: f64) -> Self {
+        self.max_indexed_value = Some(max_indexed_value);
+        self
+    }
+
+    pub fn exact_summary_statistics(mut self, exact_summary_statistics: bool) -> Self {
+        self.exact_summary_statistics = exact_summary_statistics;
+        self
+    }
+
+    pub fn build(&self) -> Result<DDSketch, Error> {
+        let (index_mapping, min_indexed_value, max_indexed_value) = self.index_range()?;
+        let sketch = DDSketch::with_stores(
+            index_mapping,
+            self.negative_store.build()?,
+            self.positive_store.build()?,
+            min_indexed_value,
+            max_indexed_value,
+        );
+        self.with_summary_statistics(sketch)
+    }
+
+    /// Builds a sketch that keeps its values in the given stores rather than in the boxed stores
+    /// of [`DDSketchBuilder::positive_store`] and [`DDSketchBuilder::negative_store`], which are
+    /// ignored. The store calls of the returned sketch are statically dispatched.
+    ///
+    /// ```
+    /// use sketches_rust::{DDSketchBuilder, SparseStore, UnboundedSizeDenseStore};
+    /// let mut sketch = DDSketchBuilder::new()
+    ///     .relative_accuracy(0.01)
+    ///     .build_with_stores(UnboundedSizeDenseStore::new(), SparseStore::new())
+    ///     .unwrap();
+    /// sketch.accept(1.0);
+    /// assert_eq!(1.0, sketch.get_count());
+    /// ```
+    pub fn build_with_stores<P: Store, N: Store>(
+        &self,
+        positive_value_store: P,
+        negative_value_store: N,
+    ) -> Result<DDSketch<P, N>, Error> {
+        let (index_mapping, min_indexed_value, max_indexed_value) = self.index_range()?;
+        let sketch = DDSketch::with_stores(
+            index_mapping,
+            negative_value_store,
+            positive_value_store,
+            min_indexed_value,
+            max_indexed_value,
+        );
+        self.with_summary_statistics(sketch)
+    }
+
+    fn with_summary_statistics<P: Store, N: Store>(
+        &self,
+        sketch: DDSketch<P, N>,
+    ) -> Result<DDSketch<P, N>, Error> {
+        if self.exact_summary_statistics {
+            sketch.with_exact_summary_statistics()
+        } else {
+            Ok(sketch)
+        }
+    }
+
+    /// Returns the index mapping and the range of indexed values.
+    fn index_range(&self) -> Result<(IndexMapping, f64, f64), Error> {
+        let index_mapping = match (self.relative_accuracy, self.gamma_offset) {
+            (Some(relative_accuracy), None) => {
+                IndexMapping::with_relative_accuracy(self.index_layout, relative_accuracy)?
+            }
+            (None, Some((gamma, index_offset))) => {
+                IndexMapping::with_gamma_offset(self.index_layout, gamma, index_offset)?
+            }
+            (Some(_), Some(_)) => {
+                return Err(Error::InvalidArgument(
+                    "The relative accuracy and gamma cannot both be set.",
+                ));
+            }
+            (None, None) => {
+                return Err(Error::InvalidArgument(
+                    "The relative accuracy or gamma must be set.",
+                ));
+            }
+        };
+
+        let mut min_indexed_value = f64::max(0.0, index_mapping.min_indexable_value());
+        if let Some(value) = self.min_indexed_value {
+            if value.is_nan() || value < 0.0 {
+                return Err(Error::InvalidArgument(
+                    "The minimum indexed value cannot be negative.",
+                ));
+            }
+            min_indexed_value = min_indexed_value.max(value);
+        }
+        let mut max_indexed_value = index_mapping.max_indexable_value();
+        if let Some(value) = self.max_indexed_value {
+            if value.is_nan() || value <= 0.0 {
+                return Err(Error::InvalidArgument(
+                    "The maximum indexed value must be positive.",
+                ));
+            }
+            max_indexed_value = max_indexed_value.min(value);
+        }
+        if min_indexed_value >= max_indexed_value {
+            return Err(Error::InvalidArgument(
+                "The minimum indexed value must be less than the maximum indexed value.",
+            ));
+        }
+
+        Ok((index_mapping, min_indexed_value, max_indexed_value))
+    }
+}
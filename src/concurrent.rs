@@ -0,0 +1,103 @@
+use crate::error::Error;
+use crate::sketch::DDSketch;
+use crate::store::Store;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Mutex, MutexGuard, PoisonError};
+use std::thread;
+
+static NEXT_THREAD_STRIPE: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    static THREAD_STRIPE: usize = NEXT_THREAD_STRIPE.fetch_add(1, Ordering::Relaxed);
+}
+
+/// A sketch that can be fed from many threads at once. Values are recorded into one of several
+/// independently locked stripes, each thread preferring its own, and the stripes are merged into a
+/// regular [`DDSketch`] on [`ConcurrentDDSketch::snapshot`].
+pub struct ConcurrentDDSketch<P = Box<dyn Store>, N = Box<dyn Store>> {
+    stripes: Box<[Mutex<DDSketch<P, N>>]>,
+}
+
+impl<P: Store + Clone, N: Store + Clone> ConcurrentDDSketch<P, N> {
+    /// Creates a concurrent sketch with one stripe per available CPU. Every stripe has the same
+    /// index mapping, stores and summary statistics configuration as `sketch`, whose values are
+    /// kept.
+    pub fn new(sketch: DDSketch<P, N>) -> Self {
+        let num_stripes = thread::available_parallelism()
+            .map(|parallelism| parallelism.get())
+            .unwrap_or(1);
+        ConcurrentDDSketch::with_stripes(sketch, num_stripes)
+    }
+
+    pub fn with_num_stripes(sketch: DDSketch<P, N>, num_stripes: usize) -> Result<Self, Error> {
+        if num_stripes == 0 {
+            return Err(Error::InvalidArgument(
+                "The number of stripes must be positive.",
+            ));
+        }
+        Ok(ConcurrentDDSketch::with_stripes(sketch, num_stripes))
+    }
+
+    fn with_stripes(sketch: DDSketch<P, N>, num_stripes: usize) -> Self {
+        let mut empty_sketch = sketch.clone();
+        empty_sketch.clear();
+        let mut stripes = Vec::with_capacity(num_stripes);
+        stripes.push(Mutex::new(sketch));
+        for _ in 1..num_stripes {
+            stripes.push(Mutex::new(empty_sketch.clone()));
+        }
+        ConcurrentDDSketch {
+            stripes: stripes.into_boxed_slice(),
+        }
+    }
+
+    pub fn num_stripes(&self) -> usize {
+        self.stripes.len()
+    }
+
+    pub fn accept(&self, value: f64) {
+        self.lock_stripe().accept(value);
+    }
+
+    pub fn accept_with_count(&self, value: f64, count: f64) -> Result<(), Error> {
+        self.lock_stripe().accept_with_count(value, count)
+    }
+
+    /// Returns a sketch holding every value recorded so far. All the stripes are locked together
+    /// while they are merged, so the snapshot reflects a single point in time.
+    pub fn snapshot(&self) -> Result<DDSketch<P, N>, Error> {
+        let stripes: Vec<MutexGuard<DDSketch<P, N>>> =
+            self.stripes.iter().map(ConcurrentDDSketch::lock).collect();
+        let mut snapshot = stripes[0].clone();
+        for stripe in &stripes[1..] {
+            snapshot.merge_with(stripe)?;
+        }
+        Ok(snapshot)
+    }
+
+    /// Removes every value recorded so far.
+    pub fn clear(&self) {
+        let mut stripes: Vec<MutexGuard<DDSketch<P, N>>> =
+            self.stripes.iter().map(ConcurrentDDSketch::lock).collect();
+        for stripe in stripes.iter_mut() {
+            stripe.clear();
+        }
+    }
+
+    /// Locks the stripe of the calling thread, or the first free one if it is busy.
+    fn lock_stripe(&self) -> MutexGuard<'_, DDSketch<P, N>> {
+        let num_stripes = self.stripes.len();
+        let home = THREAD_STRIPE.with(|stripe| *stripe) % num_stripes;
+        for i in 0..num_stripes {
+            if let Ok(stripe) = self.stripes[(home + i) % num_stripes].try_lock() {
+                return stripe;
+            }
+        }
+        ConcurrentDDSketch::lock(&self.stripes[home])
+    }
+
+    // A poisoned stripe still holds a usable sketch, so the poisoning is ignored.
+    fn lock(stripe: &Mutex<DDSketch<P, N>>) -> MutexGuard<'_, DDSketch<P, N>> {
+        stripe.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
@@ -0,0 +1,123 @@
+use crate::error::Error;
+use crate::store::Store;
+
+/// Limits and checks applied when decoding sketches, to bound the memory and time spent on
+/// untrusted input. By default, any well-formed input is accepted.
+///
+/// They apply to the `decode*_with_options` methods of [`DDSketch`](crate::DDSketch), to
+/// `from_protobuf_with_options` with the `protobuf` feature, and to serde with the `serde`
+/// feature through `DDSketchSeed`. Plain `Deserialize` uses the default options, except that it
+/// rejects invalid counts. Some input is still read before the limits apply:
+/// - with serde, the deserializer reads the bytes of compact formats before `max_len` is checked,
+///   and `max_len` does not apply to self-describing formats, whose size is up to the caller;
+/// - the exact summary statistics are taken as they are, without the count checks.
+///
+/// ```
+/// use sketches_rust::{DDSketch, DecodeOptions};
+/// let options = DecodeOptions::new()
+///     .max_num_bins(4096)
+///     .max_index_span(4096)
+///     .max_len(64 * 1024)
+///     .reject_invalid_counts(true)
+///     .reject_duplicate_index_mappings(true);
+/// let mut sketch = DDSketch::unbounded_dense(0.01).unwrap();
+/// sketch.accept(1.0);
+/// let decoded = DDSketch::decode_with_options(sketch.encode().unwrap(), &options).unwrap();
+/// assert_eq!(1.0, decoded.get_count());
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DecodeOptions {
+    max_num_bins: Option<u64>,
+    max_index_span: Option<u64>,
+    max_len: Option<usize>,
+    reject_invalid_counts: bool,
+    reject_duplicate_index_mappings: bool,
+}
+
+impl DecodeOptions {
+    pub fn new() -> Self {
+        DecodeOptions::default()
+    }
+
+    /// Rejects store sections that declare more than `max_num_bins` bins.
+    pub fn max_num_bins(mut self, max_num_bins: u64) -> Self {
+        self.max_num_bins = Some(max_num_bins);
+        self
+    }
+
+    /// Rejects bins whose index is more than `max_index_span` away from another index of the
+    /// store they are decoded into, which bounds the size of dense stores.
+    pub fn max_index_span(mut self, max_index_span: u64) -> Self {
+        self.max_index_span = Some(max_index_span);
+        self
+    }
+
+    /// Rejects inputs longer than `max_len` bytes. Readers are not read past that length.
+    pub fn max_len(mut self, max_len: usize) -> Self {
+        self.max_len = Some(max_len);
+        self
+    }
+
+    /// Rejects counts, of bins or of zeros, that are NaN, infinite or negative.
+    pub fn reject_invalid_counts(mut self, reject_invalid_counts: bool) -> Self {
+        self.reject_invalid_counts = reject_invalid_counts;
+        self
+    }
+
+    /// Rejects inputs with more than one index mapping section, rather than keeping the last one.
+    pub fn reject_duplicate_index_mappings(
+        mut self,
+        reject_duplicate_index_mappings: bool,
+    ) -> Self {
+        self.reject_duplicate_index_mappings = reject_duplicate_index_mappings;
+        self
+    }
+
+    pub(crate) fn get_max_len(&self) -> Option<usize> {
+        self.max_len
+    }
+
+    pub(crate) fn rejects_duplicate_index_mappings(&self) -> bool {
+        self.reject_duplicate_index_mappings
+    }
+
+    pub(crate) fn check_num_bins(&self, num_bins: i64) -> Result<(), Error> {
+        match self.max_num_bins {
+            Some(max) if num_bins as u64 > max => Err(Error::LimitExceeded {
+                limit: "number of bins",
+                max,
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Checks that adding a bin at `index` to `store` keeps its indices within the maximum span.
+    pub(crate) fn check_index<S: Store + ?Sized>(
+        &self,
+        store: &S,
+        index: i32,
+    ) -> Result<(), Error> {
+        let Some(max) = self.max_index_span else {
+            return Ok(());
+        };
+        if store.is_empty() {
+            return Ok(());
+        }
+        let min_index = i32::min(store.get_min_index(), index) as i64;
+        let max_index = i32::max(store.get_max_index(), index) as i64;
+        if (max_index - min_index) as u64 > max {
+            return Err(Error::LimitExceeded {
+                limit: "index span",
+                max,
+            });
+        }
+        Ok(())
+    }
+
+    pub(crate) fn check_count(&self, count: f64) -> Result<(), Error> {
+        if self.reject_invalid_counts && !(count.is_finite() && count >= 0.0) {
+            return Err(Error::InvalidCount(count));
+        }
+        Ok(())
+    }
+}
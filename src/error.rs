@@ -1,9 +1,66 @@
+use crate::index_mapping::IndexMapping;
 use std::{fmt, io};
 
 #[derive(Debug)]
 pub enum Error {
     InvalidArgument(&'static str),
-    IoError(io::ErrorKind),
+    IoError(io::Error),
+    /// A relative accuracy outside of (0, 1).
+    InvalidRelativeAccuracy(f64),
+    /// A decoded bin index that does not fit in an `i32`.
+    IndexOverflow(i64),
+    /// Two sketches that need the same index mapping for the operation do not have it.
+    MappingMismatch {
+        expected: IndexMapping,
+        actual: IndexMapping,
+    },
+    /// A flag byte that is not valid in the section of the encoding it was read from, such as
+    /// "index mapping" or "bin encoding".
+    UnknownFlag {
+        marker: u8,
+        section: &'static str,
+    },
+    /// The input ended at `position`, in the middle of the sketch it encodes.
+    TruncatedInput {
+        position: usize,
+    },
+    /// A limit set by [`DecodeOptions`](crate::DecodeOptions) was exceeded.
+    LimitExceeded {
+        limit: &'static str,
+        max: u64,
+    },
+    /// A decoded count that is NaN, infinite or negative.
+    InvalidCount(f64),
+    /// A section of the encoding that may only appear once appeared again.
+    DuplicateSection {
+        section: &'static str,
+    },
+    /// The buffer given to encode a sketch into holds `available` bytes, but `needed` are needed.
+    BufferTooSmall {
+        needed: usize,
+        available: usize,
+    },
+    /// The quantile at `position` of the requested ones is NaN or outside of [0, 1].
+    InvalidQuantile {
+        quantile: f64,
+        position: usize,
+    },
+    /// The operation needs at least one value in the sketch.
+    EmptySketch,
+    /// Decoding the part of the input that starts at `position` failed with `source`.
+    Decode {
+        position: usize,
+        source: Box<Error>,
+    },
+}
+
+impl Error {
+    pub(crate) fn decoding_at(position: usize, source: Error) -> Error {
+        Error::Decode {
+            position,
+            source: Box::new(source),
+        }
+    }
 }
 
 impl fmt::Display for Error {
@@ -11,12 +68,66 @@ impl fmt::Display for Error {
         match *self {
             Error::InvalidArgument(arg) => write!(f, "Invalid argument: {}", arg),
             Error::IoError(ref cause) => write!(f, "Io Error: {}", cause),
+            Error::InvalidRelativeAccuracy(relative_accuracy) => write!(
+                f,
+                "Invalid relative accuracy: {}, it must be between 0 and 1",
+                relative_accuracy
+            ),
+            Error::IndexOverflow(index) => {
+                write!(f, "Index overflow: {} is not a valid i32", index)
+            }
+            Error::MappingMismatch {
+                ref expected,
+                ref actual,
+            } => write!(
+                f,
+                "Mismatched index mappings: expected {:?}, got {:?}",
+                expected, actual
+            ),
+            Error::UnknownFlag { marker, section } => {
+                write!(f, "Unknown {} flag: {:#04x}", section, marker)
+            }
+            Error::TruncatedInput { position } => {
+                write!(f, "Truncated input: ended at byte {}", position)
+            }
+            Error::LimitExceeded { limit, max } => {
+                write!(f, "Limit exceeded: the {} is greater than {}", limit, max)
+            }
+            Error::InvalidCount(count) => write!(f, "Invalid count: {}", count),
+            Error::DuplicateSection { section } => {
+                write!(f, "Duplicate section: {}", section)
+            }
+            Error::BufferTooSmall { needed, available } => write!(
+                f,
+                "Buffer too small: {} bytes are needed, {} are available",
+                needed, available
+            ),
+            Error::InvalidQuantile { quantile, position } => write!(
+                f,
+                "Invalid quantile: {} at position {}, it must be between 0 and 1",
+                quantile, position
+            ),
+            Error::EmptySketch => write!(f, "Empty sketch"),
+            Error::Decode {
+                position,
+                ref source,
+            } => write!(f, "Decode error at byte {}: {}", position, source),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match *self {
+            Error::IoError(ref cause) => Some(cause),
+            Error::Decode { ref source, .. } => Some(source.as_ref()),
+            _ => None,
         }
     }
 }
 
 impl From<io::Error> for Error {
     fn from(error: io::Error) -> Self {
-        Error::IoError(error.kind())
+        Error::IoError(error)
     }
 }
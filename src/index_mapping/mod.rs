@@ -1,13 +1,18 @@
 use crate::sketch::{Flag, FlagType};
 use crate::{serde, Error};
 use rust_strictmath::cbrt;
+use std::fmt;
 
 use crate::output::Output;
 
-#[derive(PartialEq, Debug)]
+#[allow(clippy::enum_variant_names)]
+#[derive(Clone, PartialEq)]
 pub enum IndexMapping {
     LogarithmicMapping(f64, f64, f64, f64),
+    LinearlyInterpolatedMapping(f64, f64, f64, f64),
+    QuadraticallyInterpolatedMapping(f64, f64, f64, f64),
     CubicallyInterpolatedMapping(f64, f64, f64, f64),
+    QuarticallyInterpolatedMapping(f64, f64, f64, f64),
 }
 
 const CUBICALLY_INTERPOLATED_MAPPING_A: f64 = 6.0 / 35.0;
@@ -18,6 +23,19 @@ const CUBICALLY_INTERPOLATED_MAPPING_CORRECTING_FACTOR: f64 =
 const CUBICALLY_INTERPOLATED_MAPPING_BASE: f64 = 2.0;
 const LOGARITHMIC_MAPPING_CORRECTING_FACTOR: f64 = 1.0;
 const LOGARITHMIC_MAPPING_BASE: f64 = std::f64::consts::E;
+const LINEARLY_INTERPOLATED_MAPPING_CORRECTING_FACTOR: f64 = 1.0 / std::f64::consts::LN_2;
+const LINEARLY_INTERPOLATED_MAPPING_BASE: f64 = 2.0;
+const QUADRATICALLY_INTERPOLATED_MAPPING_ONE_THIRD: f64 = 1.0 / 3.0;
+const QUADRATICALLY_INTERPOLATED_MAPPING_CORRECTING_FACTOR: f64 =
+    3.0 / (4.0 * std::f64::consts::LN_2);
+const QUADRATICALLY_INTERPOLATED_MAPPING_BASE: f64 = 2.0;
+const QUARTICALLY_INTERPOLATED_MAPPING_A: f64 = -2.0 / 25.0;
+const QUARTICALLY_INTERPOLATED_MAPPING_B: f64 = 8.0 / 25.0;
+const QUARTICALLY_INTERPOLATED_MAPPING_C: f64 = -17.0 / 25.0;
+const QUARTICALLY_INTERPOLATED_MAPPING_D: f64 = 36.0 / 25.0;
+const QUARTICALLY_INTERPOLATED_MAPPING_CORRECTING_FACTOR: f64 =
+    1.0 / (QUARTICALLY_INTERPOLATED_MAPPING_D * std::f64::consts::LN_2);
+const QUARTICALLY_INTERPOLATED_MAPPING_BASE: f64 = 2.0;
 
 impl IndexMapping {
     pub fn layout(&self) -> IndexMappingLayout {
@@ -28,12 +46,30 @@ impl IndexMapping {
                 _multiplier,
                 _relative_accuracy,
             ) => IndexMappingLayout::LOG,
+            IndexMapping::LinearlyInterpolatedMapping(
+                _gamma,
+                _index_offset,
+                _multiplier,
+                _relative_accuracy,
+            ) => IndexMappingLayout::LogLinear,
+            IndexMapping::QuadraticallyInterpolatedMapping(
+                _gamma,
+                _index_offset,
+                _multiplier,
+                _relative_accuracy,
+            ) => IndexMappingLayout::LogQuadratic,
             IndexMapping::CubicallyInterpolatedMapping(
                 _gamma,
                 _index_offset,
                 _multiplier,
                 _relative_accuracy,
             ) => IndexMappingLayout::LogCubic,
+            IndexMapping::QuarticallyInterpolatedMapping(
+                _gamma,
+                _index_offset,
+                _multiplier,
+                _relative_accuracy,
+            ) => IndexMappingLayout::LogQuartic,
         }
     }
 
@@ -45,12 +81,30 @@ impl IndexMapping {
                 _multiplier,
                 _relative_accuracy,
             ) => *gamma,
+            IndexMapping::LinearlyInterpolatedMapping(
+                gamma,
+                _index_offset,
+                _multiplier,
+                _relative_accuracy,
+            ) => *gamma,
+            IndexMapping::QuadraticallyInterpolatedMapping(
+                gamma,
+                _index_offset,
+                _multiplier,
+                _relative_accuracy,
+            ) => *gamma,
             IndexMapping::CubicallyInterpolatedMapping(
                 gamma,
                 _index_offset,
                 _multiplier,
                 _relative_accuracy,
             ) => *gamma,
+            IndexMapping::QuarticallyInterpolatedMapping(
+                gamma,
+                _index_offset,
+                _multiplier,
+                _relative_accuracy,
+            ) => *gamma,
         }
     }
 
@@ -62,12 +116,30 @@ impl IndexMapping {
                 _multiplier,
                 _relative_accuracy,
             ) => *index_offset,
+            IndexMapping::LinearlyInterpolatedMapping(
+                _gamma,
+                index_offset,
+                _multiplier,
+                _relative_accuracy,
+            ) => *index_offset,
+            IndexMapping::QuadraticallyInterpolatedMapping(
+                _gamma,
+                index_offset,
+                _multiplier,
+                _relative_accuracy,
+            ) => *index_offset,
             IndexMapping::CubicallyInterpolatedMapping(
                 _gamma,
                 index_offset,
                 _multiplier,
                 _relative_accuracy,
             ) => *index_offset,
+            IndexMapping::QuarticallyInterpolatedMapping(
+                _gamma,
+                index_offset,
+                _multiplier,
+                _relative_accuracy,
+            ) => *index_offset,
         }
     }
 
@@ -79,12 +151,30 @@ impl IndexMapping {
                 multiplier,
                 _relative_accuracy,
             ) => *multiplier,
+            IndexMapping::LinearlyInterpolatedMapping(
+                _gamma,
+                _index_offset,
+                multiplier,
+                _relative_accuracy,
+            ) => *multiplier,
+            IndexMapping::QuadraticallyInterpolatedMapping(
+                _gamma,
+                _index_offset,
+                multiplier,
+                _relative_accuracy,
+            ) => *multiplier,
             IndexMapping::CubicallyInterpolatedMapping(
                 _gamma,
                 _index_offset,
                 multiplier,
                 _relative_accuracy,
             ) => *multiplier,
+            IndexMapping::QuarticallyInterpolatedMapping(
+                _gamma,
+                _index_offset,
+                multiplier,
+                _relative_accuracy,
+            ) => *multiplier,
         }
     }
 
@@ -96,12 +186,30 @@ impl IndexMapping {
                 _multiplier,
                 relative_accuracy,
             ) => *relative_accuracy,
+            IndexMapping::LinearlyInterpolatedMapping(
+                _gamma,
+                _index_offset,
+                _multiplier,
+                relative_accuracy,
+            ) => *relative_accuracy,
+            IndexMapping::QuadraticallyInterpolatedMapping(
+                _gamma,
+                _index_offset,
+                _multiplier,
+                relative_accuracy,
+            ) => *relative_accuracy,
             IndexMapping::CubicallyInterpolatedMapping(
                 _gamma,
                 _index_offset,
                 _multiplier,
                 relative_accuracy,
             ) => *relative_accuracy,
+            IndexMapping::QuarticallyInterpolatedMapping(
+                _gamma,
+                _index_offset,
+                _multiplier,
+                relative_accuracy,
+            ) => *relative_accuracy,
         }
     }
 
@@ -113,6 +221,27 @@ impl IndexMapping {
                 _multiplier,
                 _relative_accuracy,
             ) => value.ln(),
+            IndexMapping::LinearlyInterpolatedMapping(
+                _gamma,
+                _index_offset,
+                _multiplier,
+                _relative_accuracy,
+            ) => {
+                let long_bits = value.to_bits() as i64;
+                serde::get_exponent(long_bits) as f64 + serde::get_significand_plus_one(long_bits)
+                    - 1.0
+            }
+            IndexMapping::QuadraticallyInterpolatedMapping(
+                _gamma,
+                _index_offset,
+                _multiplier,
+                _relative_accuracy,
+            ) => {
+                let long_bits = value.to_bits() as i64;
+                let s: f64 = serde::get_significand_plus_one(long_bits) - 1.0;
+                let e: f64 = serde::get_exponent(long_bits) as f64;
+                e - (s - 4.0) * s * QUADRATICALLY_INTERPOLATED_MAPPING_ONE_THIRD
+            }
             IndexMapping::CubicallyInterpolatedMapping(
                 _gamma,
                 _index_offset,
@@ -127,6 +256,23 @@ impl IndexMapping {
                     * s
                     + e
             }
+            IndexMapping::QuarticallyInterpolatedMapping(
+                _gamma,
+                _index_offset,
+                _multiplier,
+                _relative_accuracy,
+            ) => {
+                let long_bits = value.to_bits() as i64;
+                let s: f64 = serde::get_significand_plus_one(long_bits) - 1.0;
+                let e: f64 = serde::get_exponent(long_bits) as f64;
+                (((QUARTICALLY_INTERPOLATED_MAPPING_A * s + QUARTICALLY_INTERPOLATED_MAPPING_B)
+                    * s
+                    + QUARTICALLY_INTERPOLATED_MAPPING_C)
+                    * s
+                    + QUARTICALLY_INTERPOLATED_MAPPING_D)
+                    * s
+                    + e
+            }
         }
     }
 
@@ -138,6 +284,27 @@ impl IndexMapping {
                 _multiplier,
                 _relative_accuracy,
             ) => index.exp(),
+            IndexMapping::LinearlyInterpolatedMapping(
+                _gamma,
+                _index_offset,
+                _multiplier,
+                _relative_accuracy,
+            ) => {
+                let exponent = index.floor() as i64;
+                let significand_plus_one: f64 = index - exponent as f64 + 1.0;
+                serde::build_double(exponent, significand_plus_one)
+            }
+            IndexMapping::QuadraticallyInterpolatedMapping(
+                _gamma,
+                _index_offset,
+                _multiplier,
+                _relative_accuracy,
+            ) => {
+                let exponent = index.floor() as i64;
+                let significand_plus_one: f64 =
+                    3.0 - (4.0 - 3.0 * (index - exponent as f64)).sqrt();
+                serde::build_double(exponent, significand_plus_one)
+            }
             IndexMapping::CubicallyInterpolatedMapping(
                 _gamma,
                 _index_offset,
@@ -166,9 +333,57 @@ impl IndexMapping {
                     + 1.0;
                 serde::build_double(exponent, significand_plus_one)
             }
+            IndexMapping::QuarticallyInterpolatedMapping(
+                _gamma,
+                _index_offset,
+                _multiplier,
+                _relative_accuracy,
+            ) => {
+                let exponent = index.floor() as i64;
+                // Derived from Ferrari's method
+                let a = QUARTICALLY_INTERPOLATED_MAPPING_B / QUARTICALLY_INTERPOLATED_MAPPING_A;
+                let b = QUARTICALLY_INTERPOLATED_MAPPING_C / QUARTICALLY_INTERPOLATED_MAPPING_A;
+                let c = QUARTICALLY_INTERPOLATED_MAPPING_D / QUARTICALLY_INTERPOLATED_MAPPING_A;
+                let d = -(index - exponent as f64) / QUARTICALLY_INTERPOLATED_MAPPING_A;
+                let alpha: f64 = -3.0 * a * a / 8.0 + b;
+                let beta: f64 = a * a * a / 8.0 - a * b / 2.0 + c;
+                let gamma: f64 = -3.0 * a * a * a * a / 256.0 + a * a * b / 16.0 - a * c / 4.0 + d;
+                let p: f64 = -alpha * alpha / 12.0 - gamma;
+                let q: f64 =
+                    -alpha * alpha * alpha / 108.0 + alpha * gamma / 3.0 - beta * beta / 8.0;
+                let r: f64 = -q / 2.0 + (q * q / 4.0 + p * p * p / 27.0).sqrt();
+                let u: f64 = cbrt(r);
+                let y: f64 = -5.0 * alpha / 6.0 + u - p / (3.0 * u);
+                let w: f64 = (alpha + 2.0 * y).sqrt();
+                let significand_plus_one: f64 =
+                    -a / 4.0 + (w - (-(3.0 * alpha + 2.0 * y + 2.0 * beta / w)).sqrt()) / 2.0 + 1.0;
+                serde::build_double(exponent, significand_plus_one)
+            }
         }
     }
 
+    /// Returns the number of bins by which multiplying a value by `multiplier` shifts its index,
+    /// if that number is the same for all the values. The interpolated mappings only
+    /// approximate the logarithm within powers of two, so they can shift only by those.
+    pub(crate) fn index_shift(&self, multiplier: f64) -> Option<i32> {
+        let log_multiplier = match self {
+            IndexMapping::LogarithmicMapping(..) => multiplier.ln(),
+            _ => {
+                let exponent = multiplier.log2();
+                if exponent.fract() != 0.0 {
+                    return None;
+                }
+                exponent
+            }
+        };
+        let shift = log_multiplier * self.multiplier();
+        let rounded_shift = shift.round();
+        if (shift - rounded_shift).abs() > 1e-9 || rounded_shift.abs() > i32::MAX as f64 {
+            return None;
+        }
+        Some(rounded_shift as i32)
+    }
+
     pub fn index(&self, value: f64) -> i32 {
         let index: f64 = self.log(value) * self.multiplier() + self.index_offset();
         if index >= 0.0 {
@@ -182,12 +397,11 @@ impl IndexMapping {
         self.lower_bound(index) * (1.0 + self.relative_accuracy())
     }
 
-    fn lower_bound(&self, index: i32) -> f64 {
+    pub(crate) fn lower_bound(&self, index: i32) -> f64 {
         self.log_inverse((index as f64 - self.index_offset()) / self.multiplier())
     }
 
-    #[allow(dead_code)]
-    fn upper_bound(&self, index: i32) -> f64 {
+    pub(crate) fn upper_bound(&self, index: i32) -> f64 {
         self.lower_bound(index + 1)
     }
 
@@ -211,29 +425,28 @@ impl IndexMapping {
         )
     }
 
-    pub fn encode(&self, output: &mut Output) -> Result<(), Error> {
+    pub(crate) fn encode(&self, output: &mut Output) -> Result<(), Error> {
         self.layout().to_flag().encode(output)?;
         output.write_double_le(self.gamma())?;
         output.write_double_le(self.index_offset())?;
         Ok(())
     }
 
+    /// The flag, then gamma and the index offset as little-endian doubles.
+    pub(crate) const ENCODED_LEN: usize = 1 + 2 * 8;
+
     pub fn with_relative_accuracy(
         index_layout: IndexMappingLayout,
         relative_accuracy: f64,
     ) -> Result<IndexMapping, Error> {
         if relative_accuracy <= 0.0 || relative_accuracy >= 1.0 {
-            return Err(Error::InvalidArgument(
-                "The relative accuracy must be between 0 and 1.",
-            ));
+            return Err(Error::InvalidRelativeAccuracy(relative_accuracy));
         }
 
         match index_layout {
             IndexMappingLayout::LOG => {
                 if relative_accuracy <= 0.0 || relative_accuracy >= 1.0 {
-                    return Err(Error::InvalidArgument(
-                        "The relative accuracy must be between 0 and 1.",
-                    ));
+                    return Err(Error::InvalidRelativeAccuracy(relative_accuracy));
                 }
 
                 let gamma =
@@ -268,7 +481,67 @@ impl IndexMapping {
                     relative_accuracy,
                 ))
             }
-            _ => Err(Error::InvalidArgument("Unsupported IndexLayout")),
+
+            IndexMappingLayout::LogLinear => {
+                let gamma = calculate_gamma(
+                    relative_accuracy,
+                    LINEARLY_INTERPOLATED_MAPPING_CORRECTING_FACTOR,
+                );
+                let index_offset: f64 = 0.0;
+
+                let multiplier = LINEARLY_INTERPOLATED_MAPPING_BASE.ln() / (gamma - 1.0).ln_1p();
+                let relative_accuracy = calculate_relative_accuracy(
+                    gamma,
+                    LINEARLY_INTERPOLATED_MAPPING_CORRECTING_FACTOR,
+                );
+                Ok(IndexMapping::LinearlyInterpolatedMapping(
+                    gamma,
+                    index_offset,
+                    multiplier,
+                    relative_accuracy,
+                ))
+            }
+
+            IndexMappingLayout::LogQuadratic => {
+                let gamma = calculate_gamma(
+                    relative_accuracy,
+                    QUADRATICALLY_INTERPOLATED_MAPPING_CORRECTING_FACTOR,
+                );
+                let index_offset: f64 = 0.0;
+
+                let multiplier =
+                    QUADRATICALLY_INTERPOLATED_MAPPING_BASE.ln() / (gamma - 1.0).ln_1p();
+                let relative_accuracy = calculate_relative_accuracy(
+                    gamma,
+                    QUADRATICALLY_INTERPOLATED_MAPPING_CORRECTING_FACTOR,
+                );
+                Ok(IndexMapping::QuadraticallyInterpolatedMapping(
+                    gamma,
+                    index_offset,
+                    multiplier,
+                    relative_accuracy,
+                ))
+            }
+
+            IndexMappingLayout::LogQuartic => {
+                let gamma = calculate_gamma(
+                    relative_accuracy,
+                    QUARTICALLY_INTERPOLATED_MAPPING_CORRECTING_FACTOR,
+                );
+                let index_offset: f64 = 0.0;
+
+                let multiplier = QUARTICALLY_INTERPOLATED_MAPPING_BASE.ln() / (gamma - 1.0).ln_1p();
+                let relative_accuracy = calculate_relative_accuracy(
+                    gamma,
+                    QUARTICALLY_INTERPOLATED_MAPPING_CORRECTING_FACTOR,
+                );
+                Ok(IndexMapping::QuarticallyInterpolatedMapping(
+                    gamma,
+                    index_offset,
+                    multiplier,
+                    relative_accuracy,
+                ))
+            }
         }
     }
 
@@ -277,6 +550,12 @@ impl IndexMapping {
         gamma: f64,
         index_offset: f64,
     ) -> Result<IndexMapping, Error> {
+        if gamma <= 1.0 || !gamma.is_finite() {
+            return Err(Error::InvalidArgument("Gamma must be greater than 1."));
+        }
+        if !index_offset.is_finite() {
+            return Err(Error::InvalidArgument("The index offset must be finite."));
+        }
         match index_layout {
             IndexMappingLayout::LOG => {
                 let multiplier = LOGARITHMIC_MAPPING_BASE.ln() / gamma.ln();
@@ -304,21 +583,88 @@ impl IndexMapping {
                 ))
             }
 
-            _ => Err(Error::InvalidArgument("Unsupported IndexLayout")),
+            IndexMappingLayout::LogLinear => {
+                let multiplier = LINEARLY_INTERPOLATED_MAPPING_BASE.ln() / gamma.ln();
+                let relative_accuracy = calculate_relative_accuracy(
+                    gamma,
+                    LINEARLY_INTERPOLATED_MAPPING_CORRECTING_FACTOR,
+                );
+                Ok(IndexMapping::LinearlyInterpolatedMapping(
+                    gamma,
+                    index_offset,
+                    multiplier,
+                    relative_accuracy,
+                ))
+            }
+
+            IndexMappingLayout::LogQuadratic => {
+                let multiplier = QUADRATICALLY_INTERPOLATED_MAPPING_BASE.ln() / gamma.ln();
+                let relative_accuracy = calculate_relative_accuracy(
+                    gamma,
+                    QUADRATICALLY_INTERPOLATED_MAPPING_CORRECTING_FACTOR,
+                );
+                Ok(IndexMapping::QuadraticallyInterpolatedMapping(
+                    gamma,
+                    index_offset,
+                    multiplier,
+                    relative_accuracy,
+                ))
+            }
+
+            IndexMappingLayout::LogQuartic => {
+                let multiplier = QUARTICALLY_INTERPOLATED_MAPPING_BASE.ln() / gamma.ln();
+                let relative_accuracy = calculate_relative_accuracy(
+                    gamma,
+                    QUARTICALLY_INTERPOLATED_MAPPING_CORRECTING_FACTOR,
+                );
+                Ok(IndexMapping::QuarticallyInterpolatedMapping(
+                    gamma,
+                    index_offset,
+                    multiplier,
+                    relative_accuracy,
+                ))
+            }
         }
     }
 }
 
+impl fmt::Debug for IndexMapping {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            IndexMapping::LogarithmicMapping(..) => "LogarithmicMapping",
+            IndexMapping::LinearlyInterpolatedMapping(..) => "LinearlyInterpolatedMapping",
+            IndexMapping::QuadraticallyInterpolatedMapping(..) => {
+                "QuadraticallyInterpolatedMapping"
+            }
+            IndexMapping::CubicallyInterpolatedMapping(..) => "CubicallyInterpolatedMapping",
+            IndexMapping::QuarticallyInterpolatedMapping(..) => "QuarticallyInterpolatedMapping",
+        };
+        f.debug_struct(name)
+            .field("gamma", &self.gamma())
+            .field("index_offset", &self.index_offset())
+            .field("relative_accuracy", &self.relative_accuracy())
+            .finish()
+    }
+}
+
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(::serde::Serialize, ::serde::Deserialize))]
 pub enum IndexMappingLayout {
+    #[cfg_attr(feature = "serde", serde(rename = "LOG"))]
     LOG = 0,
+    #[cfg_attr(feature = "serde", serde(rename = "LOG_LINEAR"))]
     LogLinear = 1,
+    #[cfg_attr(feature = "serde", serde(rename = "LOG_QUADRATIC"))]
     LogQuadratic = 2,
+    #[cfg_attr(feature = "serde", serde(rename = "LOG_CUBIC"))]
     LogCubic = 3,
+    #[cfg_attr(feature = "serde", serde(rename = "LOG_QUARTIC"))]
     LogQuartic = 4,
 }
 
 impl IndexMappingLayout {
-    pub fn of_flag(flag: &Flag) -> Result<IndexMappingLayout, Error> {
+    pub(crate) fn of_flag(flag: &Flag) -> Result<IndexMappingLayout, Error> {
         let index = flag.get_marker() >> 2;
         match index {
             0 => Ok(IndexMappingLayout::LOG),
@@ -326,16 +672,79 @@ impl IndexMappingLayout {
             2 => Ok(IndexMappingLayout::LogQuadratic),
             3 => Ok(IndexMappingLayout::LogCubic),
             4 => Ok(IndexMappingLayout::LogQuartic),
-            _ => Err(Error::InvalidArgument("Unknown Index Flag.")),
+            _ => Err(Error::UnknownFlag {
+                marker: flag.get_marker(),
+                section: "index mapping",
+            }),
         }
     }
 
-    pub fn to_flag(self) -> Flag {
+    pub(crate) fn to_flag(self) -> Flag {
         let sub_flag = self as u8;
         Flag::with_type(FlagType::IndexMapping, sub_flag)
     }
 }
 
+#[cfg(feature = "serde")]
+#[derive(::serde::Serialize, ::serde::Deserialize)]
+#[serde(rename = "IndexMapping")]
+struct IndexMappingParameters {
+    layout: IndexMappingLayout,
+    gamma: f64,
+    index_offset: f64,
+}
+
+#[cfg(feature = "serde")]
+impl ::serde::Serialize for IndexMapping {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: ::serde::Serializer,
+    {
+        if serializer.is_human_readable() {
+            let parameters = IndexMappingParameters {
+                layout: self.layout(),
+                gamma: self.gamma(),
+                index_offset: self.index_offset(),
+            };
+            ::serde::Serialize::serialize(&parameters, serializer)
+        } else {
+            let mut output = Output::with_capacity(17);
+            self.encode(&mut output)
+                .map_err(::serde::ser::Error::custom)?;
+            serializer.serialize_bytes(&output.trim())
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> ::serde::Deserialize<'de> for IndexMapping {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: ::serde::Deserializer<'de>,
+    {
+        use ::serde::de::Error as _;
+        if deserializer.is_human_readable() {
+            let parameters: IndexMappingParameters =
+                ::serde::Deserialize::deserialize(deserializer)?;
+            IndexMapping::with_gamma_offset(
+                parameters.layout,
+                parameters.gamma,
+                parameters.index_offset,
+            )
+            .map_err(D::Error::custom)
+        } else {
+            let bytes: crate::serialization::ByteBuf =
+                ::serde::Deserialize::deserialize(deserializer)?;
+            let mut input = crate::input::Input::wrap(&bytes.0);
+            let flag = Flag::decode(&mut input).map_err(D::Error::custom)?;
+            let layout = IndexMappingLayout::of_flag(&flag).map_err(D::Error::custom)?;
+            let gamma = input.read_double_le().map_err(D::Error::custom)?;
+            let index_offset = input.read_double_le().map_err(D::Error::custom)?;
+            IndexMapping::with_gamma_offset(layout, gamma, index_offset).map_err(D::Error::custom)
+        }
+    }
+}
+
 fn calculate_relative_accuracy(gamma: f64, correcting_factor: f64) -> f64 {
     let exact_log_gamma = gamma.powf(correcting_factor);
     (exact_log_gamma - 1.0) / (exact_log_gamma + 1.0)
@@ -348,8 +757,10 @@ fn calculate_gamma(relative_accuracy: f64, correcting_factor: f64) -> f64 {
 
 #[cfg(test)]
 mod tests {
-    use crate::index_mapping::IndexMapping;
-    use crate::index_mapping::IndexMappingLayout::{LogCubic, LOG};
+    use crate::index_mapping::IndexMappingLayout::{
+        LogCubic, LogLinear, LogQuadratic, LogQuartic, LOG,
+    };
+    use crate::index_mapping::{IndexMapping, IndexMappingLayout};
 
     const TEST_GAMMAS: [f64; 3] = [1.0 + 1e-6, 1.02, 1.5];
     const TEST_INDEX_OFFSETS: [f64; 4] = [0.0, 1.0, -12.23, 7768.3];
@@ -412,6 +823,16 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_with_gamma_offset_invalid() {
+        for layout in [LOG, LogCubic] {
+            assert!(IndexMapping::with_gamma_offset(layout, 1.0, 0.0).is_err());
+            assert!(IndexMapping::with_gamma_offset(layout, f64::NAN, 0.0).is_err());
+            assert!(IndexMapping::with_gamma_offset(layout, 1.02, f64::NAN).is_err());
+            assert!(IndexMapping::with_gamma_offset(layout, 1.02, f64::INFINITY).is_err());
+        }
+    }
+
     #[test]
     fn test_cubically_interpolated_mapping_validity_manual_check() {
         let d0: f64 = -0.37469387755102035;
@@ -558,4 +979,191 @@ mod tests {
             assert_eq!(indexes[i], mapping.index(values[i]));
         }
     }
+
+    fn assert_mapping_validity(mapping: &IndexMapping) {
+        let min_index = -50;
+        let max_index = 50;
+
+        let mut index = min_index;
+        let mut bound = mapping.upper_bound(index - 1);
+
+        while index <= max_index {
+            assert!(f64::abs(mapping.lower_bound(index) - bound) <= 1e10);
+            assert!(mapping.value(index) >= mapping.lower_bound(index));
+            assert!(mapping.upper_bound(index) >= mapping.value(index));
+
+            assert!(mapping.index(mapping.lower_bound(index) - EPSILON) < index);
+            assert!(mapping.index(mapping.lower_bound(index) + EPSILON) >= index);
+
+            assert!(mapping.index(mapping.upper_bound(index) - EPSILON) <= index);
+            assert!(mapping.index(mapping.upper_bound(index) + EPSILON) > index);
+
+            bound = mapping.upper_bound(index);
+            index += 1;
+        }
+    }
+
+    fn assert_relative_accuracy(mapping: &IndexMapping, relative_accuracy: f64) {
+        assert!(mapping.relative_accuracy() <= relative_accuracy + 1e-12);
+        let mut value = 1e-6;
+        while value < 1e6 {
+            let approximated = mapping.value(mapping.index(value));
+            assert!(f64::abs(approximated - value) / value <= relative_accuracy + 1e-12);
+            value *= 1.0 + relative_accuracy / 7.0;
+        }
+    }
+
+    #[test]
+    fn test_interpolated_mapping_validity() {
+        for layout in [LogLinear, LogQuadratic, LogQuartic] {
+            for relative_accuracy in [1e-1, 1e-2, 1e-3] {
+                let mapping =
+                    IndexMapping::with_relative_accuracy(layout, relative_accuracy).unwrap();
+                assert_mapping_validity(&mapping);
+                assert_relative_accuracy(&mapping, relative_accuracy);
+            }
+        }
+    }
+
+    #[test]
+    fn test_interpolated_mapping_offset() {
+        for layout in [LogLinear, LogQuadratic, LogQuartic] {
+            for gamma in TEST_GAMMAS {
+                for index_offset in TEST_INDEX_OFFSETS {
+                    let index_mapping =
+                        IndexMapping::with_gamma_offset(layout, gamma, index_offset).unwrap();
+                    let index_of1 = index_mapping.index(1.0) as f64;
+                    assert!(index_offset.ceil() - 1.0 <= index_of1);
+                    assert!(index_of1 <= index_offset.floor());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_interpolated_mapping_layout() {
+        for layout in [LOG, LogLinear, LogQuadratic, LogCubic, LogQuartic] {
+            let marker = layout.to_flag().get_marker();
+            let mapping = IndexMapping::with_relative_accuracy(layout, 2e-2).unwrap();
+            assert_eq!(marker, mapping.layout().to_flag().get_marker());
+            let decoded = IndexMapping::with_gamma_offset(
+                IndexMappingLayout::of_flag(&mapping.layout().to_flag()).unwrap(),
+                mapping.gamma(),
+                mapping.index_offset(),
+            )
+            .unwrap();
+            assert_eq!(mapping, decoded);
+        }
+    }
+
+    #[test]
+    fn test_linearly_interpolated_index() {
+        let mapping = IndexMapping::with_relative_accuracy(LogLinear, 2e-2).unwrap();
+        let values: Vec<f64> = vec![
+            1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0, 8.0, 9.0, 10.0, 11.0, 12.0, 13.0, 14.0, 15.0, 16.0,
+            17.0, 18.0, 19.0, 52.0, 227.0, 232.0, 402.0, 462.0, 595.0, 869.0, 910.0, 937.0, 1217.0,
+            1248.0, 1347.0, 1354.0, 1392.0, 1539.0, 1548.0, 1568.0, 1683.0, 1711.0, 1799.0, 1901.0,
+            1926.0, 1945.0, 1948.0, 2003.0, 2069.0, 2071.0, 2262.0, 2497.0, 2526.0, 2587.0, 2696.0,
+            2708.0, 2717.0, 2795.0, 2843.0, 2843.0, 3138.0, 3188.0, 3293.0, 3326.0, 3400.0, 3438.0,
+            3619.0, 3690.0, 3853.0, 3912.0, 3995.0, 4172.0, 4227.0, 4235.0, 4277.0, 4403.0, 4505.0,
+            4597.0, 4664.0, 4828.0, 4947.0, 5018.0, 5186.0, 5212.0, 5248.0, 5384.0, 5406.0, 5472.0,
+            5484.0, 5517.0, 5522.0, 5668.0, 6607.0, 6654.0, 6668.0, 6684.0, 6762.0, 6769.0, 7022.0,
+            7041.0, 7112.0, 7170.0, 7350.0, 7382.0, 7478.0, 7514.0, 7792.0, 8082.0, 8504.0, 8531.0,
+            8709.0, 9124.0, 9150.0, 9407.0, 9411.0, 9421.0, 9423.0, 9528.0, 9533.0, 9846.0, 9976.0,
+        ];
+        let indexes = vec![
+            0, 24, 37, 49, 56, 62, 68, 74, 78, 81, 84, 87, 90, 93, 96, 99, 101, 103, 104, 140, 194,
+            195, 214, 220, 229, 242, 244, 245, 254, 255, 257, 258, 258, 262, 262, 263, 266, 266,
+            268, 271, 271, 272, 272, 273, 275, 275, 277, 280, 280, 281, 282, 283, 283, 284, 284,
+            284, 288, 288, 290, 290, 291, 291, 294, 295, 296, 297, 298, 300, 300, 300, 301, 301,
+            302, 303, 303, 304, 305, 305, 306, 306, 306, 307, 307, 308, 308, 308, 308, 309, 315,
+            315, 315, 315, 316, 316, 317, 317, 318, 318, 319, 320, 320, 320, 322, 324, 325, 325,
+            326, 327, 327, 328, 328, 328, 328, 329, 329, 330, 330,
+        ];
+        for i in 0..values.len() {
+            assert_eq!(indexes[i], mapping.index(values[i]));
+        }
+    }
+
+    #[test]
+    fn test_quadratically_interpolated_index() {
+        let mapping = IndexMapping::with_relative_accuracy(LogQuadratic, 2e-2).unwrap();
+        let values: Vec<f64> = vec![
+            1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0, 8.0, 9.0, 10.0, 11.0, 12.0, 13.0, 14.0, 15.0, 16.0,
+            17.0, 18.0, 19.0, 52.0, 227.0, 232.0, 402.0, 462.0, 595.0, 869.0, 910.0, 937.0, 1217.0,
+            1248.0, 1347.0, 1354.0, 1392.0, 1539.0, 1548.0, 1568.0, 1683.0, 1711.0, 1799.0, 1901.0,
+            1926.0, 1945.0, 1948.0, 2003.0, 2069.0, 2071.0, 2262.0, 2497.0, 2526.0, 2587.0, 2696.0,
+            2708.0, 2717.0, 2795.0, 2843.0, 2843.0, 3138.0, 3188.0, 3293.0, 3326.0, 3400.0, 3438.0,
+            3619.0, 3690.0, 3853.0, 3912.0, 3995.0, 4172.0, 4227.0, 4235.0, 4277.0, 4403.0, 4505.0,
+            4597.0, 4664.0, 4828.0, 4947.0, 5018.0, 5186.0, 5212.0, 5248.0, 5384.0, 5406.0, 5472.0,
+            5484.0, 5517.0, 5522.0, 5668.0, 6607.0, 6654.0, 6668.0, 6684.0, 6762.0, 6769.0, 7022.0,
+            7041.0, 7112.0, 7170.0, 7350.0, 7382.0, 7478.0, 7514.0, 7792.0, 8082.0, 8504.0, 8531.0,
+            8709.0, 9124.0, 9150.0, 9407.0, 9411.0, 9421.0, 9423.0, 9528.0, 9533.0, 9846.0, 9976.0,
+        ];
+        let indexes = vec![
+            0, 18, 29, 37, 43, 48, 52, 56, 59, 62, 64, 67, 69, 71, 73, 74, 76, 78, 79, 106, 146,
+            147, 162, 166, 172, 183, 184, 185, 191, 192, 194, 194, 195, 198, 198, 198, 200, 201,
+            202, 204, 204, 204, 204, 205, 206, 206, 208, 211, 211, 212, 213, 213, 213, 214, 214,
+            214, 217, 218, 219, 219, 220, 220, 221, 222, 223, 223, 224, 225, 225, 225, 226, 226,
+            227, 227, 228, 229, 229, 230, 231, 231, 231, 232, 232, 232, 232, 232, 232, 233, 237,
+            238, 238, 238, 238, 238, 239, 239, 239, 240, 240, 241, 241, 241, 242, 243, 244, 244,
+            245, 246, 246, 247, 247, 247, 247, 247, 247, 248, 248,
+        ];
+        for i in 0..values.len() {
+            assert_eq!(indexes[i], mapping.index(values[i]));
+        }
+    }
+
+    #[test]
+    fn test_quartically_interpolated_index() {
+        let mapping = IndexMapping::with_relative_accuracy(LogQuartic, 2e-2).unwrap();
+        let values: Vec<f64> = vec![
+            1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0, 8.0, 9.0, 10.0, 11.0, 12.0, 13.0, 14.0, 15.0, 16.0,
+            17.0, 18.0, 19.0, 52.0, 227.0, 232.0, 402.0, 462.0, 595.0, 869.0, 910.0, 937.0, 1217.0,
+            1248.0, 1347.0, 1354.0, 1392.0, 1539.0, 1548.0, 1568.0, 1683.0, 1711.0, 1799.0, 1901.0,
+            1926.0, 1945.0, 1948.0, 2003.0, 2069.0, 2071.0, 2262.0, 2497.0, 2526.0, 2587.0, 2696.0,
+            2708.0, 2717.0, 2795.0, 2843.0, 2843.0, 3138.0, 3188.0, 3293.0, 3326.0, 3400.0, 3438.0,
+            3619.0, 3690.0, 3853.0, 3912.0, 3995.0, 4172.0, 4227.0, 4235.0, 4277.0, 4403.0, 4505.0,
+            4597.0, 4664.0, 4828.0, 4947.0, 5018.0, 5186.0, 5212.0, 5248.0, 5384.0, 5406.0, 5472.0,
+            5484.0, 5517.0, 5522.0, 5668.0, 6607.0, 6654.0, 6668.0, 6684.0, 6762.0, 6769.0, 7022.0,
+            7041.0, 7112.0, 7170.0, 7350.0, 7382.0, 7478.0, 7514.0, 7792.0, 8082.0, 8504.0, 8531.0,
+            8709.0, 9124.0, 9150.0, 9407.0, 9411.0, 9421.0, 9423.0, 9528.0, 9533.0, 9846.0, 9976.0,
+        ];
+        let indexes = vec![
+            0, 17, 27, 34, 40, 44, 48, 52, 55, 57, 60, 62, 64, 66, 67, 69, 70, 72, 73, 98, 135,
+            136, 150, 153, 159, 169, 170, 171, 177, 178, 180, 180, 181, 183, 183, 184, 186, 186,
+            187, 189, 189, 189, 189, 190, 191, 191, 193, 195, 196, 196, 197, 197, 198, 198, 199,
+            199, 201, 202, 202, 203, 203, 203, 205, 205, 206, 207, 207, 208, 209, 209, 209, 210,
+            210, 211, 211, 212, 213, 213, 214, 214, 214, 215, 215, 215, 215, 215, 215, 216, 220,
+            220, 220, 220, 220, 220, 221, 221, 222, 222, 222, 223, 223, 223, 224, 225, 226, 226,
+            227, 228, 228, 229, 229, 229, 229, 229, 229, 230, 230,
+        ];
+        for i in 0..values.len() {
+            assert_eq!(indexes[i], mapping.index(values[i]));
+        }
+    }
+
+    #[test]
+    fn test_index_shift() {
+        let gamma: f64 = 1.02;
+        let logarithmic = IndexMapping::with_gamma_offset(LOG, gamma, 0.5).unwrap();
+        assert_eq!(Some(3), logarithmic.index_shift(gamma.powi(3)));
+        assert_eq!(Some(-7), logarithmic.index_shift(gamma.powi(-7)));
+        assert_eq!(Some(0), logarithmic.index_shift(1.0));
+        assert_eq!(None, logarithmic.index_shift(1.5));
+
+        let two_to_one_eighth = 2f64.powf(0.125);
+        for layout in [LogLinear, LogQuadratic, LogCubic, LogQuartic] {
+            let mapping = IndexMapping::with_gamma_offset(layout, two_to_one_eighth, 3.0).unwrap();
+            assert_eq!(Some(16), mapping.index_shift(4.0));
+            assert_eq!(Some(-8), mapping.index_shift(0.5));
+            assert_eq!(None, mapping.index_shift(3.0));
+            for value in [1.3, 7.7, 1e5 + 0.5] {
+                assert_eq!(mapping.index(value) + 16, mapping.index(value * 4.0));
+            }
+        }
+
+        let cubic = IndexMapping::with_gamma_offset(LogCubic, 1.02, 0.0).unwrap();
+        assert_eq!(None, cubic.index_shift(2.0));
+    }
 }
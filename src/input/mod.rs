@@ -1,55 +1,188 @@
 use crate::error::Error;
-use std::io::ErrorKind;
+use std::io::{ErrorKind, Read};
 
+const READER_BUFFER_SIZE: usize = 8 * 1024;
+
+/// The bytes of an encoded sketch, either borrowed in full or pulled from a reader as decoding
+/// goes.
 pub struct Input<'a> {
-    vec: &'a Vec<u8>,
-    pos: usize,
-    end: usize,
+    source: Source<'a>,
+    // The position of the first byte of `bytes`, or of `buffer`, in the whole input.
+    offset: usize,
+    max_len: usize,
+}
+
+enum Source<'a> {
+    Bytes {
+        bytes: &'a [u8],
+        pos: usize,
+    },
+    Reader {
+        reader: &'a mut dyn Read,
+        buffer: Vec<u8>,
+        pos: usize,
+        len: usize,
+    },
 }
 
 impl<'a> Input<'a> {
-    pub fn wrap(vec: &'a Vec<u8>) -> Input {
+    pub fn wrap(bytes: &'a [u8]) -> Input<'a> {
+        Input {
+            source: Source::Bytes { bytes, pos: 0 },
+            offset: 0,
+            max_len: usize::MAX,
+        }
+    }
+
+    /// Reads from `reader` through an internal buffer, so `reader` does not need to be buffered.
+    pub fn from_reader(reader: &'a mut dyn Read) -> Input<'a> {
         Input {
-            pos: 0,
-            end: vec.len(),
-            vec,
+            source: Source::Reader {
+                reader,
+                buffer: Vec::new(),
+                pos: 0,
+                len: 0,
+            },
+            offset: 0,
+            max_len: usize::MAX,
         }
     }
 
-    pub(crate) fn has_remaining(&self) -> bool {
-        self.pos < self.end
+    /// Fails, now or once read, if the input is longer than `max_len` bytes.
+    pub(crate) fn limit_len(&mut self, max_len: usize) -> Result<(), Error> {
+        self.max_len = max_len;
+        match &self.source {
+            Source::Bytes { bytes, .. } if self.offset + bytes.len() > max_len => {
+                Err(len_exceeded(max_len))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// The number of bytes read so far.
+    pub(crate) fn position(&self) -> usize {
+        match &self.source {
+            Source::Bytes { pos, .. } | Source::Reader { pos, .. } => self.offset + *pos,
+        }
+    }
+
+    pub(crate) fn has_remaining(&mut self) -> Result<bool, Error> {
+        match &mut self.source {
+            Source::Bytes { bytes, pos } => Ok(*pos < bytes.len()),
+            Source::Reader {
+                reader,
+                buffer,
+                pos,
+                len,
+            } => {
+                if *pos < *len {
+                    return Ok(true);
+                }
+                if buffer.is_empty() {
+                    buffer.resize(READER_BUFFER_SIZE, 0);
+                }
+                loop {
+                    match reader.read(buffer) {
+                        Ok(read) => {
+                            self.offset += *len;
+                            *pos = 0;
+                            *len = read;
+                            if self.offset + read > self.max_len {
+                                return Err(len_exceeded(self.max_len));
+                            }
+                            return Ok(read > 0);
+                        }
+                        Err(error) if error.kind() == ErrorKind::Interrupted => {}
+                        Err(error) => return Err(Error::IoError(error)),
+                    }
+                }
+            }
+        }
     }
 
     pub(crate) fn read_byte(&mut self) -> Result<u8, Error> {
-        if self.pos >= self.end {
-            return Err(Error::IoError(ErrorKind::UnexpectedEof));
+        if let Source::Bytes { bytes, pos } = &mut self.source {
+            let Some(&value) = bytes.get(*pos) else {
+                return Err(Error::TruncatedInput {
+                    position: self.offset + *pos,
+                });
+            };
+            *pos += 1;
+            return Ok(value);
+        }
+
+        if !self.has_remaining()? {
+            return Err(self.truncated());
+        }
+        match &mut self.source {
+            Source::Reader { buffer, pos, .. } => {
+                let value = buffer[*pos];
+                *pos += 1;
+                Ok(value)
+            }
+            Source::Bytes { .. } => unreachable!(),
+        }
+    }
+
+    #[cfg(feature = "protobuf")]
+    pub(crate) fn read_slice(&mut self, len: usize) -> Result<Input<'a>, Error> {
+        match &mut self.source {
+            Source::Bytes { bytes, pos } => {
+                if len > bytes.len() - *pos {
+                    return Err(Error::TruncatedInput {
+                        position: self.offset + bytes.len(),
+                    });
+                }
+                let slice = Input {
+                    source: Source::Bytes {
+                        bytes: &bytes[*pos..*pos + len],
+                        pos: 0,
+                    },
+                    offset: self.offset + *pos,
+                    max_len: usize::MAX,
+                };
+                *pos += len;
+                Ok(slice)
+            }
+            Source::Reader { .. } => Err(Error::InvalidArgument(
+                "Nested messages can only be read from bytes.",
+            )),
         }
-        let value = self.vec[self.pos];
-        self.pos += 1;
-        Ok(value)
     }
 
     fn read_long_le(&mut self) -> Result<u64, Error> {
-        if self.pos + 8 > self.end {
-            return Err(Error::IoError(ErrorKind::UnexpectedEof));
-        }
-
-        let value = u64::from_le_bytes([
-            self.vec[self.pos],
-            self.vec[self.pos + 1],
-            self.vec[self.pos + 2],
-            self.vec[self.pos + 3],
-            self.vec[self.pos + 4],
-            self.vec[self.pos + 5],
-            self.vec[self.pos + 6],
-            self.vec[self.pos + 7],
-        ]);
-        self.pos += 8;
-        Ok(value)
+        let mut value = [0; 8];
+        if let Source::Bytes { bytes, pos } = &mut self.source {
+            if *pos + 8 > bytes.len() {
+                return Err(Error::TruncatedInput {
+                    position: self.offset + bytes.len(),
+                });
+            }
+            value.copy_from_slice(&bytes[*pos..*pos + 8]);
+            *pos += 8;
+        } else {
+            for byte in value.iter_mut() {
+                *byte = self.read_byte()?;
+            }
+        }
+        Ok(u64::from_le_bytes(value))
     }
 
     pub(crate) fn read_double_le(&mut self) -> Result<f64, Error> {
         let value = f64::from_bits(self.read_long_le()?);
         Ok(value)
     }
+
+    fn truncated(&self) -> Error {
+        Error::TruncatedInput {
+            position: self.position(),
+        }
+    }
+}
+
+fn len_exceeded(max_len: usize) -> Error {
+    Error::LimitExceeded {
+        limit: "input length",
+        max: max_len as u64,
+    }
 }
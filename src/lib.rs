@@ -34,6 +34,19 @@ Also you could merge other DDSketch:
     assert_eq!(6.0,  d2.get_count());
 ```
 
+Record from many threads and query a snapshot:
+```rust
+    use self::sketches_rust::{ConcurrentDDSketch, DDSketch};
+    let d = ConcurrentDDSketch::new(DDSketch::unbounded_dense(0.02).unwrap());
+    std::thread::scope(|scope| {
+        for _ in 0..4 {
+            scope.spawn(|| d.accept(1.0));
+        }
+    });
+    let snapshot = d.snapshot().unwrap();
+    assert_eq!(4.0, snapshot.get_count());
+```
+
 Encode to bytes:
 ```rust
     use self::sketches_rust::{DDSketch};
@@ -54,18 +67,36 @@ Decode from bytes:
         3, 0, 0, 0, 3, 0, 2, 0, 0, 3, 3, 2, 2, 3, 3, 2, 0, 0, 0, 0, 2, 0, 2, 2, 2, 4, 4, 132, 64,
         0, 4, 2, 0, 2, 2, 3, 132, 64, 4, 132, 64, 4, 2, 2, 0, 6, 4, 6, 132, 64, 2, 6,
     ];
-    let mut d = DDSketch::decode(&input).unwrap();
+    let d = DDSketch::decode(&input).unwrap();
     assert_eq!(d.get_count(), 100.0);
 ```
  */
 
+mod builder;
+mod concurrent;
+mod decode_options;
 mod error;
 mod index_mapping;
 mod input;
 mod output;
+#[cfg(feature = "protobuf")]
+mod protobuf;
 mod serde;
+#[cfg(feature = "serde")]
+mod serialization;
 mod sketch;
 mod store;
+mod summary;
 
+pub use self::builder::{DDSketchBuilder, StoreKind};
+pub use self::concurrent::ConcurrentDDSketch;
+pub use self::decode_options::DecodeOptions;
 pub use self::error::Error;
-pub use self::sketch::DDSketch;
+pub use self::index_mapping::{IndexMapping, IndexMappingLayout};
+#[cfg(feature = "serde")]
+pub use self::sketch::DDSketchSeed;
+pub use self::sketch::{DDSketch, SubtractionPolicy};
+pub use self::store::{
+    CollapsingHighestDenseStore, CollapsingLowestDenseStore, CollapsingLowestSparseStore,
+    PaginatedStore, SparseStore, Store, UnboundedSizeDenseStore,
+};
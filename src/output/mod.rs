@@ -1,22 +1,108 @@
 use crate::error::Error;
+use std::io::Write;
 
-pub struct Output {
-    vec: Vec<u8>,
+const WRITER_BUFFER_SIZE: usize = 8 * 1024;
+
+/// The destination of an encoded sketch: a growable byte vector, a caller-provided slice, or a
+/// writer that is fed as encoding goes.
+pub struct Output<'a> {
+    sink: Sink<'a>,
+}
+
+enum Sink<'a> {
+    Vec(Vec<u8>),
+    Slice {
+        bytes: &'a mut [u8],
+        len: usize,
+    },
+    Writer {
+        writer: &'a mut dyn Write,
+        buffer: Vec<u8>,
+    },
 }
 
-impl Output {
-    pub fn with_capacity(capacity: usize) -> Output {
+impl<'a> Output<'a> {
+    pub fn with_capacity(capacity: usize) -> Output<'a> {
+        Output {
+            sink: Sink::Vec(Vec::with_capacity(capacity)),
+        }
+    }
+
+    /// Writes from the start of `bytes`, failing once they are full.
+    pub fn wrap(bytes: &'a mut [u8]) -> Output<'a> {
+        Output {
+            sink: Sink::Slice { bytes, len: 0 },
+        }
+    }
+
+    /// Writes to `writer` through an internal buffer, so `writer` does not need to be buffered.
+    /// [`Output::flush`] must be called once done writing.
+    pub fn to_writer(writer: &'a mut dyn Write) -> Output<'a> {
         Output {
-            vec: Vec::with_capacity(capacity),
+            sink: Sink::Writer {
+                writer,
+                buffer: Vec::with_capacity(WRITER_BUFFER_SIZE),
+            },
         }
     }
 
     pub fn trim(self) -> Vec<u8> {
-        self.vec
+        match self.sink {
+            Sink::Vec(vec) => vec,
+            Sink::Slice { bytes, len } => bytes[..len].to_vec(),
+            Sink::Writer { buffer, .. } => buffer,
+        }
+    }
+
+    /// The number of bytes written so far, or still buffered for a writer.
+    pub(crate) fn len(&self) -> usize {
+        match &self.sink {
+            Sink::Vec(vec) => vec.len(),
+            Sink::Slice { len, .. } => *len,
+            Sink::Writer { buffer, .. } => buffer.len(),
+        }
+    }
+
+    /// Writes out whatever is buffered, then flushes the writer, if any.
+    pub fn flush(&mut self) -> Result<(), Error> {
+        if let Sink::Writer { writer, buffer } = &mut self.sink {
+            writer.write_all(buffer)?;
+            buffer.clear();
+            writer.flush()?;
+        }
+        Ok(())
     }
 
     pub(crate) fn write_byte(&mut self, value: u8) -> Result<(), Error> {
-        self.vec.push(value);
+        if let Sink::Vec(vec) = &mut self.sink {
+            vec.push(value);
+            return Ok(());
+        }
+        self.write_bytes(&[value])
+    }
+
+    pub(crate) fn write_bytes(&mut self, value: &[u8]) -> Result<(), Error> {
+        match &mut self.sink {
+            Sink::Vec(vec) => vec.extend_from_slice(value),
+            Sink::Slice { bytes, len } => {
+                let end = *len + value.len();
+                if end > bytes.len() {
+                    return Err(Error::BufferTooSmall {
+                        needed: end,
+                        available: bytes.len(),
+                    });
+                }
+                bytes[*len..end].copy_from_slice(value);
+                *len = end;
+            }
+            Sink::Writer { writer, buffer } => {
+                buffer.extend_from_slice(value);
+                if buffer.len() >= WRITER_BUFFER_SIZE {
+                    writer.write_all(buffer)?;
+                    buffer.clear();
+                }
+            }
+        }
         Ok(())
     }
 
@@ -29,8 +115,6 @@ impl Output {
     // }
 
     pub(crate) fn write_double_le(&mut self, value: f64) -> Result<(), Error> {
-        let bytes = f64::to_le_bytes(value);
-        self.vec.extend(bytes);
-        Ok(())
+        self.write_bytes(&f64::to_le_bytes(value))
     }
 }
@@ -0,0 +1,434 @@
+//! Encoding of the messages defined by DataDog's `ddsketch.proto`:
+//!
+//! ```proto
+//! message DDSketch {
+//!   IndexMapping mapping = 1;
+//!   Store positiveValues = 2;
+//!   Store negativeValues = 3;
+//!   double zeroCount = 4;
+//! }
+//!
+//! message IndexMapping {
+//!   double gamma = 1;
+//!   double indexOffset = 2;
+//!   enum Interpolation { NONE = 0; LINEAR = 1; QUADRATIC = 2; CUBIC = 3; }
+//!   Interpolation interpolation = 3;
+//! }
+//!
+//! message Store {
+//!   map<sint32, double> binCounts = 1;
+//!   repeated double contiguousBinCounts = 2 [packed = true];
+//!   sint32 contiguousBinIndexOffset = 3;
+//! }
+//! ```
+
+use crate::decode_options::DecodeOptions;
+use crate::error::Error;
+use crate::index_mapping::{IndexMapping, IndexMappingLayout};
+use crate::input::Input;
+use crate::output::Output;
+use crate::store::Store;
+
+pub(crate) const WIRE_TYPE_VARINT: u8 = 0;
+pub(crate) const WIRE_TYPE_I64: u8 = 1;
+pub(crate) const WIRE_TYPE_LEN: u8 = 2;
+pub(crate) const WIRE_TYPE_I32: u8 = 5;
+
+pub(crate) const SKETCH_MAPPING: u32 = 1;
+pub(crate) const SKETCH_POSITIVE_VALUES: u32 = 2;
+pub(crate) const SKETCH_NEGATIVE_VALUES: u32 = 3;
+pub(crate) const SKETCH_ZERO_COUNT: u32 = 4;
+
+const MAPPING_GAMMA: u32 = 1;
+const MAPPING_INDEX_OFFSET: u32 = 2;
+const MAPPING_INTERPOLATION: u32 = 3;
+
+const INTERPOLATION_NONE: u64 = 0;
+const INTERPOLATION_LINEAR: u64 = 1;
+const INTERPOLATION_QUADRATIC: u64 = 2;
+const INTERPOLATION_CUBIC: u64 = 3;
+
+const STORE_BIN_COUNTS: u32 = 1;
+const STORE_CONTIGUOUS_BIN_COUNTS: u32 = 2;
+const STORE_CONTIGUOUS_BIN_INDEX_OFFSET: u32 = 3;
+
+const BIN_COUNT_INDEX: u32 = 1;
+const BIN_COUNT_COUNT: u32 = 2;
+
+pub(crate) fn encode_index_mapping(
+    output: &mut Output,
+    index_mapping: &IndexMapping,
+) -> Result<(), Error> {
+    let interpolation = match index_mapping.layout() {
+        IndexMappingLayout::LOG => INTERPOLATION_NONE,
+        IndexMappingLayout::LogLinear => INTERPOLATION_LINEAR,
+        IndexMappingLayout::LogQuadratic => INTERPOLATION_QUADRATIC,
+        IndexMappingLayout::LogCubic => INTERPOLATION_CUBIC,
+        IndexMappingLayout::LogQuartic => {
+            return Err(Error::InvalidArgument(
+                "The quartic interpolation cannot be encoded to protobuf.",
+            ))
+        }
+    };
+    encode_double_field(output, MAPPING_GAMMA, index_mapping.gamma())?;
+    encode_double_field(output, MAPPING_INDEX_OFFSET, index_mapping.index_offset())?;
+    if interpolation != INTERPOLATION_NONE {
+        encode_tag(output, MAPPING_INTERPOLATION, WIRE_TYPE_VARINT)?;
+        encode_varint(output, interpolation)?;
+    }
+    Ok(())
+}
+
+pub(crate) fn decode_index_mapping(input: &mut Input) -> Result<IndexMapping, Error> {
+    let mut gamma = 0.0;
+    let mut index_offset = 0.0;
+    let mut layout = IndexMappingLayout::LOG;
+    while input.has_remaining()? {
+        let (field_number, wire_type) = decode_tag(input)?;
+        match (field_number, wire_type) {
+            (MAPPING_GAMMA, WIRE_TYPE_I64) => gamma = input.read_double_le()?,
+            (MAPPING_INDEX_OFFSET, WIRE_TYPE_I64) => index_offset = input.read_double_le()?,
+            (MAPPING_INTERPOLATION, WIRE_TYPE_VARINT) => {
+                layout = match decode_varint(input)? {
+                    INTERPOLATION_NONE => IndexMappingLayout::LOG,
+                    INTERPOLATION_LINEAR => IndexMappingLayout::LogLinear,
+                    INTERPOLATION_QUADRATIC => IndexMappingLayout::LogQuadratic,
+                    INTERPOLATION_CUBIC => IndexMappingLayout::LogCubic,
+                    _ => return Err(Error::InvalidArgument("Unknown interpolation.")),
+                }
+            }
+            _ => skip_field(input, wire_type)?,
+        }
+    }
+    IndexMapping::with_gamma_offset(layout, gamma, index_offset)
+}
+
+/// Writes the store as a map of non-empty bins, or as a range of contiguous counts if that is
+/// more compact.
+pub(crate) fn encode_store(output: &mut Output, store: &dyn Store) -> Result<(), Error> {
+    if store.is_empty() {
+        return Ok(());
+    }
+
+    let min_index = store.get_min_index();
+    let max_index = store.get_max_index();
+    let mut bin_counts_size = 0;
+    for (index, _) in store.get_ascending_iter() {
+        bin_counts_size += 12 + varint_size(zigzag(index));
+    }
+    let num_contiguous_counts = (max_index as i64 - min_index as i64 + 1) as u64;
+    let mut contiguous_size =
+        1 + varint_size(8 * num_contiguous_counts) + 8 * num_contiguous_counts;
+    if min_index != 0 {
+        contiguous_size += 1 + varint_size(zigzag(min_index));
+    }
+
+    if contiguous_size < bin_counts_size {
+        encode_tag(output, STORE_CONTIGUOUS_BIN_COUNTS, WIRE_TYPE_LEN)?;
+        encode_varint(output, 8 * num_contiguous_counts)?;
+        let mut next_index = min_index;
+        for (index, count) in store.get_ascending_iter() {
+            while next_index < index {
+                output.write_double_le(0.0)?;
+                next_index += 1;
+            }
+            output.write_double_le(count)?;
+            next_index = index.saturating_add(1);
+        }
+        if min_index != 0 {
+            encode_tag(output, STORE_CONTIGUOUS_BIN_INDEX_OFFSET, WIRE_TYPE_VARINT)?;
+            encode_varint(output, zigzag(min_index))?;
+        }
+    } else {
+        for (index, count) in store.get_ascending_iter() {
+            encode_tag(output, STORE_BIN_COUNTS, WIRE_TYPE_LEN)?;
+            encode_varint(output, 10 + varint_size(zigzag(index)))?;
+            encode_tag(output, BIN_COUNT_INDEX, WIRE_TYPE_VARINT)?;
+            encode_varint(output, zigzag(index))?;
+            encode_double_field(output, BIN_COUNT_COUNT, count)?;
+        }
+    }
+    Ok(())
+}
+
+/// Merges the bins of a `Store` message into `store`. The contiguous counts are only added once
+/// the whole message is read, as their index offset may come after them.
+pub(crate) fn decode_store_and_merge_with(
+    input: &mut Input,
+    store: &mut dyn Store,
+    options: &DecodeOptions,
+) -> Result<(), Error> {
+    let mut num_bins = 0;
+    let mut contiguous_bin_counts = Vec::new();
+    let mut contiguous_bin_index_offset = 0;
+    while input.has_remaining()? {
+        let (field_number, wire_type) = decode_tag(input)?;
+        match (field_number, wire_type) {
+            (STORE_BIN_COUNTS, WIRE_TYPE_LEN) => {
+                let mut entry = decode_len_delimited(input)?;
+                let mut index = 0;
+                let mut count = 0.0;
+                while entry.has_remaining()? {
+                    let (field_number, wire_type) = decode_tag(&mut entry)?;
+                    match (field_number, wire_type) {
+                        (BIN_COUNT_INDEX, WIRE_TYPE_VARINT) => {
+                            index = unzigzag(decode_varint(&mut entry)?)
+                        }
+                        (BIN_COUNT_COUNT, WIRE_TYPE_I64) => count = entry.read_double_le()?,
+                        _ => skip_field(&mut entry, wire_type)?,
+                    }
+                }
+                num_bins += 1;
+                options.check_num_bins(num_bins)?;
+                options.check_count(count)?;
+                options.check_index(store, index)?;
+                store.add_bin((index, count));
+            }
+            (STORE_CONTIGUOUS_BIN_COUNTS, WIRE_TYPE_LEN) => {
+                let mut packed = decode_len_delimited(input)?;
+                while packed.has_remaining()? {
+                    contiguous_bin_counts.push(packed.read_double_le()?);
+                    options.check_num_bins(num_bins + contiguous_bin_counts.len() as i64)?;
+                }
+            }
+            (STORE_CONTIGUOUS_BIN_COUNTS, WIRE_TYPE_I64) => {
+                contiguous_bin_counts.push(input.read_double_le()?);
+                options.check_num_bins(num_bins + contiguous_bin_counts.len() as i64)?;
+            }
+            (STORE_CONTIGUOUS_BIN_INDEX_OFFSET, WIRE_TYPE_VARINT) => {
+                contiguous_bin_index_offset = unzigzag(decode_varint(input)?);
+            }
+            _ => skip_field(input, wire_type)?,
+        }
+    }
+
+    if contiguous_bin_counts.is_empty() {
+        return Ok(());
+    }
+    let max_index = contiguous_bin_index_offset as i64 + contiguous_bin_counts.len() as i64 - 1;
+    if max_index > i32::MAX as i64 {
+        return Err(Error::IndexOverflow(max_index));
+    }
+    for (index, count) in (contiguous_bin_index_offset..).zip(contiguous_bin_counts) {
+        options.check_count(count)?;
+        if count != 0.0 {
+            options.check_index(store, index)?;
+        }
+        store.add_bin((index, count));
+    }
+    Ok(())
+}
+
+pub(crate) fn encode_message<F>(
+    output: &mut Output,
+    field_number: u32,
+    encode: F,
+) -> Result<(), Error>
+where
+    F: FnOnce(&mut Output) -> Result<(), Error>,
+{
+    let mut message = Output::with_capacity(64);
+    encode(&mut message)?;
+    let bytes = message.trim();
+    encode_tag(output, field_number, WIRE_TYPE_LEN)?;
+    encode_varint(output, bytes.len() as u64)?;
+    output.write_bytes(&bytes)
+}
+
+pub(crate) fn encode_double_field(
+    output: &mut Output,
+    field_number: u32,
+    value: f64,
+) -> Result<(), Error> {
+    encode_tag(output, field_number, WIRE_TYPE_I64)?;
+    output.write_double_le(value)
+}
+
+fn encode_tag(output: &mut Output, field_number: u32, wire_type: u8) -> Result<(), Error> {
+    encode_varint(output, ((field_number as u64) << 3) | wire_type as u64)
+}
+
+pub(crate) fn decode_tag(input: &mut Input) -> Result<(u32, u8), Error> {
+    let tag = decode_varint(input)?;
+    let field_number = tag >> 3;
+    if field_number == 0 || field_number > u32::MAX as u64 {
+        return Err(Error::InvalidArgument("Invalid field number."));
+    }
+    Ok((field_number as u32, (tag & 0x07) as u8))
+}
+
+pub(crate) fn decode_len_delimited<'a>(input: &mut Input<'a>) -> Result<Input<'a>, Error> {
+    let len = decode_varint(input)?;
+    if len > usize::MAX as u64 {
+        return Err(Error::InvalidArgument("Invalid length."));
+    }
+    input.read_slice(len as usize)
+}
+
+pub(crate) fn skip_field(input: &mut Input, wire_type: u8) -> Result<(), Error> {
+    match wire_type {
+        WIRE_TYPE_VARINT => {
+            decode_varint(input)?;
+        }
+        WIRE_TYPE_I64 => {
+            input.read_double_le()?;
+        }
+        WIRE_TYPE_LEN => {
+            decode_len_delimited(input)?;
+        }
+        WIRE_TYPE_I32 => {
+            input.read_slice(4)?;
+        }
+        _ => return Err(Error::InvalidArgument("Unsupported wire type.")),
+    }
+    Ok(())
+}
+
+fn encode_varint(output: &mut Output, mut value: u64) -> Result<(), Error> {
+    while value >= 0x80 {
+        output.write_byte((value as u8) | 0x80)?;
+        value >>= 7;
+    }
+    output.write_byte(value as u8)
+}
+
+fn decode_varint(input: &mut Input) -> Result<u64, Error> {
+    let mut value = 0;
+    for shift in (0..64).step_by(7) {
+        let next = input.read_byte()?;
+        value |= ((next & 0x7F) as u64) << shift;
+        if next & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(Error::InvalidArgument("Malformed varint."))
+}
+
+fn varint_size(value: u64) -> u64 {
+    (64 - (value | 1).leading_zeros() as u64).div_ceil(7)
+}
+
+fn zigzag(value: i32) -> u64 {
+    ((value << 1) ^ (value >> 31)) as u32 as u64
+}
+
+fn unzigzag(value: u64) -> i32 {
+    let value = value as u32;
+    ((value >> 1) as i32) ^ -((value & 1) as i32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::SparseStore;
+
+    #[test]
+    fn test_varint() {
+        for value in [0, 1, 127, 128, 300, 16383, 16384, u32::MAX as u64, u64::MAX] {
+            let mut output = Output::with_capacity(10);
+            encode_varint(&mut output, value).unwrap();
+            let bytes = output.trim();
+            assert_eq!(varint_size(value), bytes.len() as u64);
+            let mut input = Input::wrap(&bytes);
+            assert_eq!(value, decode_varint(&mut input).unwrap());
+            assert!(!input.has_remaining().unwrap());
+        }
+    }
+
+    #[test]
+    fn test_zigzag() {
+        assert_eq!(0, zigzag(0));
+        assert_eq!(1, zigzag(-1));
+        assert_eq!(2, zigzag(1));
+        assert_eq!(4294967294, zigzag(i32::MAX));
+        assert_eq!(4294967295, zigzag(i32::MIN));
+        for value in [0, 1, -1, 1000, -1000, i32::MAX, i32::MIN] {
+            assert_eq!(value, unzigzag(zigzag(value)));
+        }
+    }
+
+    #[test]
+    fn test_store_bin_counts() {
+        let mut store = SparseStore::new();
+        store.add(-2, 1.0);
+        store.add(1000, 2.0);
+
+        let mut output = Output::with_capacity(64);
+        encode_store(&mut output, &store).unwrap();
+        let bytes = output.trim();
+        // binCounts { key: -2 value: 1.0 } binCounts { key: 1000 value: 2.0 }
+        assert_eq!(
+            vec![
+                0x0A, 0x0B, 0x08, 0x03, 0x11, 0, 0, 0, 0, 0, 0, 0xF0, 0x3F, //
+                0x0A, 0x0C, 0x08, 0xD0, 0x0F, 0x11, 0, 0, 0, 0, 0, 0, 0x00, 0x40,
+            ],
+            bytes
+        );
+
+        let mut decoded = SparseStore::new();
+        decode_store_and_merge_with(
+            &mut Input::wrap(&bytes),
+            &mut decoded,
+            &DecodeOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(store.get_ascending_stream(), decoded.get_ascending_stream());
+    }
+
+    #[test]
+    fn test_store_contiguous_bin_counts() {
+        let mut store = SparseStore::new();
+        store.add(3, 1.0);
+        store.add(5, 2.0);
+        store.add(6, 4.0);
+
+        let mut output = Output::with_capacity(64);
+        encode_store(&mut output, &store).unwrap();
+        let bytes = output.trim();
+        // contiguousBinCounts: [1.0, 0.0, 2.0, 4.0] contiguousBinIndexOffset: 3
+        assert_eq!(
+            vec![
+                0x12, 0x20, 0, 0, 0, 0, 0, 0, 0xF0, 0x3F, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+                0x00, 0x40, 0, 0, 0, 0, 0, 0, 0x10, 0x40, 0x18, 0x06,
+            ],
+            bytes
+        );
+
+        let mut decoded = SparseStore::new();
+        decode_store_and_merge_with(
+            &mut Input::wrap(&bytes),
+            &mut decoded,
+            &DecodeOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(store.get_ascending_stream(), decoded.get_ascending_stream());
+    }
+
+    #[test]
+    fn test_store_unpacked_contiguous_bin_counts() {
+        // contiguousBinIndexOffset: -1 contiguousBinCounts: 1.0 contiguousBinCounts: 3.0
+        let bytes = vec![
+            0x18, 0x01, 0x11, 0, 0, 0, 0, 0, 0, 0xF0, 0x3F, 0x11, 0, 0, 0, 0, 0, 0, 0x08, 0x40,
+        ];
+        let mut store = SparseStore::new();
+        decode_store_and_merge_with(
+            &mut Input::wrap(&bytes),
+            &mut store,
+            &DecodeOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(vec![(-1, 1.0), (0, 3.0)], store.get_ascending_stream());
+    }
+
+    #[test]
+    fn test_skip_unknown_fields() {
+        // field 15 (varint), field 16 (fixed32), field 17 (bytes), then gamma and indexOffset
+        let bytes = vec![
+            0x78, 0x96, 0x01, 0x85, 0x01, 1, 2, 3, 4, 0x8A, 0x01, 0x02, 0xAA, 0xBB, 0x09, 0, 0, 0,
+            0, 0, 0, 0, 0x40, 0x11, 0, 0, 0, 0, 0, 0, 0xF0, 0x3F,
+        ];
+        let index_mapping = decode_index_mapping(&mut Input::wrap(&bytes)).unwrap();
+        assert_eq!(2.0, index_mapping.gamma());
+        assert_eq!(1.0, index_mapping.index_offset());
+    }
+}
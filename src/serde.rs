@@ -1,7 +1,6 @@
 use crate::error::Error;
 use crate::input::*;
 use crate::output::Output;
-use crate::sketch::Flag;
 
 const SIGNIFICAND_WIDTH: i64 = 53;
 const SIGNIFICAND_MASK: i64 = 0x000fffffffffffff;
@@ -60,7 +59,7 @@ pub fn decode_var_double(input: &mut Input) -> Result<f64, Error> {
 pub fn i64_to_i32_exact(value: i64) -> Result<i32, Error> {
     let v = value as i32;
     if value != v as i64 {
-        return Err(Error::InvalidArgument("Value is not valid i32."));
+        return Err(Error::IndexOverflow(value));
     }
     Ok(v)
 }
@@ -96,18 +95,6 @@ fn var_bits_to_double(bits: i64) -> f64 {
     f64::from_bits((i64::rotate_right(bits, 6) + f64::to_bits(1.0) as i64) as u64) - 1.0
 }
 
-pub fn ignore_exact_summary_statistic_flags(input: &mut Input, flag: Flag) -> Result<(), Error> {
-    if flag == Flag::COUNT {
-        decode_var_double(input)?;
-        Ok(())
-    } else if flag == Flag::SUM || flag == Flag::MIN || flag == Flag::MAX {
-        input.read_double_le()?;
-        Ok(())
-    } else {
-        Err(Error::InvalidArgument("Unknown Flag."))
-    }
-}
-
 pub fn encode_var_double(output: &mut Output, value: f64) -> Result<(), Error> {
     let mut bits = double_to_var_bits(value);
     for _ in 0..8 {
@@ -0,0 +1,268 @@
+//! Helpers shared by the `serde` implementations of the sketch, the index mapping and the stores.
+//! Self-describing formats get the bins as an index to count map, the other formats get the
+//! compact binary encoding.
+
+use crate::decode_options::DecodeOptions;
+use crate::error::Error;
+use crate::input::Input;
+use crate::output::Output;
+use crate::sketch::{Flag, FlagType};
+use crate::store::{BinEncodingMode, SparseStore, Store};
+use ::serde::de::{
+    self, Deserialize, DeserializeSeed, Deserializer, MapAccess, SeqAccess, Visitor,
+};
+use ::serde::ser::{self, Serialize, SerializeMap, SerializeStruct, Serializer};
+use std::fmt;
+
+pub(crate) struct BinsRef<'a>(pub &'a dyn Store);
+
+impl Serialize for BinsRef<'_> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        if serializer.is_human_readable() {
+            let mut map = serializer.serialize_map(None)?;
+            for (index, count) in self.0.get_ascending_iter() {
+                map.serialize_entry(&index, &count)?;
+            }
+            map.end()
+        } else {
+            let mut output = Output::with_capacity(64);
+            self.0
+                .encode(&mut output, FlagType::PositiveStore)
+                .map_err(ser::Error::custom)?;
+            serializer.serialize_bytes(&output.trim())
+        }
+    }
+}
+
+pub(crate) struct Bins(pub Vec<(i32, f64)>);
+
+/// The options bins are deserialized with when none are given. Invalid counts are rejected, as
+/// they cannot be told apart from valid ones once added to a store.
+pub(crate) fn default_decode_options() -> DecodeOptions {
+    DecodeOptions::new().reject_invalid_counts(true)
+}
+
+impl<'de> Deserialize<'de> for Bins {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        BinsSeed(&default_decode_options()).deserialize(deserializer)
+    }
+}
+
+/// Deserializes bins within the limits of the decode options.
+pub(crate) struct BinsSeed<'a>(pub &'a DecodeOptions);
+
+impl<'de> DeserializeSeed<'de> for BinsSeed<'_> {
+    type Value = Bins;
+
+    fn deserialize<D>(self, deserializer: D) -> Result<Self::Value, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        if deserializer.is_human_readable() {
+            deserializer.deserialize_map(BinsVisitor(self.0))
+        } else {
+            let bytes = ByteBuf::deserialize(deserializer)?;
+            decode_bins(&bytes.0, self.0)
+                .map(Bins)
+                .map_err(de::Error::custom)
+        }
+    }
+}
+
+struct BinsVisitor<'a>(&'a DecodeOptions);
+
+impl<'de> Visitor<'de> for BinsVisitor<'_> {
+    type Value = Bins;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a map from bin indexes to counts")
+    }
+
+    fn visit_map<A>(self, mut map: A) -> Result<Self::Value, A::Error>
+    where
+        A: MapAccess<'de>,
+    {
+        let options = self.0;
+        let mut store = SparseStore::new();
+        let mut num_bins = 0;
+        while let Some((index, count)) = map.next_entry::<i32, f64>()? {
+            num_bins += 1;
+            options
+                .check_num_bins(num_bins)
+                .and_then(|_| options.check_count(count))
+                .and_then(|_| options.check_index(&store, index))
+                .map_err(de::Error::custom)?;
+            store.add_bin((index, count));
+        }
+        Ok(Bins(store.get_ascending_stream()))
+    }
+}
+
+fn decode_bins(bytes: &[u8], options: &DecodeOptions) -> Result<Vec<(i32, f64)>, Error> {
+    let mut store = SparseStore::new();
+    let mut input = Input::wrap(bytes);
+    if let Some(max_len) = options.get_max_len() {
+        input.limit_len(max_len)?;
+    }
+    while input.has_remaining()? {
+        let flag = Flag::decode(&mut input)?;
+        let mode = BinEncodingMode::of_flag(flag.get_marker())?;
+        store.decode_and_merge_with(&mut input, mode, options)?;
+    }
+    Ok(store.get_ascending_stream())
+}
+
+pub(crate) struct ByteBuf(pub Vec<u8>);
+
+impl<'de> Deserialize<'de> for ByteBuf {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_byte_buf(ByteBufVisitor)
+    }
+}
+
+struct ByteBufVisitor;
+
+impl<'de> Visitor<'de> for ByteBufVisitor {
+    type Value = ByteBuf;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a byte array")
+    }
+
+    fn visit_bytes<E>(self, bytes: &[u8]) -> Result<Self::Value, E>
+    where
+        E: de::Error,
+    {
+        Ok(ByteBuf(bytes.to_vec()))
+    }
+
+    fn visit_byte_buf<E>(self, bytes: Vec<u8>) -> Result<Self::Value, E>
+    where
+        E: de::Error,
+    {
+        Ok(ByteBuf(bytes))
+    }
+
+    fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
+    where
+        A: SeqAccess<'de>,
+    {
+        let mut bytes = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+        while let Some(byte) = seq.next_element()? {
+            bytes.push(byte);
+        }
+        Ok(ByteBuf(bytes))
+    }
+}
+
+pub(crate) fn serialize_bins<S>(store: &dyn Store, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    BinsRef(store).serialize(serializer)
+}
+
+pub(crate) fn deserialize_bins<'de, D>(deserializer: D) -> Result<Vec<(i32, f64)>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Ok(Bins::deserialize(deserializer)?.0)
+}
+
+/// Serializes a store that keeps a bounded number of bins, along with that bound.
+pub(crate) fn serialize_bounded_bins<S>(
+    store: &dyn Store,
+    max_num_bins: usize,
+    serializer: S,
+) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    let mut state = serializer.serialize_struct("BoundedStore", 2)?;
+    state.serialize_field("max_num_bins", &max_num_bins)?;
+    state.serialize_field("bins", &BinsRef(store))?;
+    state.end()
+}
+
+#[derive(::serde::Deserialize)]
+#[serde(rename = "BoundedStore")]
+pub(crate) struct BoundedBins {
+    pub max_num_bins: usize,
+    pub bins: Bins,
+}
+
+pub(crate) fn deserialize_bounded_bins<'de, D>(deserializer: D) -> Result<BoundedBins, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    BoundedBins::deserialize(deserializer)
+}
+
+/// Implements `Serialize` and `Deserialize` for a store from its non-empty bins. Bounded stores
+/// name the field that holds their capacity, which is serialized along with the bins and given
+/// back to their `with_capacity` constructor.
+macro_rules! impl_store_serde {
+    ($store:ident) => {
+        impl ::serde::Serialize for $store {
+            fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+            where
+                S: ::serde::Serializer,
+            {
+                $crate::serialization::serialize_bins(self, serializer)
+            }
+        }
+
+        impl<'de> ::serde::Deserialize<'de> for $store {
+            fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+            where
+                D: ::serde::Deserializer<'de>,
+            {
+                let mut store = $store::new();
+                for bin in $crate::serialization::deserialize_bins(deserializer)? {
+                    store.add_bin(bin);
+                }
+                Ok(store)
+            }
+        }
+    };
+    ($store:ident, bounded by $max_num_bins:ident) => {
+        impl ::serde::Serialize for $store {
+            fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+            where
+                S: ::serde::Serializer,
+            {
+                $crate::serialization::serialize_bounded_bins(
+                    self,
+                    self.$max_num_bins as usize,
+                    serializer,
+                )
+            }
+        }
+
+        impl<'de> ::serde::Deserialize<'de> for $store {
+            fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+            where
+                D: ::serde::Deserializer<'de>,
+            {
+                let bounded_bins = $crate::serialization::deserialize_bounded_bins(deserializer)?;
+                let mut store = $store::with_capacity(bounded_bins.max_num_bins)
+                    .map_err(::serde::de::Error::custom)?;
+                for bin in bounded_bins.bins.0 {
+                    store.add_bin(bin);
+                }
+                Ok(store)
+            }
+        }
+    };
+}
+
+pub(crate) use impl_store_serde;
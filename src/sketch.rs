@@ -1,21 +1,62 @@
+use crate::builder::{DDSketchBuilder, StoreKind};
+use crate::decode_options::DecodeOptions;
 use crate::error::Error;
 use crate::index_mapping::IndexMappingLayout::{LogCubic, LOG};
 use crate::index_mapping::{IndexMapping, IndexMappingLayout};
 use crate::input::Input;
 use crate::output::Output;
+#[cfg(feature = "protobuf")]
+use crate::protobuf;
 use crate::serde;
-use crate::store::{
-    BinEncodingMode, CollapsingHighestDenseStore, CollapsingLowestDenseStore, Store,
-    UnboundedSizeDenseStore,
-};
+use crate::store::{BinEncodingMode, Store, UnboundedSizeDenseStore};
+use crate::summary::SummaryStatistics;
+use std::fmt;
+use std::io::{Read, Write};
 
-pub struct DDSketch {
+/// A quantile sketch with relative-error guarantees. `P` and `N` are the stores of the positive
+/// and negative values. By default they are boxed, so that sketches with different stores have the
+/// same type; concrete stores let the compiler inline the store calls of [`DDSketch::accept`].
+#[derive(Clone)]
+pub struct DDSketch<P = Box<dyn Store>, N = Box<dyn Store>> {
     index_mapping: IndexMapping,
     min_indexed_value: f64,
     max_indexed_value: f64,
-    negative_value_store: Box<dyn Store>,
-    positive_value_store: Box<dyn Store>,
+    negative_value_store: N,
+    positive_value_store: P,
     zero_count: f64,
+    summary_statistics: Option<SummaryStatistics>,
+}
+
+impl<P: Store, N: Store> fmt::Debug for DDSketch<P, N> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("DDSketch")
+            .field("index_mapping", &self.index_mapping)
+            .field("zero_count", &self.zero_count)
+            .field(
+                "positive_value_store",
+                &(&self.positive_value_store as &dyn Store),
+            )
+            .field(
+                "negative_value_store",
+                &(&self.negative_value_store as &dyn Store),
+            )
+            .field("summary_statistics", &self.summary_statistics)
+            .finish()
+    }
+}
+
+/// Two sketches are equal if they have the same index mapping, zero count, non-empty bins and
+/// exact summary statistics, whatever the implementation of their stores.
+impl<P: Store, N: Store, OP: Store, ON: Store> PartialEq<DDSketch<OP, ON>> for DDSketch<P, N> {
+    fn eq(&self, other: &DDSketch<OP, ON>) -> bool {
+        self.index_mapping == other.index_mapping
+            && self.zero_count == other.zero_count
+            && (&self.positive_value_store as &dyn Store)
+                == (&other.positive_value_store as &dyn Store)
+            && (&self.negative_value_store as &dyn Store)
+                == (&other.negative_value_store as &dyn Store)
+            && self.summary_statistics == other.summary_statistics
+    }
 }
 
 #[derive(PartialEq)]
@@ -30,28 +71,40 @@ pub enum FlagType {
     NegativeStore = 0b11,
 }
 
-impl DDSketch {
+impl<P: Store, N: Store> DDSketch<P, N> {
     pub fn accept(&mut self, value: f64) {
-        self.accept_with_count(value, 1.0);
+        self.add(value, 1.0);
     }
 
-    pub fn accept_with_count(&mut self, value: f64, count: f64) {
+    pub fn accept_with_count(&mut self, value: f64, count: f64) -> Result<(), Error> {
+        if count.is_nan() || count.is_infinite() {
+            return Err(Error::InvalidArgument("The count must be finite."));
+        }
         if count < 0.0 {
-            return;
+            return Err(Error::InvalidArgument("The count cannot be negative."));
         }
+        self.add(value, count);
+        Ok(())
+    }
 
-        if value < -self.max_indexed_value || value > self.max_indexed_value {
+    fn add(&mut self, value: f64, count: f64) {
+        // NaN would otherwise be counted as a zero and spoil the exact sum.
+        if value.is_nan() || value < -self.max_indexed_value || value > self.max_indexed_value {
             return;
         }
 
+        if let Some(summary_statistics) = self.summary_statistics.as_mut() {
+            summary_statistics.add(value, count);
+        }
+
         if value > self.min_indexed_value {
             self.positive_value_store
-                .add(self.index_mapping.index(value), 1.0);
+                .add(self.index_mapping.index(value), count);
         } else if value < -self.min_indexed_value {
             self.negative_value_store
-                .add(self.index_mapping.index(-value), 1.0);
+                .add(self.index_mapping.index(-value), count);
         } else {
-            self.zero_count += 1.0;
+            self.zero_count += count;
         }
     }
 
@@ -65,20 +118,54 @@ impl DDSketch {
         self.negative_value_store.clear();
         self.positive_value_store.clear();
         self.zero_count = 0.0;
+        if let Some(summary_statistics) = self.summary_statistics.as_mut() {
+            summary_statistics.clear();
+        }
+    }
+
+    /// Tracks the exact count, sum, minimum and maximum of the accepted values, instead of
+    /// estimating them from the bins. The bins only hold estimates, so this fails if the sketch
+    /// already holds values.
+    pub fn with_exact_summary_statistics(mut self) -> Result<Self, Error> {
+        if self.summary_statistics.is_none() {
+            if !self.is_empty() {
+                return Err(Error::InvalidArgument(
+                    "Exact summary statistics can only be tracked from an empty sketch.",
+                ));
+            }
+            self.summary_statistics = Some(SummaryStatistics::new());
+        }
+        Ok(self)
     }
 
-    pub fn get_count(&mut self) -> f64 {
+    pub fn has_exact_summary_statistics(&self) -> bool {
+        self.summary_statistics.is_some()
+    }
+
+    /// Returns the number of bytes used by the sketch, including what its stores have allocated
+    /// on the heap.
+    pub fn get_memory_footprint(&self) -> usize {
+        std::mem::size_of::<Self>()
+            + self.negative_value_store.get_memory_footprint()
+            + self.positive_value_store.get_memory_footprint()
+    }
+
+    pub fn get_count(&self) -> f64 {
         self.zero_count
             + self.negative_value_store.get_total_count()
             + self.positive_value_store.get_total_count()
     }
 
-    pub fn get_sum(&mut self) -> Option<f64> {
+    pub fn get_sum(&self) -> Option<f64> {
         let count = self.get_count();
         if count <= 0.0 {
             return None;
         }
 
+        if let Some(summary_statistics) = self.summary_statistics.as_ref() {
+            return Some(summary_statistics.get_sum());
+        }
+
         let mut sum = 0.0;
         sum -= self.negative_value_store.get_sum(&self.index_mapping);
         sum += self.positive_value_store.get_sum(&self.index_mapping);
@@ -86,7 +173,13 @@ impl DDSketch {
         Some(sum)
     }
 
-    pub fn get_max(&mut self) -> Option<f64> {
+    pub fn get_max(&self) -> Option<f64> {
+        if let Some(summary_statistics) = self.summary_statistics.as_ref() {
+            if summary_statistics.get_count() > 0.0 && summary_statistics.has_bounds() {
+                return Some(summary_statistics.get_max());
+            }
+        }
+
         if !self.positive_value_store.is_empty() {
             Some(
                 self.index_mapping
@@ -105,7 +198,13 @@ impl DDSketch {
         }
     }
 
-    pub fn get_min(&mut self) -> Option<f64> {
+    pub fn get_min(&self) -> Option<f64> {
+        if let Some(summary_statistics) = self.summary_statistics.as_ref() {
+            if summary_statistics.get_count() > 0.0 && summary_statistics.has_bounds() {
+                return Some(summary_statistics.get_min());
+            }
+        }
+
         if !self.negative_value_store.is_empty() {
             Some(
                 -self
@@ -124,7 +223,7 @@ impl DDSketch {
         }
     }
 
-    pub fn get_average(&mut self) -> Option<f64> {
+    pub fn get_average(&self) -> Option<f64> {
         let count = self.get_count();
         if count <= 0.0 {
             return None;
@@ -132,7 +231,7 @@ impl DDSketch {
         Some(self.get_sum()? / count)
     }
 
-    pub fn get_value_at_quantile(self: &mut DDSketch, quantile: f64) -> Option<f64> {
+    pub fn get_value_at_quantile(&self, quantile: f64) -> Option<f64> {
         if !(0.0..=1.0).contains(&quantile) {
             return None;
         }
@@ -145,6 +244,7 @@ impl DDSketch {
         let rank = quantile * (count - 1.0);
 
         let mut n: f64 = 0.0;
+        let mut last_bin = None;
 
         let negative_bin_iterator = self.negative_value_store.get_descending_iter();
         for bin in negative_bin_iterator {
@@ -152,12 +252,16 @@ impl DDSketch {
             if n > rank {
                 return Some(-self.index_mapping.value(bin.0));
             }
+            last_bin = Some((-1.0, bin.0));
         }
 
         n += self.zero_count;
         if n > rank {
             return Some(0.0);
         }
+        if self.zero_count > 0.0 {
+            last_bin = Some((0.0, 0));
+        }
 
         let positive_bin_iterator = self.positive_value_store.get_ascending_iter();
         for bin in positive_bin_iterator {
@@ -165,147 +269,1098 @@ impl DDSketch {
             if n > rank {
                 return Some(self.index_mapping.value(bin.0));
             }
+            last_bin = Some((1.0, bin.0));
         }
 
-        None
+        // Rounding errors may leave the highest ranks past the accumulated count.
+        last_bin.map(|(sign, index)| self.signed_value(sign, index))
     }
 
-    pub fn decode_and_merge_with(&mut self, bytes: &Vec<u8>) -> Result<(), Error> {
-        let mut input = Input::wrap(bytes);
-        while input.has_remaining() {
-            let flag = Flag::decode(&mut input)?;
-            let flag_type = flag.get_type()?;
-            match flag_type {
-                FlagType::PositiveStore => {
-                    let mode = BinEncodingMode::of_flag(flag.get_marker())?;
-                    self.positive_value_store
-                        .decode_and_merge_with(&mut input, mode)?;
+    /// Returns the number of values that are less than or equal to `value`, or `None` if `value`
+    /// is NaN.
+    ///
+    /// The sketch cannot tell apart the values that share a bin, so all the values of the bin of
+    /// `value` are counted. The result is at least the exact rank of `value`, and at most the exact
+    /// rank of a value within a factor `(1 + a) / (1 - a)` of it, `a` being the relative accuracy
+    /// of the index mapping.
+    pub fn get_rank(&self, value: f64) -> Option<f64> {
+        if value.is_nan() {
+            return None;
+        }
+
+        if value < -self.min_indexed_value {
+            let min_index = self
+                .index_mapping
+                .index(f64::min(-value, self.max_indexed_value));
+            return Some(
+                self.negative_value_store
+                    .get_count_in_range(min_index, i32::MAX),
+            );
+        }
+
+        let mut rank = self.negative_value_store.get_total_count() + self.zero_count;
+        if value > self.min_indexed_value {
+            let max_index = self
+                .index_mapping
+                .index(f64::min(value, self.max_indexed_value));
+            rank += self
+                .positive_value_store
+                .get_count_in_range(i32::MIN, max_index);
+        }
+        Some(rank)
+    }
+
+    /// Returns the fraction of the values that are less than or equal to `value`, with the same
+    /// error bounds as [`DDSketch::get_rank`], or `None` if the sketch is empty or `value` is NaN.
+    pub fn get_cdf(&self, value: f64) -> Option<f64> {
+        let count = self.get_count();
+        if count <= 0.0 {
+            return None;
+        }
+        Some(self.get_rank(value)? / count)
+    }
+
+    /// Returns the number of values between `lower` and `upper`, both included, or `None` if a
+    /// bound is NaN or `lower` is greater than `upper`. Bounds can be infinite to leave the range
+    /// open-ended.
+    ///
+    /// As with [`DDSketch::get_rank`], all the values of the bins of the bounds are counted, so
+    /// the result is at least the exact count, and at most the exact count of a range whose bounds
+    /// are widened by a factor `(1 + a) / (1 - a)`, `a` being the relative accuracy of the index
+    /// mapping.
+    pub fn get_count_between(&self, lower: f64, upper: f64) -> Option<f64> {
+        if lower.is_nan() || upper.is_nan() || lower > upper {
+            return None;
+        }
+
+        let mut count = 0.0;
+        if lower < -self.min_indexed_value {
+            let min_index = if upper < -self.min_indexed_value {
+                self.index_mapping
+                    .index(f64::min(-upper, self.max_indexed_value))
+            } else {
+                i32::MIN
+            };
+            let max_index = self
+                .index_mapping
+                .index(f64::min(-lower, self.max_indexed_value));
+            count += self
+                .negative_value_store
+                .get_count_in_range(min_index, max_index);
+        }
+
+        if lower <= self.min_indexed_value && upper >= -self.min_indexed_value {
+            count += self.zero_count;
+        }
+
+        if upper > self.min_indexed_value {
+            let min_index = if lower > self.min_indexed_value {
+                self.index_mapping
+                    .index(f64::min(lower, self.max_indexed_value))
+            } else {
+                i32::MIN
+            };
+            let max_index = self
+                .index_mapping
+                .index(f64::min(upper, self.max_indexed_value));
+            count += self
+                .positive_value_store
+                .get_count_in_range(min_index, max_index);
+        }
+        Some(count)
+    }
+
+    /// Returns the values at the given quantiles, in the order they are requested, walking the
+    /// bins only once. Each value is the one [`DDSketch::get_value_at_quantile`] would return.
+    /// Fails with [`Error::InvalidQuantile`] if a quantile is NaN or not between 0 and 1, and
+    /// with [`Error::EmptySketch`] if quantiles are requested from an empty sketch.
+    pub fn get_values_at_quantiles(&self, quantiles: &[f64]) -> Result<Vec<f64>, Error> {
+        if let Some((position, &quantile)) = quantiles
+            .iter()
+            .enumerate()
+            .find(|(_, quantile)| !(0.0..=1.0).contains(*quantile))
+        {
+            return Err(Error::InvalidQuantile { quantile, position });
+        }
+
+        let mut values = vec![0.0; quantiles.len()];
+        if quantiles.is_empty() {
+            return Ok(values);
+        }
+
+        let count = self.get_count();
+        if count <= 0.0 {
+            return Err(Error::EmptySketch);
+        }
+
+        let mut order: Vec<usize> = (0..quantiles.len()).collect();
+        order.sort_by(|a, b| quantiles[*a].total_cmp(&quantiles[*b]));
+        let mut pending = order.into_iter().peekable();
+
+        // Bins as (sign, index, count), from the lowest value to the highest one.
+        let bins = self
+            .negative_value_store
+            .get_descending_iter()
+            .map(|(index, count)| (-1.0, index, count))
+            .chain(std::iter::once((0.0, 0, self.zero_count)))
+            .chain(
+                self.positive_value_store
+                    .get_ascending_iter()
+                    .map(|(index, count)| (1.0, index, count)),
+            );
+
+        let mut n: f64 = 0.0;
+        let mut last_bin = (0.0, 0);
+        for (sign, index, bin_count) in bins {
+            if bin_count == 0.0 {
+                continue;
+            }
+            n += bin_count;
+            last_bin = (sign, index);
+            while let Some(&position) = pending.peek() {
+                if n <= quantiles[position] * (count - 1.0) {
+                    break;
                 }
-                FlagType::NegativeStore => {
-                    let mode = BinEncodingMode::of_flag(flag.get_marker())?;
-                    self.negative_value_store
-                        .decode_and_merge_with(&mut input, mode)?;
+                values[position] = self.signed_value(sign, index);
+                pending.next();
+            }
+            if pending.peek().is_none() {
+                return Ok(values);
+            }
+        }
+
+        // Rounding errors may leave the highest ranks past the accumulated count.
+        for position in pending {
+            values[position] = self.signed_value(last_bin.0, last_bin.1);
+        }
+        Ok(values)
+    }
+
+    fn signed_value(&self, sign: f64, index: i32) -> f64 {
+        if sign == 0.0 {
+            0.0
+        } else {
+            sign * self.index_mapping.value(index)
+        }
+    }
+
+    /// Merges the encoded sketch in `bytes` into this one. If it was encoded with a different index
+    /// mapping, its bins are re-binned as described in [`DDSketch::merge_with`].
+    pub fn decode_and_merge_with<B: AsRef<[u8]>>(&mut self, bytes: B) -> Result<(), Error> {
+        self.decode_and_merge_input(&mut Input::wrap(bytes.as_ref()), &DecodeOptions::default())
+    }
+
+    /// Merges the encoded sketch in `bytes` into this one, as [`DDSketch::decode_and_merge_with`]
+    /// does, if it passes the checks of `options`. Otherwise, the sketch is left unchanged.
+    pub fn decode_and_merge_with_options<B: AsRef<[u8]>>(
+        &mut self,
+        bytes: B,
+        options: &DecodeOptions,
+    ) -> Result<(), Error> {
+        self.decode_and_merge_input(&mut Input::wrap(bytes.as_ref()), options)
+    }
+
+    /// Merges the encoded sketch read from `reader` into this one, as
+    /// [`DDSketch::decode_and_merge_with`] does. `reader` is read until it is exhausted.
+    pub fn decode_and_merge_from<R: Read>(&mut self, mut reader: R) -> Result<(), Error> {
+        self.decode_and_merge_input(
+            &mut Input::from_reader(&mut reader),
+            &DecodeOptions::default(),
+        )
+    }
+
+    fn decode_and_merge_input(
+        &mut self,
+        input: &mut Input,
+        options: &DecodeOptions,
+    ) -> Result<(), Error> {
+        let target = DecodeTarget {
+            index_mapping: &self.index_mapping,
+            negative_value_store: &self.negative_value_store,
+            positive_value_store: &self.positive_value_store,
+        };
+        let decoded = DecodedSketch::decode(input, options, Some(target))?;
+        let DecodedSketch {
+            index_mapping: decoded_index_mapping,
+            negative_value_store,
+            positive_value_store,
+            zero_count,
+            summary_statistics: decoded_summary_statistics,
+            has_values: has_decoded_values,
+            ..
+        } = decoded;
+
+        if let Some(summary_statistics) = self.summary_statistics.as_mut() {
+            match decoded_summary_statistics {
+                Some(decoded) => summary_statistics.merge_with(&decoded),
+                None if has_decoded_values => {
+                    return Err(Error::InvalidArgument("Missing exact summary statistics."));
                 }
-                FlagType::IndexMapping => {
-                    let layout = IndexMappingLayout::of_flag(&flag)?;
-                    let gamma = input.read_double_le()?;
-                    let index_offset = input.read_double_le()?;
-                    let decoded_index_mapping =
-                        IndexMapping::with_gamma_offset(layout, gamma, index_offset)?;
-                    if self.index_mapping != decoded_index_mapping {
-                        return Err(Error::InvalidArgument("Unmatched IndexMapping"));
-                    }
+                None => {}
+            }
+        }
+        let negative_value_store = negative_value_store.unwrap_or_else(empty_decoded_store);
+        let positive_value_store = positive_value_store.unwrap_or_else(empty_decoded_store);
+        match decoded_index_mapping {
+            Some(index_mapping) if index_mapping != self.index_mapping => {
+                self.merge_remapped_bins(
+                    &index_mapping,
+                    &*negative_value_store,
+                    &*positive_value_store,
+                );
+            }
+            _ => {
+                self.negative_value_store
+                    .merge_with(negative_value_store.get_descending_stream());
+                self.positive_value_store
+                    .merge_with(positive_value_store.get_descending_stream());
+            }
+        }
+        self.zero_count += zero_count;
+        Ok(())
+    }
+
+    /// Merges the values of `other` into this sketch.
+    ///
+    /// If `other` has a different index mapping, each of its bins is re-binned into the index
+    /// mapping of this sketch, its count being split between the bins it overlaps in proportion to
+    /// the length of the overlap on a logarithmic scale. The merge is then lossy: values merged
+    /// from `other` are only guaranteed to be within a relative error of about
+    /// `2 * α_other + α_self` rather than `α_self`, where `α` is the relative accuracy of a
+    /// mapping. Use the coarser of the two mappings as the target to keep the error closest to
+    /// the original accuracies.
+    pub fn merge_with<OP: Store, ON: Store>(
+        &mut self,
+        other: &DDSketch<OP, ON>,
+    ) -> Result<(), Error> {
+        if let Some(summary_statistics) = self.summary_statistics.as_mut() {
+            match other.summary_statistics.as_ref() {
+                Some(other_summary_statistics) => {
+                    summary_statistics.merge_with(other_summary_statistics)
                 }
-                FlagType::SketchFeatures => {
-                    if Flag::ZERO_COUNT == flag {
-                        self.zero_count += serde::decode_var_double(&mut input)?;
-                    } else {
-                        serde::ignore_exact_summary_statistic_flags(&mut input, flag)?;
-                    }
+                None if !other.is_empty() => {
+                    return Err(Error::InvalidArgument("Missing exact summary statistics."));
                 }
+                None => {}
             }
         }
+        if self.index_mapping == other.index_mapping {
+            self.negative_value_store
+                .merge_with(other.negative_value_store.get_descending_stream());
+            self.positive_value_store
+                .merge_with(other.positive_value_store.get_descending_stream());
+        } else {
+            self.merge_remapped_bins(
+                &other.index_mapping,
+                &other.negative_value_store,
+                &other.positive_value_store,
+            );
+        }
+        self.zero_count += other.zero_count;
         Ok(())
     }
 
-    pub fn merge_with(&mut self, other: &DDSketch) -> Result<(), Error> {
+    /// Returns a copy of this sketch that uses `index_mapping`, with the same stores and summary
+    /// statistics. The bins are re-binned as described in [`DDSketch::merge_with`], with the same
+    /// accuracy guarantee. The range of indexed values is kept, clamped to the values that
+    /// `index_mapping` can index.
+    pub fn convert_to_mapping(&self, index_mapping: &IndexMapping) -> Self
+    where
+        P: Clone,
+        N: Clone,
+    {
+        let mut converted = self.clone();
+        if *index_mapping == self.index_mapping {
+            return converted;
+        }
+        let min_indexable_value = f64::max(0.0, index_mapping.min_indexable_value());
+        let max_indexable_value = index_mapping.max_indexable_value();
+        let min_indexed_value = self.min_indexed_value.max(min_indexable_value);
+        let max_indexed_value = self.max_indexed_value.min(max_indexable_value);
+        converted.index_mapping = index_mapping.clone();
+        if min_indexed_value < max_indexed_value {
+            converted.min_indexed_value = min_indexed_value;
+            converted.max_indexed_value = max_indexed_value;
+        } else {
+            converted.min_indexed_value = min_indexable_value;
+            converted.max_indexed_value = max_indexable_value;
+        }
+        converted.negative_value_store.clear();
+        converted.positive_value_store.clear();
+        converted.merge_remapped_bins(
+            &self.index_mapping,
+            &self.negative_value_store,
+            &self.positive_value_store,
+        );
+        converted
+    }
+
+    pub fn get_index_mapping(&self) -> &IndexMapping {
+        &self.index_mapping
+    }
+
+    fn merge_remapped_bins(
+        &mut self,
+        index_mapping: &IndexMapping,
+        negative_value_store: &dyn Store,
+        positive_value_store: &dyn Store,
+    ) {
+        let (negative_bins, negative_zero_count) =
+            self.remap_bins(negative_value_store, index_mapping);
+        let (positive_bins, positive_zero_count) =
+            self.remap_bins(positive_value_store, index_mapping);
+        self.negative_value_store.merge_with(negative_bins);
+        self.positive_value_store.merge_with(positive_bins);
+        self.zero_count += negative_zero_count + positive_zero_count;
+    }
+
+    /// Splits the bins of `store`, indexed with `index_mapping`, into the bins of this sketch.
+    fn remap_bins(
+        &self,
+        store: &dyn Store,
+        index_mapping: &IndexMapping,
+    ) -> (Vec<(i32, f64)>, f64) {
+        let mut bins = Vec::new();
+        let mut zero_count = 0.0;
+        for (index, count) in store.get_ascending_iter() {
+            let value = index_mapping.value(index);
+            if value <= self.min_indexed_value {
+                zero_count += count;
+                continue;
+            }
+            if value > self.max_indexed_value {
+                bins.push((self.index_mapping.index(self.max_indexed_value), count));
+                continue;
+            }
+
+            let lower = index_mapping.lower_bound(index).max(self.min_indexed_value);
+            let upper = index_mapping.upper_bound(index).min(self.max_indexed_value);
+            let first_index = self.index_mapping.index(lower);
+            let last_index = self.index_mapping.index(upper);
+            let start = bins.len();
+            let mut total_overlap = 0.0;
+            for target_index in first_index..=last_index {
+                let overlap = (upper.min(self.index_mapping.upper_bound(target_index))
+                    / lower.max(self.index_mapping.lower_bound(target_index)))
+                .ln();
+                if overlap > 0.0 {
+                    bins.push((target_index, overlap));
+                    total_overlap += overlap;
+                }
+            }
+            if total_overlap > 0.0 {
+                for bin in &mut bins[start..] {
+                    bin.1 *= count / total_overlap;
+                }
+            } else {
+                bins.truncate(start);
+                bins.push((self.index_mapping.index(value), count));
+            }
+        }
+        (bins, zero_count)
+    }
+
+    /// Removes the values of `other` from this sketch, for instance to get the values recorded
+    /// between two snapshots of a cumulative sketch. Both sketches must have the same index
+    /// mapping. `policy` decides what happens to bins that would end up with a negative count;
+    /// with [`SubtractionPolicy::Error`], the sketch is left unchanged.
+    ///
+    /// If this sketch tracks exact summary statistics, so must `other` unless it is empty. The
+    /// exact count and sum are subtracted, but the exact minimum and maximum of the remaining
+    /// values cannot be recovered: [`DDSketch::get_min`] and [`DDSketch::get_max`] fall back to
+    /// the estimates from the bins until the sketch is cleared.
+    pub fn subtract<OP: Store, ON: Store>(
+        &mut self,
+        other: &DDSketch<OP, ON>,
+        policy: SubtractionPolicy,
+    ) -> Result<(), Error> {
         if self.index_mapping != other.index_mapping {
-            return Err(Error::InvalidArgument("Unmatched indexMapping."));
+            return Err(Error::MappingMismatch {
+                expected: self.index_mapping.clone(),
+                actual: other.index_mapping.clone(),
+            });
         }
-        self.negative_value_store
-            .merge_with(other.negative_value_store.get_descending_stream());
-        self.positive_value_store
-            .merge_with(other.positive_value_store.get_descending_stream());
-        self.zero_count += other.zero_count;
+        let mut summary_statistics = self.summary_statistics;
+        if let Some(summary_statistics) = summary_statistics.as_mut() {
+            match other.summary_statistics.as_ref() {
+                Some(other_summary_statistics) => {
+                    subtract_count(
+                        summary_statistics.get_count(),
+                        other_summary_statistics.get_count(),
+                        policy,
+                    )?;
+                    summary_statistics.subtract(other_summary_statistics);
+                }
+                None if !other.is_empty() => {
+                    return Err(Error::InvalidArgument("Missing exact summary statistics."));
+                }
+                None => {}
+            }
+        }
+
+        let negative_bins = subtract_bins(
+            &self.negative_value_store,
+            &other.negative_value_store,
+            policy,
+        )?;
+        let positive_bins = subtract_bins(
+            &self.positive_value_store,
+            &other.positive_value_store,
+            policy,
+        )?;
+        let zero_count = subtract_count(self.zero_count, other.zero_count, policy)?;
+
+        self.negative_value_store.clear();
+        self.negative_value_store.merge_with(negative_bins);
+        self.positive_value_store.clear();
+        self.positive_value_store.merge_with(positive_bins);
+        self.zero_count = zero_count;
+        self.summary_statistics = summary_statistics;
         Ok(())
     }
 
+    /// Multiplies the count of every value by `factor`, for instance to account for sampling
+    /// before merging with unsampled sketches. A factor of zero empties the sketch.
+    pub fn scale_counts(&mut self, factor: f64) -> Result<(), Error> {
+        if !factor.is_finite() {
+            return Err(Error::InvalidArgument("The factor must be finite."));
+        }
+        if factor < 0.0 {
+            return Err(Error::InvalidArgument("The factor cannot be negative."));
+        }
+        self.negative_value_store.scale(factor);
+        self.positive_value_store.scale(factor);
+        self.zero_count *= factor;
+        if let Some(summary_statistics) = self.summary_statistics.as_mut() {
+            summary_statistics.scale(factor);
+        }
+        Ok(())
+    }
+
+    /// Returns a sketch of the values of this one multiplied by `multiplier`, for instance to
+    /// convert nanoseconds to milliseconds, with the same index mapping and stores.
+    ///
+    /// If the multiplication shifts all the indexes by the same number of bins, the bins are
+    /// moved exactly. Otherwise, each bin is moved to the bin of its rescaled value, so that
+    /// the relative error of the rescaled sketch is up to about twice the relative accuracy of
+    /// the mapping. Values that no longer fit in the indexable range are counted in the zero
+    /// bucket or in the highest bin.
+    pub fn rescale_values(&self, multiplier: f64) -> Result<Self, Error>
+    where
+        P: Clone,
+        N: Clone,
+    {
+        if !multiplier.is_finite() || multiplier <= 0.0 {
+            return Err(Error::InvalidArgument(
+                "The multiplier must be positive and finite.",
+            ));
+        }
+
+        let index_shift = self.index_mapping.index_shift(multiplier);
+        let (negative_bins, negative_zero_count) =
+            self.rescale_bins(&self.negative_value_store, multiplier, index_shift);
+        let (positive_bins, positive_zero_count) =
+            self.rescale_bins(&self.positive_value_store, multiplier, index_shift);
+
+        let mut rescaled = self.clone();
+        rescaled.negative_value_store.clear();
+        rescaled.negative_value_store.merge_with(negative_bins);
+        rescaled.positive_value_store.clear();
+        rescaled.positive_value_store.merge_with(positive_bins);
+        rescaled.zero_count += negative_zero_count + positive_zero_count;
+        if let Some(summary_statistics) = rescaled.summary_statistics.as_mut() {
+            summary_statistics.rescale_values(multiplier);
+        }
+        Ok(rescaled)
+    }
+
+    fn rescale_bins(
+        &self,
+        store: &dyn Store,
+        multiplier: f64,
+        index_shift: Option<i32>,
+    ) -> (Vec<(i32, f64)>, f64) {
+        let mut bins = Vec::new();
+        let mut zero_count = 0.0;
+        for (index, count) in store.get_ascending_iter() {
+            let value = self.index_mapping.value(index) * multiplier;
+            if value <= self.min_indexed_value {
+                zero_count += count;
+            } else if value > self.max_indexed_value {
+                bins.push((self.index_mapping.index(self.max_indexed_value), count));
+            } else if let Some(index_shift) = index_shift {
+                bins.push((index.saturating_add(index_shift), count));
+            } else {
+                bins.push((self.index_mapping.index(value), count));
+            }
+        }
+        (bins, zero_count)
+    }
+
     pub fn encode(&self) -> Result<Vec<u8>, Error> {
-        let mut output = Output::with_capacity(64);
-        self.index_mapping.encode(&mut output)?;
+        let mut output = Output::with_capacity(self.encoded_len());
+        self.encode_output(&mut output)?;
+        Ok(output.trim())
+    }
 
+    /// The exact number of bytes [`DDSketch::encode`] returns.
+    pub fn encoded_len(&self) -> usize {
+        let mut len = IndexMapping::ENCODED_LEN;
+        if let Some(summary_statistics) = self.summary_statistics.as_ref() {
+            len += summary_statistics.encoded_len();
+        }
         if self.zero_count != 0.0 {
-            Flag::ZERO_COUNT.encode(&mut output)?;
-            serde::encode_var_double(&mut output, self.zero_count)?;
+            len += 1 + serde::var_double_encoded_length(self.zero_count) as usize;
+        }
+        len + self.positive_value_store.encoded_len() + self.negative_value_store.encoded_len()
+    }
+
+    /// Encodes the sketch at the start of `bytes`, and returns the number of bytes written, which
+    /// is [`DDSketch::encoded_len`]. Fails with [`Error::BufferTooSmall`] if `bytes` is too
+    /// small, leaving it untouched.
+    pub fn encode_into(&self, bytes: &mut [u8]) -> Result<usize, Error> {
+        let needed = self.encoded_len();
+        if bytes.len() < needed {
+            return Err(Error::BufferTooSmall {
+                needed,
+                available: bytes.len(),
+            });
+        }
+        let mut output = Output::wrap(bytes);
+        self.encode_output(&mut output)?;
+        Ok(output.len())
+    }
+
+    /// Writes the encoding of the sketch, as returned by [`DDSketch::encode`], to `writer`.
+    pub fn encode_to<W: Write>(&self, mut writer: W) -> Result<(), Error> {
+        let mut output = Output::to_writer(&mut writer);
+        self.encode_output(&mut output)?;
+        output.flush()
+    }
+
+    fn encode_output(&self, output: &mut Output) -> Result<(), Error> {
+        self.index_mapping.encode(output)?;
+
+        if let Some(summary_statistics) = self.summary_statistics.as_ref() {
+            summary_statistics.encode(output)?;
+        }
+
+        if self.zero_count != 0.0 {
+            Flag::ZERO_COUNT.encode(output)?;
+            serde::encode_var_double(output, self.zero_count)?;
         }
 
         self.positive_value_store
-            .encode(&mut output, FlagType::PositiveStore)?;
+            .encode(output, FlagType::PositiveStore)?;
         self.negative_value_store
-            .encode(&mut output, FlagType::NegativeStore)?;
+            .encode(output, FlagType::NegativeStore)?;
 
-        Ok(output.trim())
+        Ok(())
     }
+}
 
-    pub fn decode(bytes: &Vec<u8>) -> Result<DDSketch, Error> {
-        let mut input = Input::wrap(bytes);
-        let mut positive_value_store = UnboundedSizeDenseStore::new();
-        let mut negative_value_store = UnboundedSizeDenseStore::new();
-        let mut index_mapping = None;
-        let mut zero_count = 0.0;
-        while input.has_remaining() {
-            let flag = Flag::decode(&mut input)?;
-            let flag_type = flag.get_type()?;
-            match flag_type {
-                FlagType::PositiveStore => {
-                    let mode = BinEncodingMode::of_flag(flag.get_marker())?;
-                    positive_value_store.decode_and_merge_with(&mut input, mode)?;
-                }
-                FlagType::NegativeStore => {
-                    let mode = BinEncodingMode::of_flag(flag.get_marker())?;
-                    negative_value_store.decode_and_merge_with(&mut input, mode)?;
-                }
-                FlagType::IndexMapping => {
-                    let layout = IndexMappingLayout::of_flag(&flag)?;
-                    let gamma = input.read_double_le()?;
-                    let index_offset = input.read_double_le()?;
-                    index_mapping = Some(IndexMapping::with_gamma_offset(
-                        layout,
-                        gamma,
-                        index_offset,
-                    )?);
-                }
-                FlagType::SketchFeatures => {
-                    if Flag::ZERO_COUNT == flag {
-                        zero_count += serde::decode_var_double(&mut input)?;
-                    } else {
-                        serde::ignore_exact_summary_statistic_flags(&mut input, flag)?;
-                    }
-                }
-            }
+impl DDSketch {
+    /// Decodes a sketch from `bytes`, which may be anything that derefs to a byte slice, such as
+    /// `Vec<u8>`, `&[u8]` or `bytes::Bytes`. The bytes are borrowed, not copied.
+    pub fn decode<B: AsRef<[u8]>>(bytes: B) -> Result<DDSketch, Error> {
+        Self::decode_input(&mut Input::wrap(bytes.as_ref()), &DecodeOptions::default())
+    }
+
+    /// Decodes a sketch from `bytes`, as [`DDSketch::decode`] does, rejecting inputs that do not
+    /// pass the checks of `options`.
+    pub fn decode_with_options<B: AsRef<[u8]>>(
+        bytes: B,
+        options: &DecodeOptions,
+    ) -> Result<DDSketch, Error> {
+        Self::decode_input(&mut Input::wrap(bytes.as_ref()), options)
+    }
+
+    /// Decodes a sketch read from `reader`, as [`DDSketch::decode`] does. `reader` is read until
+    /// it is exhausted.
+    pub fn decode_from<R: Read>(mut reader: R) -> Result<DDSketch, Error> {
+        Self::decode_input(
+            &mut Input::from_reader(&mut reader),
+            &DecodeOptions::default(),
+        )
+    }
+
+    /// Decodes a sketch read from `reader`, as [`DDSketch::decode_from`] does, rejecting inputs
+    /// that do not pass the checks of `options`.
+    pub fn decode_from_with_options<R: Read>(
+        mut reader: R,
+        options: &DecodeOptions,
+    ) -> Result<DDSketch, Error> {
+        Self::decode_input(&mut Input::from_reader(&mut reader), options)
+    }
+
+    fn decode_input(input: &mut Input, options: &DecodeOptions) -> Result<DDSketch, Error> {
+        DecodedSketch::decode(input, options, None)?.into_sketch()
+    }
+}
+
+/// The sketch that decoded bins are merged into.
+#[derive(Clone, Copy)]
+struct DecodeTarget<'a> {
+    index_mapping: &'a IndexMapping,
+    negative_value_store: &'a dyn Store,
+    positive_value_store: &'a dyn Store,
+}
+
+/// The content of an encoded sketch, before it is turned into a sketch or merged into one.
+///
+/// Each store is created when its first bins are decoded. If the index mapping decoded so far is
+/// the one of the target, the bins are decoded into an empty clone of the target's store, so that
+/// merging them does not go through a store that may grow larger than the target. Otherwise, they
+/// will be re-binned, and are decoded into a temporary [`UnboundedSizeDenseStore`].
+struct DecodedSketch<'a> {
+    target: Option<DecodeTarget<'a>>,
+    index_mapping: Option<IndexMapping>,
+    negative_value_store: Option<Box<dyn Store>>,
+    positive_value_store: Option<Box<dyn Store>>,
+    zero_count: f64,
+    summary_statistics: Option<SummaryStatistics>,
+    has_values: bool,
+}
+
+fn empty_decoded_store() -> Box<dyn Store> {
+    Box::new(UnboundedSizeDenseStore::new())
+}
+
+impl<'a> DecodedSketch<'a> {
+    fn new(target: Option<DecodeTarget<'a>>) -> Self {
+        DecodedSketch {
+            target,
+            index_mapping: None,
+            negative_value_store: None,
+            positive_value_store: None,
+            zero_count: 0.0,
+            summary_statistics: None,
+            has_values: false,
         }
+    }
 
-        match index_mapping {
+    /// Returns the store that the bins of `store_flag_type` are decoded into.
+    fn store_mut(&mut self, store_flag_type: FlagType) -> &mut dyn Store {
+        let target_store = self
+            .target
+            .filter(|target| self.index_mapping.as_ref() == Some(target.index_mapping))
+            .map(|target| match store_flag_type {
+                FlagType::NegativeStore => target.negative_value_store,
+                _ => target.positive_value_store,
+            });
+        let store = match store_flag_type {
+            FlagType::NegativeStore => &mut self.negative_value_store,
+            _ => &mut self.positive_value_store,
+        };
+        &mut **store.get_or_insert_with(|| match target_store {
+            Some(target_store) => {
+                let mut store = target_store.clone_box();
+                store.clear();
+                store
+            }
+            None => empty_decoded_store(),
+        })
+    }
+
+    fn into_sketch(self) -> Result<DDSketch, Error> {
+        match self.index_mapping {
             Some(mapping) => {
                 let min_indexed_value = f64::max(0.0, mapping.min_indexable_value());
                 let max_indexed_value = mapping.max_indexable_value();
                 Ok(DDSketch {
                     index_mapping: mapping,
-                    negative_value_store: Box::new(negative_value_store),
-                    positive_value_store: Box::new(positive_value_store),
+                    negative_value_store: self
+                        .negative_value_store
+                        .unwrap_or_else(empty_decoded_store),
+                    positive_value_store: self
+                        .positive_value_store
+                        .unwrap_or_else(empty_decoded_store),
                     min_indexed_value,
                     max_indexed_value,
-                    zero_count,
+                    zero_count: self.zero_count,
+                    summary_statistics: self.summary_statistics,
                 })
             }
             None => Err(Error::InvalidArgument("No IndexMapping decoded")),
         }
     }
+
+    /// Decodes `input` section by section, a section being a flag and what follows it. Errors are
+    /// reported with the position of the section they occurred in.
+    fn decode(
+        input: &mut Input,
+        options: &DecodeOptions,
+        target: Option<DecodeTarget<'a>>,
+    ) -> Result<DecodedSketch<'a>, Error> {
+        if let Some(max_len) = options.get_max_len() {
+            input.limit_len(max_len)?;
+        }
+        let mut decoded = DecodedSketch::new(target);
+        while input.has_remaining()? {
+            let position = input.position();
+            decoded
+                .decode_section(input, options)
+                .map_err(|error| Error::decoding_at(position, error))?;
+        }
+        Ok(decoded)
+    }
+
+    fn decode_section(&mut self, input: &mut Input, options: &DecodeOptions) -> Result<(), Error> {
+        let flag = Flag::decode(input)?;
+        match flag.get_type()? {
+            store_flag_type @ (FlagType::PositiveStore | FlagType::NegativeStore) => {
+                let mode = BinEncodingMode::of_flag(flag.get_marker())?;
+                self.store_mut(store_flag_type)
+                    .decode_and_merge_with(input, mode, options)?;
+                self.has_values = true;
+            }
+            FlagType::IndexMapping => {
+                if self.index_mapping.is_some() && options.rejects_duplicate_index_mappings() {
+                    return Err(Error::DuplicateSection {
+                        section: "index mapping",
+                    });
+                }
+                let layout = IndexMappingLayout::of_flag(&flag)?;
+                let gamma = input.read_double_le()?;
+                let index_offset = input.read_double_le()?;
+                self.index_mapping = Some(IndexMapping::with_gamma_offset(
+                    layout,
+                    gamma,
+                    index_offset,
+                )?);
+            }
+            FlagType::SketchFeatures => {
+                if Flag::ZERO_COUNT == flag {
+                    let zero_count = serde::decode_var_double(input)?;
+                    options.check_count(zero_count)?;
+                    self.zero_count += zero_count;
+                    self.has_values |= self.zero_count != 0.0;
+                } else {
+                    self.summary_statistics
+                        .get_or_insert_with(SummaryStatistics::new)
+                        .decode_flag(input, flag)?;
+                }
+            }
+        }
+        Ok(())
+    }
 }
 
-// factory methods
+#[cfg(feature = "protobuf")]
+impl<P: Store, N: Store> DDSketch<P, N> {
+    /// Encodes the sketch as a `DDSketch` message of DataDog's `ddsketch.proto`. The exact summary
+    /// statistics are not part of that message and are dropped.
+    pub fn to_protobuf(&self) -> Result<Vec<u8>, Error> {
+        let mut output = Output::with_capacity(64);
+        protobuf::encode_message(&mut output, protobuf::SKETCH_MAPPING, |output| {
+            protobuf::encode_index_mapping(output, &self.index_mapping)
+        })?;
+        protobuf::encode_message(&mut output, protobuf::SKETCH_POSITIVE_VALUES, |output| {
+            protobuf::encode_store(output, &self.positive_value_store)
+        })?;
+        protobuf::encode_message(&mut output, protobuf::SKETCH_NEGATIVE_VALUES, |output| {
+            protobuf::encode_store(output, &self.negative_value_store)
+        })?;
+        if self.zero_count != 0.0 {
+            protobuf::encode_double_field(
+                &mut output,
+                protobuf::SKETCH_ZERO_COUNT,
+                self.zero_count,
+            )?;
+        }
+        Ok(output.trim())
+    }
+}
+
+#[cfg(feature = "protobuf")]
 impl DDSketch {
-    pub fn collapsing_lowest_dense(
-        relative_accuracy: f64,
-        max_num_bins: usize,
+    /// Decodes a `DDSketch` message of DataDog's `ddsketch.proto`.
+    pub fn from_protobuf<B: AsRef<[u8]>>(bytes: B) -> Result<DDSketch, Error> {
+        DDSketch::from_protobuf_with_options(bytes, &DecodeOptions::default())
+    }
+
+    /// Decodes a `DDSketch` message of DataDog's `ddsketch.proto`, applying the limits and checks
+    /// of `options`. Errors are reported with the position of the field they occurred in.
+    pub fn from_protobuf_with_options<B: AsRef<[u8]>>(
+        bytes: B,
+        options: &DecodeOptions,
     ) -> Result<DDSketch, Error> {
-        let index_mapping = IndexMapping::with_relative_accuracy(LogCubic, relative_accuracy)?;
-        let negative_value_store = CollapsingLowestDenseStore::with_capacity(max_num_bins)?;
-        let positive_value_store = CollapsingLowestDenseStore::with_capacity(max_num_bins)?;
+        let mut input = Input::wrap(bytes.as_ref());
+        if let Some(max_len) = options.get_max_len() {
+            input.limit_len(max_len)?;
+        }
+        let mut decoded = DecodedSketch::new(None);
+        while input.has_remaining()? {
+            let position = input.position();
+            decoded
+                .decode_protobuf_field(&mut input, options)
+                .map_err(|error| Error::decoding_at(position, error))?;
+        }
+        decoded.into_sketch()
+    }
+}
+
+#[cfg(feature = "protobuf")]
+impl DecodedSketch<'_> {
+    fn decode_protobuf_field(
+        &mut self,
+        input: &mut Input,
+        options: &DecodeOptions,
+    ) -> Result<(), Error> {
+        let (field_number, wire_type) = protobuf::decode_tag(input)?;
+        match (field_number, wire_type) {
+            (protobuf::SKETCH_MAPPING, protobuf::WIRE_TYPE_LEN) => {
+                if self.index_mapping.is_some() && options.rejects_duplicate_index_mappings() {
+                    return Err(Error::DuplicateSection {
+                        section: "index mapping",
+                    });
+                }
+                let mut message = protobuf::decode_len_delimited(input)?;
+                self.index_mapping = Some(protobuf::decode_index_mapping(&mut message)?);
+            }
+            (protobuf::SKETCH_POSITIVE_VALUES, protobuf::WIRE_TYPE_LEN) => {
+                let mut message = protobuf::decode_len_delimited(input)?;
+                protobuf::decode_store_and_merge_with(
+                    &mut message,
+                    self.store_mut(FlagType::PositiveStore),
+                    options,
+                )?;
+            }
+            (protobuf::SKETCH_NEGATIVE_VALUES, protobuf::WIRE_TYPE_LEN) => {
+                let mut message = protobuf::decode_len_delimited(input)?;
+                protobuf::decode_store_and_merge_with(
+                    &mut message,
+                    self.store_mut(FlagType::NegativeStore),
+                    options,
+                )?;
+            }
+            (protobuf::SKETCH_ZERO_COUNT, protobuf::WIRE_TYPE_I64) => {
+                let zero_count = input.read_double_le()?;
+                options.check_count(zero_count)?;
+                self.zero_count = zero_count;
+            }
+            _ => protobuf::skip_field(input, wire_type)?,
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "bytes")]
+impl<P: Store, N: Store> DDSketch<P, N> {
+    /// Merges the encoded sketch in `buf` into this one, as [`DDSketch::decode_and_merge_with`]
+    /// does. Contiguous buffers, such as `Bytes` and `BytesMut`, are read in place; chained ones
+    /// are read chunk by chunk.
+    pub fn decode_and_merge_from_buf<B: bytes::Buf>(&mut self, buf: B) -> Result<(), Error> {
+        if buf.chunk().len() == buf.remaining() {
+            self.decode_and_merge_with(buf.chunk())
+        } else {
+            self.decode_and_merge_from(buf.reader())
+        }
+    }
+}
+
+#[cfg(feature = "bytes")]
+impl DDSketch {
+    /// Decodes a sketch from `buf`, as [`DDSketch::decode`] does, without first copying a chained
+    /// buffer into a contiguous one.
+    pub fn decode_from_buf<B: bytes::Buf>(buf: B) -> Result<DDSketch, Error> {
+        if buf.chunk().len() == buf.remaining() {
+            DDSketch::decode(buf.chunk())
+        } else {
+            DDSketch::decode_from(buf.reader())
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+#[derive(::serde::Serialize)]
+#[serde(rename = "DDSketch")]
+struct DDSketchRef<'a> {
+    mapping: &'a IndexMapping,
+    zero_count: f64,
+    positive_bins: crate::serialization::BinsRef<'a>,
+    negative_bins: crate::serialization::BinsRef<'a>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    summary_statistics: Option<&'a SummaryStatistics>,
+}
+
+/// Self-describing formats get the mapping parameters and the non-empty bins of both stores,
+/// other formats get the bytes of [`DDSketch::encode`].
+#[cfg(feature = "serde")]
+impl<P: Store, N: Store> ::serde::Serialize for DDSketch<P, N> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: ::serde::Serializer,
+    {
+        if serializer.is_human_readable() {
+            let fields = DDSketchRef {
+                mapping: &self.index_mapping,
+                zero_count: self.zero_count,
+                positive_bins: crate::serialization::BinsRef(&self.positive_value_store),
+                negative_bins: crate::serialization::BinsRef(&self.negative_value_store),
+                summary_statistics: self.summary_statistics.as_ref(),
+            };
+            ::serde::Serialize::serialize(&fields, serializer)
+        } else {
+            let bytes = self.encode().map_err(::serde::ser::Error::custom)?;
+            serializer.serialize_bytes(&bytes)
+        }
+    }
+}
+
+/// Like [`DDSketch::decode`], the deserialized sketch keeps its bins in unbounded dense stores.
+/// Invalid counts are rejected; use [`DDSketchSeed`] to apply other [`DecodeOptions`].
+#[cfg(feature = "serde")]
+impl<'de> ::serde::Deserialize<'de> for DDSketch {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: ::serde::Deserializer<'de>,
+    {
+        let options = crate::serialization::default_decode_options();
+        ::serde::de::DeserializeSeed::deserialize(DDSketchSeed::new(&options), deserializer)
+    }
+}
+
+/// Deserializes a [`DDSketch`] within the limits and checks of [`DecodeOptions`], as
+/// [`DDSketch::decode_with_options`] does for the encoded bytes.
+///
+/// ```
+/// use serde::de::DeserializeSeed;
+/// use sketches_rust::{DDSketch, DDSketchSeed, DecodeOptions};
+/// let mut sketch = DDSketch::unbounded_dense(0.01).unwrap();
+/// sketch.accept(1.0);
+/// sketch.accept(1e9);
+/// let json = serde_json::to_string(&sketch).unwrap();
+///
+/// let options = DecodeOptions::new().max_index_span(100);
+/// let mut deserializer = serde_json::Deserializer::from_str(&json);
+/// assert!(DDSketchSeed::new(&options).deserialize(&mut deserializer).is_err());
+/// ```
+#[cfg(feature = "serde")]
+#[derive(Clone, Copy, Debug)]
+pub struct DDSketchSeed<'a> {
+    options: &'a DecodeOptions,
+}
+
+#[cfg(feature = "serde")]
+impl<'a> DDSketchSeed<'a> {
+    pub fn new(options: &'a DecodeOptions) -> Self {
+        DDSketchSeed { options }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> ::serde::de::DeserializeSeed<'de> for DDSketchSeed<'_> {
+    type Value = DDSketch;
+
+    fn deserialize<D>(self, deserializer: D) -> Result<Self::Value, D::Error>
+    where
+        D: ::serde::Deserializer<'de>,
+    {
+        use ::serde::de::Error as _;
+        if !deserializer.is_human_readable() {
+            let bytes: crate::serialization::ByteBuf =
+                ::serde::Deserialize::deserialize(deserializer)?;
+            return DDSketch::decode_with_options(&bytes.0, self.options).map_err(D::Error::custom);
+        }
+        deserializer.deserialize_struct("DDSketch", DDSKETCH_FIELDS, self)
+    }
+}
+
+#[cfg(feature = "serde")]
+const DDSKETCH_FIELDS: &[&str] = &[
+    "mapping",
+    "zero_count",
+    "positive_bins",
+    "negative_bins",
+    "summary_statistics",
+];
+
+#[cfg(feature = "serde")]
+#[derive(::serde::Deserialize)]
+#[serde(field_identifier, rename_all = "snake_case")]
+enum DDSketchField {
+    Mapping,
+    ZeroCount,
+    PositiveBins,
+    NegativeBins,
+    SummaryStatistics,
+    #[serde(other)]
+    Other,
+}
+
+#[cfg(feature = "serde")]
+impl<'de> ::serde::de::Visitor<'de> for DDSketchSeed<'_> {
+    type Value = DDSketch;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("struct DDSketch")
+    }
+
+    fn visit_map<A>(self, mut map: A) -> Result<Self::Value, A::Error>
+    where
+        A: ::serde::de::MapAccess<'de>,
+    {
+        use ::serde::de::Error as _;
+        let mut index_mapping: Option<IndexMapping> = None;
+        let mut zero_count = None;
+        let mut positive_bins = None;
+        let mut negative_bins = None;
+        let mut summary_statistics: Option<Option<SummaryStatistics>> = None;
+        while let Some(field) = map.next_key()? {
+            match field {
+                DDSketchField::Mapping if index_mapping.is_some() => {
+                    return Err(A::Error::duplicate_field("mapping"));
+                }
+                DDSketchField::Mapping => index_mapping = Some(map.next_value()?),
+                DDSketchField::ZeroCount if zero_count.is_some() => {
+                    return Err(A::Error::duplicate_field("zero_count"));
+                }
+                DDSketchField::ZeroCount => {
+                    let count: f64 = map.next_value()?;
+                    self.options.check_count(count).map_err(A::Error::custom)?;
+                    zero_count = Some(count);
+                }
+                DDSketchField::PositiveBins if positive_bins.is_some() => {
+                    return Err(A::Error::duplicate_field("positive_bins"));
+                }
+                DDSketchField::PositiveBins => {
+                    let seed = crate::serialization::BinsSeed(self.options);
+                    positive_bins = Some(map.next_value_seed(seed)?.0);
+                }
+                DDSketchField::NegativeBins if negative_bins.is_some() => {
+                    return Err(A::Error::duplicate_field("negative_bins"));
+                }
+                DDSketchField::NegativeBins => {
+                    let seed = crate::serialization::BinsSeed(self.options);
+                    negative_bins = Some(map.next_value_seed(seed)?.0);
+                }
+                DDSketchField::SummaryStatistics if summary_statistics.is_some() => {
+                    return Err(A::Error::duplicate_field("summary_statistics"));
+                }
+                DDSketchField::SummaryStatistics => summary_statistics = Some(map.next_value()?),
+                DDSketchField::Other => {
+                    map.next_value::<::serde::de::IgnoredAny>()?;
+                }
+            }
+        }
+
+        let index_mapping = index_mapping.ok_or_else(|| A::Error::missing_field("mapping"))?;
+        let zero_count = zero_count.ok_or_else(|| A::Error::missing_field("zero_count"))?;
+        let positive_bins =
+            positive_bins.ok_or_else(|| A::Error::missing_field("positive_bins"))?;
+        let negative_bins =
+            negative_bins.ok_or_else(|| A::Error::missing_field("negative_bins"))?;
+        let mut positive_value_store = UnboundedSizeDenseStore::new();
+        for bin in positive_bins {
+            positive_value_store.add_bin(bin);
+        }
+        let mut negative_value_store = UnboundedSizeDenseStore::new();
+        for bin in negative_bins {
+            negative_value_store.add_bin(bin);
+        }
         let min_indexed_value = f64::max(0.0, index_mapping.min_indexable_value());
         let max_indexed_value = index_mapping.max_indexable_value();
-        let zero_count = 0.0;
-
         Ok(DDSketch {
             index_mapping,
             negative_value_store: Box::new(negative_value_store),
@@ -313,103 +1368,204 @@ impl DDSketch {
             min_indexed_value,
             max_indexed_value,
             zero_count,
+            summary_statistics: summary_statistics.flatten(),
         })
     }
+}
+
+/// What [`DDSketch::subtract`] does with counts that would become negative.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SubtractionPolicy {
+    /// Sets the count to zero.
+    Clamp,
+    /// Returns an error.
+    Error,
+}
+
+fn subtract_count(count: f64, other_count: f64, policy: SubtractionPolicy) -> Result<f64, Error> {
+    let difference = count - other_count;
+    if difference >= 0.0 {
+        return Ok(difference);
+    }
+    match policy {
+        SubtractionPolicy::Clamp => Ok(0.0),
+        SubtractionPolicy::Error => Err(Error::InvalidArgument(
+            "The subtraction results in a negative count.",
+        )),
+    }
+}
+
+fn subtract_bins(
+    store: &dyn Store,
+    other_store: &dyn Store,
+    policy: SubtractionPolicy,
+) -> Result<Vec<(i32, f64)>, Error> {
+    let mut bins = Vec::new();
+    let mut other_bins = other_store.get_ascending_iter().peekable();
+    for (index, count) in store.get_ascending_iter() {
+        let mut other_count = 0.0;
+        while let Some(&(other_index, other_bin_count)) = other_bins.peek() {
+            if other_index > index {
+                break;
+            }
+            if other_index == index {
+                other_count = other_bin_count;
+            } else {
+                subtract_count(0.0, other_bin_count, policy)?;
+            }
+            other_bins.next();
+        }
+        let difference = subtract_count(count, other_count, policy)?;
+        if difference > 0.0 {
+            bins.push((index, difference));
+        }
+    }
+    for (_, count) in other_bins {
+        subtract_count(0.0, count, policy)?;
+    }
+    Ok(bins)
+}
+
+impl<P: Store, N: Store> DDSketch<P, N> {
+    pub(crate) fn with_stores(
+        index_mapping: IndexMapping,
+        negative_value_store: N,
+        positive_value_store: P,
+        min_indexed_value: f64,
+        max_indexed_value: f64,
+    ) -> Self {
+        DDSketch {
+            index_mapping,
+            negative_value_store,
+            positive_value_store,
+            min_indexed_value,
+            max_indexed_value,
+            zero_count: 0.0,
+            summary_statistics: None,
+        }
+    }
+}
+
+// factory methods
+impl DDSketch {
+    pub fn builder() -> DDSketchBuilder {
+        DDSketchBuilder::new()
+    }
+
+    pub fn collapsing_lowest_dense(
+        relative_accuracy: f64,
+        max_num_bins: usize,
+    ) -> Result<DDSketch, Error> {
+        DDSketchBuilder::new()
+            .index_layout(LogCubic)
+            .relative_accuracy(relative_accuracy)
+            .stores(StoreKind::CollapsingLowestDense { max_num_bins })
+            .build()
+    }
 
     pub fn collapsing_highest_dense(
         relative_accuracy: f64,
         max_num_bins: usize,
     ) -> Result<DDSketch, Error> {
-        let index_mapping = IndexMapping::with_relative_accuracy(LogCubic, relative_accuracy)?;
-        let negative_value_store = CollapsingHighestDenseStore::with_capacity(max_num_bins)?;
-        let positive_value_store = CollapsingHighestDenseStore::with_capacity(max_num_bins)?;
-        let min_indexed_value = f64::max(0.0, index_mapping.min_indexable_value());
-        let max_indexed_value = index_mapping.max_indexable_value();
-        let zero_count = 0.0;
-        Ok(DDSketch {
-            index_mapping,
-            negative_value_store: Box::new(negative_value_store),
-            positive_value_store: Box::new(positive_value_store),
-            min_indexed_value,
-            max_indexed_value,
-            zero_count,
-        })
+        DDSketchBuilder::new()
+            .index_layout(LogCubic)
+            .relative_accuracy(relative_accuracy)
+            .stores(StoreKind::CollapsingHighestDense { max_num_bins })
+            .build()
     }
 
     pub fn unbounded_dense(relative_accuracy: f64) -> Result<DDSketch, Error> {
-        let index_mapping = IndexMapping::with_relative_accuracy(LogCubic, relative_accuracy)?;
-        let negative_value_store = UnboundedSizeDenseStore::new();
-        let positive_value_store = UnboundedSizeDenseStore::new();
-        let min_indexed_value = f64::max(0.0, index_mapping.min_indexable_value());
-        let max_indexed_value = index_mapping.max_indexable_value();
-        let zero_count = 0.0;
-        Ok(DDSketch {
-            index_mapping,
-            negative_value_store: Box::new(negative_value_store),
-            positive_value_store: Box::new(positive_value_store),
-            min_indexed_value,
-            max_indexed_value,
-            zero_count,
-        })
+        DDSketchBuilder::new()
+            .index_layout(LogCubic)
+            .relative_accuracy(relative_accuracy)
+            .stores(StoreKind::UnboundedDense)
+            .build()
+    }
+
+    pub fn paginated(relative_accuracy: f64) -> Result<DDSketch, Error> {
+        DDSketchBuilder::new()
+            .index_layout(LogCubic)
+            .relative_accuracy(relative_accuracy)
+            .stores(StoreKind::Paginated)
+            .build()
+    }
+
+    pub fn sparse(relative_accuracy: f64) -> Result<DDSketch, Error> {
+        DDSketchBuilder::new()
+            .index_layout(LogCubic)
+            .relative_accuracy(relative_accuracy)
+            .stores(StoreKind::Sparse)
+            .build()
+    }
+
+    pub fn collapsing_lowest_sparse(
+        relative_accuracy: f64,
+        max_num_bins: usize,
+    ) -> Result<DDSketch, Error> {
+        DDSketchBuilder::new()
+            .index_layout(LogCubic)
+            .relative_accuracy(relative_accuracy)
+            .stores(StoreKind::CollapsingLowestSparse { max_num_bins })
+            .build()
     }
 
     pub fn logarithmic_collapsing_lowest_dense(
         relative_accuracy: f64,
         max_num_bins: usize,
     ) -> Result<DDSketch, Error> {
-        let index_mapping = IndexMapping::with_relative_accuracy(LOG, relative_accuracy)?;
-        let negative_value_store = CollapsingLowestDenseStore::with_capacity(max_num_bins)?;
-        let positive_value_store = CollapsingLowestDenseStore::with_capacity(max_num_bins)?;
-        let min_indexed_value = f64::max(0.0, index_mapping.min_indexable_value());
-        let max_indexed_value = index_mapping.max_indexable_value();
-        let zero_count = 0.0;
-        Ok(DDSketch {
-            index_mapping,
-            negative_value_store: Box::new(negative_value_store),
-            positive_value_store: Box::new(positive_value_store),
-            min_indexed_value,
-            max_indexed_value,
-            zero_count,
-        })
+        DDSketchBuilder::new()
+            .index_layout(LOG)
+            .relative_accuracy(relative_accuracy)
+            .stores(StoreKind::CollapsingLowestDense { max_num_bins })
+            .build()
     }
 
     pub fn logarithmic_collapsing_highest_dense(
         relative_accuracy: f64,
         max_num_bins: usize,
     ) -> Result<DDSketch, Error> {
-        let index_mapping = IndexMapping::with_relative_accuracy(LOG, relative_accuracy)?;
-        let negative_value_store = CollapsingHighestDenseStore::with_capacity(max_num_bins)?;
-        let positive_value_store = CollapsingHighestDenseStore::with_capacity(max_num_bins)?;
-        let min_indexed_value = f64::max(0.0, index_mapping.min_indexable_value());
-        let max_indexed_value = index_mapping.max_indexable_value();
-        let zero_count = 0.0;
-        Ok(DDSketch {
-            index_mapping,
-            negative_value_store: Box::new(negative_value_store),
-            positive_value_store: Box::new(positive_value_store),
-            min_indexed_value,
-            max_indexed_value,
-            zero_count,
-        })
+        DDSketchBuilder::new()
+            .index_layout(LOG)
+            .relative_accuracy(relative_accuracy)
+            .stores(StoreKind::CollapsingHighestDense { max_num_bins })
+            .build()
     }
 
     pub fn logarithmic_unbounded_size_dense_store(
         relative_accuracy: f64,
     ) -> Result<DDSketch, Error> {
-        let index_mapping = IndexMapping::with_relative_accuracy(LOG, relative_accuracy)?;
-        let negative_value_store = UnboundedSizeDenseStore::new();
-        let positive_value_store = UnboundedSizeDenseStore::new();
-        let min_indexed_value = f64::max(0.0, index_mapping.min_indexable_value());
-        let max_indexed_value = index_mapping.max_indexable_value();
-        let zero_count = 0.0;
-        Ok(DDSketch {
-            index_mapping,
-            negative_value_store: Box::new(negative_value_store),
-            positive_value_store: Box::new(positive_value_store),
-            min_indexed_value,
-            max_indexed_value,
-            zero_count,
-        })
+        DDSketchBuilder::new()
+            .index_layout(LOG)
+            .relative_accuracy(relative_accuracy)
+            .stores(StoreKind::UnboundedDense)
+            .build()
+    }
+
+    pub fn logarithmic_paginated(relative_accuracy: f64) -> Result<DDSketch, Error> {
+        DDSketchBuilder::new()
+            .index_layout(LOG)
+            .relative_accuracy(relative_accuracy)
+            .stores(StoreKind::Paginated)
+            .build()
+    }
+
+    pub fn logarithmic_sparse(relative_accuracy: f64) -> Result<DDSketch, Error> {
+        DDSketchBuilder::new()
+            .index_layout(LOG)
+            .relative_accuracy(relative_accuracy)
+            .stores(StoreKind::Sparse)
+            .build()
+    }
+
+    pub fn logarithmic_collapsing_lowest_sparse(
+        relative_accuracy: f64,
+        max_num_bins: usize,
+    ) -> Result<DDSketch, Error> {
+        DDSketchBuilder::new()
+            .index_layout(LOG)
+            .relative_accuracy(relative_accuracy)
+            .stores(StoreKind::CollapsingLowestSparse { max_num_bins })
+            .build()
     }
 }
 
@@ -454,7 +1610,10 @@ impl FlagType {
             0b01 => Ok(FlagType::PositiveStore),
             0b10 => Ok(FlagType::IndexMapping),
             0b11 => Ok(FlagType::NegativeStore),
-            _ => Err(Error::InvalidArgument("Unknown FlagType.")),
+            _ => Err(Error::UnknownFlag {
+                marker: t,
+                section: "flag type",
+            }),
         }
     }
 }
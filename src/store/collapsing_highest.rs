@@ -1,6 +1,8 @@
 use super::*;
 use crate::serde;
 
+/// A dense store that keeps at most `capacity` bins, collapsing the highest ones into the
+/// highest remaining bin.
 #[derive(Clone)]
 pub struct CollapsingHighestDenseStore {
     counts: Vec<f64>,
@@ -66,7 +68,9 @@ impl CollapsingHighestDenseStore {
             self.min_index = new_min_index;
             self.max_index = new_max_index;
             self.adjust(new_min_index, new_max_index);
-        } else if new_min_index >= self.offset && new_max_index < self.offset + self.get_length() {
+        } else if new_min_index >= self.offset
+            && (new_max_index as i64) < self.offset as i64 + self.get_length() as i64
+        {
             self.min_index = new_min_index;
             self.max_index = new_max_index;
         } else {
@@ -81,7 +85,7 @@ impl CollapsingHighestDenseStore {
     }
 
     fn adjust(&mut self, new_min_index: i32, new_max_index: i32) {
-        if new_max_index - new_min_index + 1 > self.get_length() {
+        if new_max_index as i64 - new_min_index as i64 + 1 > self.get_length() as i64 {
             // The range of indices is too wide, buckets of lowest indices need to be collapsed.
 
             let new_max_index = new_min_index + self.get_length() - 1;
@@ -123,20 +127,24 @@ impl CollapsingHighestDenseStore {
     }
 
     fn get_new_length(&self, new_min_index: i32, new_max_index: i32) -> i32 {
-        let desired_length = (new_max_index as i64 - new_min_index as i64) as i32 + 1;
-        i32::min(
-            self.max_num_bins,
-            ((desired_length + self.array_length_overhead - 1)
-                / self.array_length_growth_increment
+        let desired_length = new_max_index as i64 - new_min_index as i64 + 1;
+        i64::min(
+            self.max_num_bins as i64,
+            ((desired_length + self.array_length_overhead as i64 - 1)
+                / self.array_length_growth_increment as i64
                 + 1)
-                * self.array_length_growth_increment,
-        )
+                * self.array_length_growth_increment as i64,
+        ) as i32
     }
 
     fn center_counts(&mut self, new_min_index: i32, new_max_index: i32) {
-        let middle_index = new_min_index + (new_max_index - new_min_index + 1) / 2;
-        let shift = self.offset + self.get_length() / 2 - middle_index;
-        self.shift_counts(shift);
+        let middle_index = (new_min_index as i64 + new_max_index as i64 + 1).div_euclid(2);
+        // The offset is kept so that every slot of the array has an index within the i32 range.
+        let new_offset = (middle_index - (self.get_length() / 2) as i64).clamp(
+            i32::MIN as i64,
+            i32::MAX as i64 - self.get_length() as i64 + 1,
+        );
+        self.shift_counts((self.offset as i64 - new_offset) as i32);
         self.min_index = new_min_index;
         self.max_index = new_max_index;
     }
@@ -218,12 +226,8 @@ impl Store for CollapsingHighestDenseStore {
             return;
         }
 
-        let array_index = serde::i32_to_usize_exact(self.normalize(index));
-        match array_index {
-            Ok(index) => {
-                self.counts[index] += count;
-            }
-            _ => {}
+        if let Ok(array_index) = serde::i32_to_usize_exact(self.normalize(index)) {
+            self.counts[array_index] += count;
         }
     }
 
@@ -231,12 +235,8 @@ impl Store for CollapsingHighestDenseStore {
         if bin.1 == 0.0 {
             return;
         }
-        let array_index = serde::i32_to_usize_exact(self.normalize(bin.0));
-        match array_index {
-            Ok(index) => {
-                self.counts[index] += bin.1;
-            }
-            _ => {}
+        if let Ok(array_index) = serde::i32_to_usize_exact(self.normalize(bin.0)) {
+            self.counts[array_index] += bin.1;
         }
     }
 
@@ -256,10 +256,6 @@ impl Store for CollapsingHighestDenseStore {
         self.get_total_count_with_range(self.min_index, self.max_index)
     }
 
-    fn get_offset(&self) -> i32 {
-        self.offset
-    }
-
     fn get_min_index(&self) -> i32 {
         self.min_index
     }
@@ -268,39 +264,31 @@ impl Store for CollapsingHighestDenseStore {
         self.max_index
     }
 
-    fn get_count(&self, i: i32) -> f64 {
-        self.counts[i as usize]
-    }
-
     fn get_descending_stream(&self) -> Vec<(i32, f64)> {
         let mut bins = Vec::new();
-        let mut index = self.max_index;
-        while index >= self.min_index {
+        for index in (self.min_index..=self.max_index).rev() {
             let value = self.counts[(index - self.offset) as usize];
             if value > 0.0 {
                 let bin = (index, value);
                 bins.push(bin);
             }
-            index -= 1;
         }
         bins
     }
 
     fn get_ascending_stream(&self) -> Vec<(i32, f64)> {
         let mut bins = Vec::new();
-        let mut index = self.min_index;
-        while index <= self.max_index {
+        for index in self.min_index..=self.max_index {
             let value = self.counts[(index - self.offset) as usize];
             if value > 0.0 {
                 let bin = (index, value);
                 bins.push(bin);
             }
-            index -= 1;
         }
         bins
     }
 
-    fn get_descending_iter(&self) -> StoreIter {
+    fn get_descending_iter(&self) -> StoreIter<'_> {
         StoreIter::new(
             self.min_index,
             self.max_index,
@@ -310,7 +298,7 @@ impl Store for CollapsingHighestDenseStore {
         )
     }
 
-    fn get_ascending_iter(&self) -> StoreIter {
+    fn get_ascending_iter(&self) -> StoreIter<'_> {
         StoreIter::new(
             self.min_index,
             self.max_index,
@@ -319,4 +307,11 @@ impl Store for CollapsingHighestDenseStore {
             self.counts.as_slice(),
         )
     }
+
+    fn get_memory_footprint(&self) -> usize {
+        self.counts.capacity() * mem::size_of::<f64>()
+    }
 }
+
+#[cfg(feature = "serde")]
+crate::serialization::impl_store_serde!(CollapsingHighestDenseStore, bounded by max_num_bins);
@@ -0,0 +1,117 @@
+use super::*;
+use std::collections::BTreeMap;
+
+/// A sparse store that keeps at most `max_num_bins` non-empty bins. When the budget is exceeded,
+/// the lowest bins are collapsed into the lowest remaining one.
+#[derive(Clone)]
+pub struct CollapsingLowestSparseStore {
+    bins: BTreeMap<i32, f64>,
+    max_num_bins: usize,
+    is_collapsed: bool,
+}
+
+impl CollapsingLowestSparseStore {
+    pub fn with_capacity(capacity: usize) -> Result<Self, Error> {
+        if capacity == 0 {
+            return Err(Error::InvalidArgument(
+                "The maximum number of bins must be positive.",
+            ));
+        }
+        Ok(CollapsingLowestSparseStore {
+            bins: BTreeMap::new(),
+            max_num_bins: capacity,
+            is_collapsed: false,
+        })
+    }
+
+    fn normalize(&self, index: i32) -> i32 {
+        if self.is_collapsed || self.bins.len() >= self.max_num_bins {
+            let min_index = self.get_min_index();
+            if index < min_index {
+                return min_index;
+            }
+        }
+        index
+    }
+
+    fn collapse(&mut self) {
+        while self.bins.len() > self.max_num_bins {
+            if let Some((_, count)) = self.bins.pop_first() {
+                if let Some(lowest) = self.bins.values_mut().next() {
+                    *lowest += count;
+                }
+            }
+            self.is_collapsed = true;
+        }
+    }
+}
+
+impl Store for CollapsingLowestSparseStore {
+    fn add(&mut self, index: i32, count: f64) {
+        if count <= 0.0 {
+            return;
+        }
+        let index = self.normalize(index);
+        *self.bins.entry(index).or_insert(0.0) += count;
+        self.collapse();
+    }
+
+    fn add_bin(&mut self, bin: (i32, f64)) {
+        if bin.1 == 0.0 {
+            return;
+        }
+        let index = self.normalize(bin.0);
+        *self.bins.entry(index).or_insert(0.0) += bin.1;
+        self.collapse();
+    }
+
+    fn clear(&mut self) {
+        self.bins.clear();
+        self.is_collapsed = false;
+    }
+
+    fn is_empty(&self) -> bool {
+        self.bins.is_empty()
+    }
+
+    fn get_total_count(&self) -> f64 {
+        self.bins.values().sum()
+    }
+
+    fn get_min_index(&self) -> i32 {
+        match self.bins.first_key_value() {
+            Some((index, _)) => *index,
+            None => i32::MAX,
+        }
+    }
+
+    fn get_max_index(&self) -> i32 {
+        match self.bins.last_key_value() {
+            Some((index, _)) => *index,
+            None => i32::MIN,
+        }
+    }
+
+    fn get_descending_stream(&self) -> Vec<(i32, f64)> {
+        self.bins.iter().rev().map(|(i, c)| (*i, *c)).collect()
+    }
+
+    fn get_ascending_stream(&self) -> Vec<(i32, f64)> {
+        self.bins.iter().map(|(i, c)| (*i, *c)).collect()
+    }
+
+    fn get_descending_iter(&self) -> StoreIter<'_> {
+        StoreIter::from_bins(self.bins.iter().rev().map(|(i, c)| (*i, *c)))
+    }
+
+    fn get_ascending_iter(&self) -> StoreIter<'_> {
+        StoreIter::from_bins(self.bins.iter().map(|(i, c)| (*i, *c)))
+    }
+
+    fn get_memory_footprint(&self) -> usize {
+        sparse_memory_footprint(self.bins.len())
+    }
+}
+
+#[cfg(feature = "serde")]
+crate::serialization::impl_store_serde!(CollapsingLowestSparseStore, bounded by max_num_bins);
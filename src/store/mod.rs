@@ -1,9 +1,15 @@
+use crate::decode_options::DecodeOptions;
 use crate::error::Error;
 use crate::input::Input;
 use crate::serde;
+use std::fmt;
+use std::mem;
 
 mod collapsing_highest;
 mod collapsing_lowest;
+mod collapsing_lowest_sparse;
+mod paginated;
+mod sparse;
 mod unbounded;
 
 use crate::index_mapping::IndexMapping;
@@ -11,9 +17,17 @@ use crate::output::Output;
 use crate::sketch::{Flag, FlagType};
 pub use collapsing_highest::CollapsingHighestDenseStore;
 pub use collapsing_lowest::CollapsingLowestDenseStore;
+pub use collapsing_lowest_sparse::CollapsingLowestSparseStore;
+pub use paginated::PaginatedStore;
+pub use sparse::SparseStore;
 pub use unbounded::UnboundedSizeDenseStore;
 
-pub trait Store {
+/// The bins of one side of a sketch, positive or negative values.
+///
+/// The trait is sealed: it can be named, as the store parameters of
+/// [`DDSketch`](crate::DDSketch) are bound by it, but it cannot be implemented outside of this
+/// crate, since its methods read and write the encoding of the sketch through crate-private types.
+pub trait Store: sealed::Sealed + StoreClone + Send + Sync {
     fn add(&mut self, index: i32, count: f64);
     fn add_bin(&mut self, bin: (i32, f64));
     fn merge_with(&mut self, bins: Vec<(i32, f64)>) {
@@ -24,10 +38,8 @@ pub trait Store {
     fn clear(&mut self);
     fn is_empty(&self) -> bool;
     fn get_total_count(&self) -> f64;
-    fn get_offset(&self) -> i32;
     fn get_min_index(&self) -> i32;
     fn get_max_index(&self) -> i32;
-    fn get_count(&self, i: i32) -> f64;
     fn encode(&self, output: &mut Output, store_flag_type: FlagType) -> Result<(), Error> {
         if self.is_empty() {
             return Ok(());
@@ -35,75 +47,81 @@ pub trait Store {
 
         let min_index = self.get_min_index();
         let max_index = self.get_max_index();
-        let offset = self.get_offset();
+        let sizes = BinEncodingSizes::of(self);
 
-        let mut dense_encoding_size: i64 = 0;
-        let num_bins: i64 = max_index as i64 - min_index as i64 + 1;
-        dense_encoding_size += serde::unsigned_var_long_encoded_length(num_bins);
-        dense_encoding_size += serde::signed_var_long_encoded_length(min_index as i64);
-        dense_encoding_size += serde::signed_var_long_encoded_length(1);
-
-        let mut sparse_encoding_size: i64 = 0;
-        let mut num_non_empty_bins: i64 = 0;
-        let mut previous_index: i64 = 0;
-
-        for i in min_index - offset..max_index - offset + 1 {
-            let count = self.get_count(i);
-            let count_var_double_encoded_length = serde::var_double_encoded_length(count);
-            dense_encoding_size += count_var_double_encoded_length;
-            if count != 0.0 {
-                num_non_empty_bins += 1;
-                let index: i64 = offset as i64 + i as i64;
-                sparse_encoding_size +=
-                    serde::signed_var_long_encoded_length(index - previous_index);
-                sparse_encoding_size += count_var_double_encoded_length;
-                previous_index = index;
-            }
-        }
-
-        if dense_encoding_size <= sparse_encoding_size {
+        if sizes.dense <= sizes.sparse {
             BinEncodingMode::ContiguousCounts
                 .to_flag(store_flag_type)
                 .encode(output)?;
-            serde::encode_unsigned_var_long(output, num_bins)?;
+            serde::encode_unsigned_var_long(output, sizes.num_bins)?;
             serde::encode_signed_var_long(output, min_index as i64)?;
             serde::encode_signed_var_long(output, 1)?;
-            for i in min_index - offset..max_index - offset + 1 {
-                serde::encode_var_double(output, self.get_count(i))?;
+            let mut next_index = min_index as i64;
+            for (index, count) in self.get_ascending_iter() {
+                if count != 0.0 {
+                    while next_index < index as i64 {
+                        serde::encode_var_double(output, 0.0)?;
+                        next_index += 1;
+                    }
+                    serde::encode_var_double(output, count)?;
+                    next_index += 1;
+                }
+            }
+            while next_index <= max_index as i64 {
+                serde::encode_var_double(output, 0.0)?;
+                next_index += 1;
             }
         } else {
             BinEncodingMode::IndexDeltasAndCounts
                 .to_flag(store_flag_type)
                 .encode(output)?;
-            serde::encode_unsigned_var_long(output, num_non_empty_bins)?;
+            serde::encode_unsigned_var_long(output, sizes.num_non_empty_bins)?;
             let mut previous_index = 0;
-            for i in min_index - offset..max_index - offset + 1 {
-                let count = self.get_count(i);
+            for (index, count) in self.get_ascending_iter() {
                 if count != 0.0 {
-                    let index: i64 = offset as i64 + i as i64;
-                    serde::encode_signed_var_long(output, index - previous_index)?;
+                    serde::encode_signed_var_long(output, index as i64 - previous_index)?;
                     serde::encode_var_double(output, count)?;
-                    previous_index = index;
+                    previous_index = index as i64;
                 }
             }
         }
         Ok(())
     }
+    /// The exact number of bytes [`Store::encode`] writes.
+    fn encoded_len(&self) -> usize {
+        if self.is_empty() {
+            return 0;
+        }
+        let sizes = BinEncodingSizes::of(self);
+        let bins_len = if sizes.dense <= sizes.sparse {
+            sizes.dense
+        } else {
+            sizes.sparse + serde::unsigned_var_long_encoded_length(sizes.num_non_empty_bins)
+        };
+        1 + bins_len as usize
+    }
+    /// Decodes bins encoded with `mode` from `input` and adds them to the store, within the limits
+    /// of `options`.
     fn decode_and_merge_with(
         &mut self,
         input: &mut Input,
         mode: BinEncodingMode,
+        options: &DecodeOptions,
     ) -> Result<(), Error> {
         match mode {
             BinEncodingMode::IndexDeltasAndCounts => {
                 let num_bins = serde::decode_unsigned_var_long(input)?;
+                options.check_num_bins(num_bins)?;
                 let mut index: i64 = 0;
                 let mut i = 0;
                 while i < num_bins {
                     let index_delta = serde::decode_signed_var_long(input)?;
                     let count = serde::decode_var_double(input)?;
-                    index += index_delta;
-                    self.add(serde::i64_to_i32_exact(index)?, count);
+                    index = index.saturating_add(index_delta);
+                    let index = serde::i64_to_i32_exact(index)?;
+                    options.check_count(count)?;
+                    options.check_index(self, index)?;
+                    self.add(index, count);
                     i += 1;
                 }
 
@@ -112,12 +130,15 @@ pub trait Store {
 
             BinEncodingMode::IndexDeltas => {
                 let num_bins = serde::decode_unsigned_var_long(input)?;
+                options.check_num_bins(num_bins)?;
                 let mut index: i64 = 0;
                 let mut i = 0;
                 while i < num_bins {
                     let index_delta = serde::decode_signed_var_long(input)?;
-                    index += index_delta;
-                    self.add(serde::i64_to_i32_exact(index)?, 1.0);
+                    index = index.saturating_add(index_delta);
+                    let index = serde::i64_to_i32_exact(index)?;
+                    options.check_index(self, index)?;
+                    self.add(index, 1.0);
                     i += 1;
                 }
                 Ok(())
@@ -125,14 +146,18 @@ pub trait Store {
 
             BinEncodingMode::ContiguousCounts => {
                 let num_bins = serde::decode_unsigned_var_long(input)?;
+                options.check_num_bins(num_bins)?;
                 let mut index: i64 = serde::decode_signed_var_long(input)?;
                 let index_delta = serde::decode_signed_var_long(input)?;
 
                 let mut i = 0;
                 while i < num_bins {
                     let count = serde::decode_var_double(input)?;
-                    self.add(serde::i64_to_i32_exact(index)?, count);
-                    index += index_delta;
+                    let bin_index = serde::i64_to_i32_exact(index)?;
+                    options.check_count(count)?;
+                    options.check_index(self, bin_index)?;
+                    self.add(bin_index, count);
+                    index = index.saturating_add(index_delta);
                     i += 1;
                 }
                 Ok(())
@@ -141,36 +166,231 @@ pub trait Store {
     }
     fn get_descending_stream(&self) -> Vec<(i32, f64)>;
     fn get_ascending_stream(&self) -> Vec<(i32, f64)>;
-    fn get_descending_iter(&self) -> StoreIter;
-    fn get_ascending_iter(&self) -> StoreIter;
+    fn get_descending_iter(&self) -> StoreIter<'_>;
+    fn get_ascending_iter(&self) -> StoreIter<'_>;
+    /// Multiplies the count of every bin by `factor`, which must be finite and non-negative.
+    fn scale(&mut self, factor: f64) {
+        let bins = self.get_ascending_stream();
+        self.clear();
+        for (index, count) in bins {
+            self.add_bin((index, count * factor));
+        }
+    }
+    /// Returns the total count of the bins whose index is between `min_index` and `max_index`,
+    /// both included.
+    fn get_count_in_range(&self, min_index: i32, max_index: i32) -> f64 {
+        self.get_ascending_iter()
+            .skip_while(|(index, _)| *index < min_index)
+            .take_while(|(index, _)| *index <= max_index)
+            .map(|(_, count)| count)
+            .sum()
+    }
+    /// Returns the number of bytes the store has allocated on the heap, which is an estimate for
+    /// the stores backed by a `BTreeMap`.
+    fn get_memory_footprint(&self) -> usize;
     fn get_sum(&self, index_mapping: &IndexMapping) -> f64 {
         let mut sum = 0.0;
-        if self.is_empty() {
-            return sum;
+        for (index, count) in self.get_ascending_iter() {
+            if count != 0.0 {
+                sum += index_mapping.value(index) * count;
+            }
         }
+        sum
+    }
+}
+
+mod sealed {
+    /// Keeps [`Store`](super::Store) from being implemented outside of this crate.
+    pub trait Sealed {}
+
+    impl Sealed for super::CollapsingHighestDenseStore {}
+    impl Sealed for super::CollapsingLowestDenseStore {}
+    impl Sealed for super::CollapsingLowestSparseStore {}
+    impl Sealed for super::PaginatedStore {}
+    impl Sealed for super::SparseStore {}
+    impl Sealed for super::UnboundedSizeDenseStore {}
+    impl Sealed for Box<dyn super::Store> {}
+}
+
+/// Lets a boxed store be cloned without knowing its concrete type.
+pub trait StoreClone {
+    fn clone_box(&self) -> Box<dyn Store>;
+}
+
+impl<T> StoreClone for T
+where
+    T: 'static + Store + Clone,
+{
+    fn clone_box(&self) -> Box<dyn Store> {
+        Box::new(self.clone())
+    }
+}
+
+impl Clone for Box<dyn Store> {
+    fn clone(&self) -> Box<dyn Store> {
+        (**self).clone_box()
+    }
+}
+
+/// Forwards every method, including the provided ones, so that a boxed store behaves exactly like
+/// the store it holds.
+impl Store for Box<dyn Store> {
+    fn add(&mut self, index: i32, count: f64) {
+        (**self).add(index, count)
+    }
+    fn add_bin(&mut self, bin: (i32, f64)) {
+        (**self).add_bin(bin)
+    }
+    fn merge_with(&mut self, bins: Vec<(i32, f64)>) {
+        (**self).merge_with(bins)
+    }
+    fn clear(&mut self) {
+        (**self).clear()
+    }
+    fn is_empty(&self) -> bool {
+        (**self).is_empty()
+    }
+    fn get_total_count(&self) -> f64 {
+        (**self).get_total_count()
+    }
+    fn get_min_index(&self) -> i32 {
+        (**self).get_min_index()
+    }
+    fn get_max_index(&self) -> i32 {
+        (**self).get_max_index()
+    }
+    fn encode(&self, output: &mut Output, store_flag_type: FlagType) -> Result<(), Error> {
+        (**self).encode(output, store_flag_type)
+    }
+    fn encoded_len(&self) -> usize {
+        (**self).encoded_len()
+    }
+    fn decode_and_merge_with(
+        &mut self,
+        input: &mut Input,
+        mode: BinEncodingMode,
+        options: &DecodeOptions,
+    ) -> Result<(), Error> {
+        (**self).decode_and_merge_with(input, mode, options)
+    }
+    fn get_descending_stream(&self) -> Vec<(i32, f64)> {
+        (**self).get_descending_stream()
+    }
+    fn get_ascending_stream(&self) -> Vec<(i32, f64)> {
+        (**self).get_ascending_stream()
+    }
+    fn get_descending_iter(&self) -> StoreIter<'_> {
+        (**self).get_descending_iter()
+    }
+    fn get_ascending_iter(&self) -> StoreIter<'_> {
+        (**self).get_ascending_iter()
+    }
+    fn scale(&mut self, factor: f64) {
+        (**self).scale(factor)
+    }
+    fn get_count_in_range(&self, min_index: i32, max_index: i32) -> f64 {
+        (**self).get_count_in_range(min_index, max_index)
+    }
+    fn get_memory_footprint(&self) -> usize {
+        mem::size_of_val(&**self) + (**self).get_memory_footprint()
+    }
+    fn get_sum(&self, index_mapping: &IndexMapping) -> f64 {
+        (**self).get_sum(index_mapping)
+    }
+}
+
+/// The sizes of both bin encodings of a non-empty store, excluding the flag, so that the smaller
+/// one can be picked. `sparse` leaves out the number of non-empty bins written before the index
+/// deltas and counts, which the choice does not account for.
+struct BinEncodingSizes {
+    num_bins: i64,
+    num_non_empty_bins: i64,
+    dense: i64,
+    sparse: i64,
+}
+
+impl BinEncodingSizes {
+    fn of<S: Store + ?Sized>(store: &S) -> BinEncodingSizes {
+        let min_index = store.get_min_index();
+        let max_index = store.get_max_index();
+
+        let mut dense_encoding_size: i64 = 0;
+        let num_bins: i64 = max_index as i64 - min_index as i64 + 1;
+        dense_encoding_size += serde::unsigned_var_long_encoded_length(num_bins);
+        dense_encoding_size += serde::signed_var_long_encoded_length(min_index as i64);
+        dense_encoding_size += serde::signed_var_long_encoded_length(1);
 
-        for i in self.get_min_index()..self.get_max_index() {
-            let value = self.get_count(i - self.get_offset());
-            if value != 0.0 {
-                sum += index_mapping.value(i) * value;
+        let mut sparse_encoding_size: i64 = 0;
+        let mut num_non_empty_bins: i64 = 0;
+        let mut previous_index: i64 = 0;
+
+        for (index, count) in store.get_ascending_iter() {
+            if count != 0.0 {
+                let count_var_double_encoded_length = serde::var_double_encoded_length(count);
+                dense_encoding_size += count_var_double_encoded_length;
+                num_non_empty_bins += 1;
+                sparse_encoding_size +=
+                    serde::signed_var_long_encoded_length(index as i64 - previous_index);
+                sparse_encoding_size += count_var_double_encoded_length;
+                previous_index = index as i64;
             }
         }
+        // Each empty bin within the range is encoded as a single zero byte.
+        dense_encoding_size +=
+            (num_bins - num_non_empty_bins) * serde::var_double_encoded_length(0.0);
 
-        let last_count = self.get_count(self.get_max_index() - self.get_offset());
-        if last_count != 0.0 {
-            sum += index_mapping.value(self.get_max_index()) * last_count;
+        BinEncodingSizes {
+            num_bins,
+            num_non_empty_bins,
+            dense: dense_encoding_size,
+            sparse: sparse_encoding_size,
         }
+    }
+}
 
-        sum
+// A `BTreeMap` node holds up to 11 entries, plus a parent pointer, its position in the parent and
+// its length. Nodes are assumed to be full, internal nodes being ignored.
+const BTREE_NODE_CAPACITY: usize = 11;
+const BTREE_NODE_SIZE: usize = BTREE_NODE_CAPACITY
+    * (mem::size_of::<i32>() + mem::size_of::<f64>())
+    + mem::size_of::<usize>()
+    + 2 * mem::size_of::<u16>();
+
+fn sparse_memory_footprint(num_bins: usize) -> usize {
+    num_bins.div_ceil(BTREE_NODE_CAPACITY) * BTREE_NODE_SIZE
+}
+
+/// Two stores are equal if they hold the same non-empty bins, whatever their implementation.
+impl<'a, 'b> PartialEq<dyn Store + 'b> for dyn Store + 'a {
+    fn eq(&self, other: &(dyn Store + 'b)) -> bool {
+        self.get_ascending_iter().eq(other.get_ascending_iter())
     }
 }
 
-pub struct StoreIter<'a> {
-    min_index: i32,
-    max_index: i32,
-    offset: i32,
-    desc: bool,
-    counts: &'a [f64],
+/// Summarizes the bins instead of listing them, since a store can hold thousands of them.
+impl fmt::Debug for dyn Store + '_ {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut debug_struct = f.debug_struct("Store");
+        debug_struct.field("num_bins", &self.get_ascending_iter().count());
+        if !self.is_empty() {
+            debug_struct
+                .field("min_index", &self.get_min_index())
+                .field("max_index", &self.get_max_index())
+                .field("total_count", &self.get_total_count());
+        }
+        debug_struct.finish()
+    }
+}
+
+pub enum StoreIter<'a> {
+    Dense {
+        min_index: i32,
+        max_index: i32,
+        offset: i32,
+        desc: bool,
+        counts: &'a [f64],
+    },
+    Bins(Box<dyn Iterator<Item = (i32, f64)> + 'a>),
 }
 
 impl<'a> StoreIter<'a> {
@@ -180,8 +400,8 @@ impl<'a> StoreIter<'a> {
         offset: i32,
         desc: bool,
         counts: &'a [f64],
-    ) -> StoreIter {
-        StoreIter {
+    ) -> StoreIter<'a> {
+        StoreIter::Dense {
             desc,
             min_index,
             max_index,
@@ -189,51 +409,53 @@ impl<'a> StoreIter<'a> {
             counts,
         }
     }
+
+    pub fn from_bins<I>(bins: I) -> StoreIter<'a>
+    where
+        I: Iterator<Item = (i32, f64)> + 'a,
+    {
+        StoreIter::Bins(Box::new(bins))
+    }
 }
 
 impl<'a> Iterator for StoreIter<'a> {
     type Item = (i32, f64);
     fn next(&mut self) -> Option<Self::Item> {
-        if self.desc {
-            if self.max_index < self.min_index {
-                return None;
-            }
-
-            let index = self.max_index;
-            self.max_index -= 1;
-
-            while self.max_index >= self.min_index {
-                let count = self.counts[(self.max_index - self.offset) as usize];
-                if count != 0.0 {
-                    break;
+        match self {
+            StoreIter::Dense {
+                min_index,
+                max_index,
+                offset,
+                desc,
+                counts,
+            } => {
+                if *max_index < *min_index {
+                    return None;
                 }
-                self.max_index -= 1;
-            }
-
-            let count = self.counts[(index - self.offset) as usize];
-            Some((index, count))
-        } else {
-            if self.min_index > self.max_index {
-                return None;
-            }
-
-            let index = self.min_index;
-            self.min_index += 1;
-
-            while self.min_index <= self.max_index {
-                let count = self.counts[(self.min_index - self.offset) as usize];
-                if count != 0.0 {
-                    break;
+                // Looks for the next non-empty bin without stepping past the ends of the i32 range.
+                let is_non_empty = |index: &i32| counts[(*index - *offset) as usize] != 0.0;
+                let index = if *desc { *max_index } else { *min_index };
+                let next = if *desc {
+                    (*min_index..index).rev().find(is_non_empty)
+                } else {
+                    (index..=*max_index).skip(1).find(is_non_empty)
+                };
+                match next {
+                    Some(next) if *desc => *max_index = next,
+                    Some(next) => *min_index = next,
+                    None => {
+                        *min_index = i32::MAX;
+                        *max_index = i32::MIN;
+                    }
                 }
-                self.min_index += 1;
+                Some((index, counts[(index - *offset) as usize]))
             }
-
-            let count = self.counts[(index - self.offset) as usize];
-            Some((index, count))
+            StoreIter::Bins(bins) => bins.next(),
         }
     }
 }
 
+#[derive(Clone, Copy)]
 pub enum BinEncodingMode {
     IndexDeltasAndCounts = 1,
     IndexDeltas = 2,
@@ -242,12 +464,14 @@ pub enum BinEncodingMode {
 
 impl BinEncodingMode {
     pub fn of_flag(marker: u8) -> Result<BinEncodingMode, Error> {
-        let index = (marker >> 2) - 1;
-        match index {
-            0 => Ok(BinEncodingMode::IndexDeltasAndCounts),
-            1 => Ok(BinEncodingMode::IndexDeltas),
-            2 => Ok(BinEncodingMode::ContiguousCounts),
-            _ => Err(Error::InvalidArgument("Unknown BinEncodingMode.")),
+        match marker >> 2 {
+            1 => Ok(BinEncodingMode::IndexDeltasAndCounts),
+            2 => Ok(BinEncodingMode::IndexDeltas),
+            3 => Ok(BinEncodingMode::ContiguousCounts),
+            _ => Err(Error::UnknownFlag {
+                marker,
+                section: "bin encoding",
+            }),
         }
     }
 
@@ -275,6 +499,65 @@ mod tests {
         assert_eq!(20.0, store.get_total_count());
     }
 
+    #[test]
+    fn test_dense_store_ascending_stream() {
+        let indexes = [7, -3, 12, 7, 0, 5];
+        let mut stores: Vec<Box<dyn Store>> = vec![
+            Box::new(CollapsingLowestDenseStore::with_capacity(32).unwrap()),
+            Box::new(CollapsingHighestDenseStore::with_capacity(32).unwrap()),
+            Box::new(UnboundedSizeDenseStore::new()),
+        ];
+        for store in stores.iter_mut() {
+            for i in indexes {
+                store.add(i, 1.0);
+            }
+            assert_eq!(
+                vec![(-3, 1.0), (0, 1.0), (5, 1.0), (7, 2.0), (12, 1.0)],
+                store.get_ascending_stream()
+            );
+            let mut descending = store.get_descending_stream();
+            descending.reverse();
+            assert_eq!(descending, store.get_ascending_stream());
+        }
+    }
+
+    #[test]
+    fn test_dense_store_extreme_indexes() {
+        for index in [i32::MIN, i32::MIN + 1, i32::MAX - 1, i32::MAX] {
+            let mut stores: Vec<Box<dyn Store>> = vec![
+                Box::new(CollapsingLowestDenseStore::with_capacity(2048).unwrap()),
+                Box::new(CollapsingHighestDenseStore::with_capacity(2048).unwrap()),
+                Box::new(UnboundedSizeDenseStore::new()),
+            ];
+            for store in stores.iter_mut() {
+                store.add(index, 1.0);
+                store.add(index, 2.0);
+                assert_eq!(vec![(index, 3.0)], store.get_ascending_stream());
+                assert_eq!(vec![(index, 3.0)], store.get_descending_stream());
+                assert_eq!(
+                    vec![(index, 3.0)],
+                    store.get_ascending_iter().collect::<Vec<_>>()
+                );
+                assert_eq!(
+                    vec![(index, 3.0)],
+                    store.get_descending_iter().collect::<Vec<_>>()
+                );
+            }
+        }
+
+        let mut lowest = CollapsingLowestDenseStore::with_capacity(2048).unwrap();
+        lowest.add(i32::MIN, 1.0);
+        lowest.add(i32::MAX, 2.0);
+        assert_eq!(3.0, lowest.get_total_count());
+        assert_eq!(i32::MAX, lowest.get_max_index());
+
+        let mut highest = CollapsingHighestDenseStore::with_capacity(2048).unwrap();
+        highest.add(i32::MAX, 1.0);
+        highest.add(i32::MIN, 2.0);
+        assert_eq!(3.0, highest.get_total_count());
+        assert_eq!(i32::MIN, highest.get_min_index());
+    }
+
     #[test]
     fn test_collapsing_highest_dense_store_add() {
         let mut store = CollapsingHighestDenseStore::with_capacity(10).unwrap();
@@ -302,4 +585,321 @@ mod tests {
         assert_eq!(6, store.get_min_index());
         assert_eq!(20.0, store.get_total_count());
     }
+
+    #[test]
+    fn test_store_add_with_count() {
+        let mut stores: Vec<Box<dyn Store>> = vec![
+            Box::new(CollapsingLowestDenseStore::with_capacity(10).unwrap()),
+            Box::new(CollapsingHighestDenseStore::with_capacity(10).unwrap()),
+            Box::new(UnboundedSizeDenseStore::new()),
+        ];
+        for store in stores.iter_mut() {
+            store.add(5, 0.25);
+            store.add(5, 1e15);
+            store.add(7, 2.5);
+            store.add(7, 0.0);
+            store.add(9, -1.0);
+            assert_eq!(1e15 + 2.75, store.get_total_count());
+            assert_eq!(
+                vec![(7, 2.5), (5, 1e15 + 0.25)],
+                store.get_descending_iter().collect::<Vec<_>>()
+            );
+        }
+    }
+
+    #[test]
+    fn test_sparse_store_add() {
+        let mut store = SparseStore::new();
+        let indexes = vec![
+            17, 32, 6, 42, 24, 75, 56, 58, 28, 10, 76, 43, 90, 59, 17, 17, 34, 47, 56, 32,
+        ];
+        for i in indexes {
+            store.add(i, 1.0);
+        }
+        store.add(i32::MIN + 1, 1.0);
+        store.add(i32::MAX - 1, 1.0);
+        assert_eq!(i32::MAX - 1, store.get_max_index());
+        assert_eq!(i32::MIN + 1, store.get_min_index());
+        assert_eq!(22.0, store.get_total_count());
+        assert_eq!(
+            vec![(i32::MIN + 1, 1.0), (6, 1.0), (10, 1.0), (17, 3.0)],
+            store.get_ascending_iter().take(4).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_collapsing_lowest_sparse_store_add() {
+        let mut store = CollapsingLowestSparseStore::with_capacity(10).unwrap();
+        let indexes = vec![
+            66, 14, 95, 71, 63, 28, 80, 54, 67, 41, 4, 24, 93, 73, 37, 37, 51, 49, 22, 90,
+        ];
+        for i in indexes {
+            store.add(i, 1.0);
+        }
+        assert_eq!(95, store.get_max_index());
+        assert_eq!(54, store.get_min_index());
+        assert_eq!(20.0, store.get_total_count());
+        assert_eq!(10, store.get_ascending_iter().count());
+        assert_eq!(Some((54, 11.0)), store.get_ascending_iter().next());
+
+        store.add(1, 1.0);
+        assert_eq!(Some((54, 12.0)), store.get_ascending_iter().next());
+    }
+
+    #[test]
+    fn test_sparse_store_encode() {
+        let mut sparse = SparseStore::new();
+        let mut dense = UnboundedSizeDenseStore::new();
+        for (index, count) in [(-3, 1.0), (0, 2.5), (1, 1.0), (4, 7.0), (12, 1.0)] {
+            sparse.add(index, count);
+            dense.add(index, count);
+        }
+
+        let mut sparse_output = Output::with_capacity(64);
+        sparse
+            .encode(&mut sparse_output, FlagType::PositiveStore)
+            .unwrap();
+        let mut dense_output = Output::with_capacity(64);
+        dense
+            .encode(&mut dense_output, FlagType::PositiveStore)
+            .unwrap();
+        let bytes = sparse_output.trim();
+        assert_eq!(dense_output.trim(), bytes);
+
+        let mut decoded = SparseStore::new();
+        let mut input = Input::wrap(&bytes);
+        let flag = Flag::decode(&mut input).unwrap();
+        let mode = BinEncodingMode::of_flag(flag.get_marker()).unwrap();
+        decoded
+            .decode_and_merge_with(&mut input, mode, &DecodeOptions::default())
+            .unwrap();
+        assert_eq!(
+            sparse.get_ascending_stream(),
+            decoded.get_ascending_stream()
+        );
+    }
+
+    #[test]
+    fn test_paginated_store_add() {
+        let mut store = PaginatedStore::new();
+        let indexes = vec![
+            17, 32, 6, 42, 24, 75, 56, 58, 28, 10, 76, 43, 90, 59, 17, 17, 34, 47, 56, 32,
+        ];
+        for i in indexes {
+            store.add(i, 1.0);
+        }
+        assert_eq!(90, store.get_max_index());
+        assert_eq!(6, store.get_min_index());
+        assert_eq!(20.0, store.get_total_count());
+
+        store.add(-1000, 2.0);
+        store.add(5000, 0.5);
+        store.add(-100_000, 1.0);
+        assert_eq!(5000, store.get_max_index());
+        assert_eq!(-100_000, store.get_min_index());
+        assert_eq!(23.5, store.get_total_count());
+        assert_eq!(
+            vec![
+                (-100_000, 1.0),
+                (-1000, 2.0),
+                (6, 1.0),
+                (10, 1.0),
+                (17, 3.0)
+            ],
+            store.get_ascending_iter().take(5).collect::<Vec<_>>()
+        );
+        assert_eq!(
+            vec![(5000, 0.5), (90, 1.0), (76, 1.0)],
+            store.get_descending_iter().take(3).collect::<Vec<_>>()
+        );
+
+        store.clear();
+        assert!(store.is_empty());
+        assert_eq!(0.0, store.get_total_count());
+        assert_eq!(None, store.get_ascending_iter().next());
+    }
+
+    #[test]
+    fn test_paginated_store_extreme_indexes() {
+        for (first, second) in [(i32::MIN + 1000, i32::MIN), (i32::MAX - 1000, i32::MAX)] {
+            let mut store = PaginatedStore::new();
+            store.add(first, 1.0);
+            store.add(second, 2.0);
+            assert_eq!(first.min(second), store.get_min_index());
+            assert_eq!(first.max(second), store.get_max_index());
+            let mut bins = vec![(first, 1.0), (second, 2.0)];
+            bins.sort_by_key(|bin| bin.0);
+            assert_eq!(bins, store.get_ascending_stream());
+            assert_eq!(3.0, store.get_total_count());
+        }
+    }
+
+    #[test]
+    fn test_paginated_store_page_table() {
+        let mut store = PaginatedStore::new();
+        store.add(0, 1.0);
+        let footprint = store.get_memory_footprint();
+        // Extending the range adds page slots, but only allocates the pages that get counts.
+        store.add(-100_000, 1.0);
+        store.add(100_000, 1.0);
+        let page_table = (200_000 / 128) * mem::size_of::<usize>();
+        assert!(store.get_memory_footprint() >= footprint + page_table);
+        assert!(store.get_memory_footprint() < 4 * page_table + 3 * 128 * mem::size_of::<f64>());
+        store.scale(0.5);
+        assert_eq!(1.5, store.get_total_count());
+        store.add_bin((7, 0.5));
+        assert_eq!(2.0, store.get_total_count());
+    }
+
+    #[test]
+    fn test_paginated_store_encode() {
+        let mut paginated = PaginatedStore::new();
+        let mut dense = UnboundedSizeDenseStore::new();
+        for (index, count) in [(-300, 1.0), (-1, 2.5), (0, 1.0), (127, 7.0), (128, 1.0)] {
+            paginated.add(index, count);
+            dense.add(index, count);
+        }
+
+        let mut paginated_output = Output::with_capacity(64);
+        paginated
+            .encode(&mut paginated_output, FlagType::NegativeStore)
+            .unwrap();
+        let mut dense_output = Output::with_capacity(64);
+        dense
+            .encode(&mut dense_output, FlagType::NegativeStore)
+            .unwrap();
+        let bytes = paginated_output.trim();
+        assert_eq!(dense_output.trim(), bytes);
+
+        let mut decoded = PaginatedStore::new();
+        let mut input = Input::wrap(&bytes);
+        let flag = Flag::decode(&mut input).unwrap();
+        let mode = BinEncodingMode::of_flag(flag.get_marker()).unwrap();
+        decoded
+            .decode_and_merge_with(&mut input, mode, &DecodeOptions::default())
+            .unwrap();
+        assert_eq!(
+            paginated.get_descending_stream(),
+            decoded.get_descending_stream()
+        );
+    }
+
+    #[test]
+    fn test_store_memory_footprint() {
+        let mut dense = UnboundedSizeDenseStore::new();
+        assert_eq!(0, dense.get_memory_footprint());
+        for index in 0..1000 {
+            dense.add(index, 1.0);
+        }
+        assert!(dense.get_memory_footprint() >= 1000 * mem::size_of::<f64>());
+
+        let mut collapsing = CollapsingLowestDenseStore::with_capacity(100).unwrap();
+        for index in 0..1000 {
+            collapsing.add(index, 1.0);
+        }
+        assert!(collapsing.get_memory_footprint() < dense.get_memory_footprint());
+
+        let mut sparse = SparseStore::new();
+        let mut paginated = PaginatedStore::new();
+        for index in [-1_000_000, 0, 1_000_000] {
+            sparse.add(index, 1.0);
+            paginated.add(index, 1.0);
+        }
+        assert!(sparse.get_memory_footprint() > 0);
+        assert!(sparse.get_memory_footprint() < paginated.get_memory_footprint());
+
+        let boxed: Box<dyn Store> = Box::new(sparse.clone());
+        assert_eq!(
+            mem::size_of::<SparseStore>() + sparse.get_memory_footprint(),
+            boxed.get_memory_footprint()
+        );
+    }
+
+    #[test]
+    fn test_store_encoded_len() {
+        let contiguous = [(-3, 1.0), (-2, 2.5), (0, 1.0), (1, 7.0)];
+        let spread = [(-100_000, 1.0), (0, 0.5), (1_000, 3.0), (2_000_000, 1.0)];
+        for bins in [&contiguous[..], &spread[..], &[]] {
+            let mut stores: Vec<Box<dyn Store>> = vec![
+                Box::new(UnboundedSizeDenseStore::new()),
+                Box::new(SparseStore::new()),
+                Box::new(PaginatedStore::new()),
+            ];
+            for store in stores.iter_mut() {
+                for &(index, count) in bins {
+                    store.add(index, count);
+                }
+                let mut output = Output::with_capacity(0);
+                store.encode(&mut output, FlagType::PositiveStore).unwrap();
+                assert_eq!(output.trim().len(), store.encoded_len());
+            }
+        }
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_store_serde() {
+        let mut store = CollapsingLowestDenseStore::with_capacity(3).unwrap();
+        for index in [-5, 1, 2, 2, 3] {
+            store.add(index, 1.0);
+        }
+        let json = serde_json::to_string(&store).unwrap();
+        assert_eq!(
+            r#"{"max_num_bins":3,"bins":{"1":2.0,"2":2.0,"3":1.0}}"#,
+            json
+        );
+        let decoded: CollapsingLowestDenseStore = serde_json::from_str(&json).unwrap();
+        assert_eq!(store.get_ascending_stream(), decoded.get_ascending_stream());
+
+        let compact = rmp_serde::to_vec(&store).unwrap();
+        let decoded: CollapsingLowestDenseStore = rmp_serde::from_slice(&compact).unwrap();
+        assert_eq!(store.get_ascending_stream(), decoded.get_ascending_stream());
+
+        let mut sparse = SparseStore::new();
+        sparse.add(-1_000_000, 1.0);
+        sparse.add(1_000_000, 3.0);
+        let json = serde_json::to_string(&sparse).unwrap();
+        assert_eq!(r#"{"-1000000":1.0,"1000000":3.0}"#, json);
+        let decoded: SparseStore = serde_json::from_str(&json).unwrap();
+        assert_eq!(
+            sparse.get_ascending_stream(),
+            decoded.get_ascending_stream()
+        );
+
+        sparse.add(0, f64::NAN);
+        let compact = rmp_serde::to_vec(&sparse).unwrap();
+        assert!(rmp_serde::from_slice::<SparseStore>(&compact).is_err());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_store_serde_round_trip() {
+        fn round_trip<S>(mut store: S)
+        where
+            S: Store + ::serde::Serialize + for<'de> ::serde::Deserialize<'de>,
+        {
+            for index in [-300, -1, 0, 5, 5, 127, 128] {
+                store.add(index, 1.5);
+            }
+            let json = serde_json::to_string(&store).unwrap();
+            let from_json: S = serde_json::from_str(&json).unwrap();
+            assert_eq!(
+                store.get_ascending_stream(),
+                from_json.get_ascending_stream()
+            );
+            let compact = rmp_serde::to_vec(&store).unwrap();
+            let from_compact: S = rmp_serde::from_slice(&compact).unwrap();
+            assert_eq!(
+                store.get_ascending_stream(),
+                from_compact.get_ascending_stream()
+            );
+        }
+
+        round_trip(UnboundedSizeDenseStore::new());
+        round_trip(SparseStore::new());
+        round_trip(PaginatedStore::new());
+        round_trip(CollapsingLowestDenseStore::with_capacity(4).unwrap());
+        round_trip(CollapsingHighestDenseStore::with_capacity(4).unwrap());
+        round_trip(CollapsingLowestSparseStore::with_capacity(4).unwrap());
+    }
 }
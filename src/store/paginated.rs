@@ -0,0 +1,171 @@
+use super::*;
+
+const PAGE_LENGTH_SHIFT: i32 = 7;
+const PAGE_LENGTH: usize = 1 << PAGE_LENGTH_SHIFT;
+const PAGE_MASK: i32 = (PAGE_LENGTH as i32) - 1;
+const MIN_PAGE_INDEX: i32 = i32::MIN >> PAGE_LENGTH_SHIFT;
+const MAX_PAGE_INDEX: i32 = i32::MAX >> PAGE_LENGTH_SHIFT;
+const PAGE_TABLE_GROWTH_INCREMENT: i64 = 64;
+
+/// A dense store whose counts are split into fixed-size pages that are allocated lazily, as in
+/// the Java `PaginatedStore`. The page table has a slot per page between the lowest and the
+/// highest index, and extending the range of indexes only moves page pointers, never the counts
+/// themselves. The table grows with the range of indexes, which
+/// [`DecodeOptions::max_index_span`](crate::DecodeOptions::max_index_span) bounds when decoding
+/// untrusted input.
+#[derive(Clone)]
+pub struct PaginatedStore {
+    pages: Vec<Option<Box<[f64; PAGE_LENGTH]>>>,
+    min_page_index: i32,
+    min_index: i32,
+    max_index: i32,
+    total_count: f64,
+}
+
+impl PaginatedStore {
+    pub fn new() -> Self {
+        PaginatedStore {
+            pages: Vec::new(),
+            min_page_index: 0,
+            min_index: i32::MAX,
+            max_index: i32::MIN,
+            total_count: 0.0,
+        }
+    }
+
+    fn page_index(index: i32) -> i32 {
+        index >> PAGE_LENGTH_SHIFT
+    }
+
+    fn line_index(index: i32) -> usize {
+        (index & PAGE_MASK) as usize
+    }
+
+    fn get_page_mut(&mut self, page_index: i32) -> &mut [f64; PAGE_LENGTH] {
+        let mut position = page_index as i64 - self.min_page_index as i64;
+        if position < 0 || position >= self.pages.len() as i64 {
+            self.extend_range(page_index);
+            position = page_index as i64 - self.min_page_index as i64;
+        }
+        self.pages[position as usize].get_or_insert_with(|| Box::new([0.0; PAGE_LENGTH]))
+    }
+
+    /// Extends the page table to `page_index`, with some room for more pages on that side.
+    fn extend_range(&mut self, page_index: i32) {
+        if self.pages.is_empty() {
+            let max_page_index =
+                (page_index as i64 + PAGE_TABLE_GROWTH_INCREMENT - 1).min(MAX_PAGE_INDEX as i64);
+            self.min_page_index = page_index;
+            self.pages
+                .resize_with((max_page_index - page_index as i64 + 1) as usize, || None);
+        } else if page_index < self.min_page_index {
+            let new_min_page_index =
+                (page_index as i64 - PAGE_TABLE_GROWTH_INCREMENT + 1).max(MIN_PAGE_INDEX as i64);
+            let shift = (self.min_page_index as i64 - new_min_page_index) as usize;
+            self.pages
+                .splice(0..0, std::iter::repeat_with(|| None).take(shift));
+            self.min_page_index = new_min_page_index as i32;
+        } else {
+            let max_page_index =
+                (page_index as i64 + PAGE_TABLE_GROWTH_INCREMENT - 1).min(MAX_PAGE_INDEX as i64);
+            self.pages.resize_with(
+                (max_page_index - self.min_page_index as i64 + 1) as usize,
+                || None,
+            );
+        }
+    }
+
+    fn bins(&self) -> impl DoubleEndedIterator<Item = (i32, f64)> + '_ {
+        let min_page_index = self.min_page_index;
+        self.pages
+            .iter()
+            .enumerate()
+            .filter_map(|(position, page)| page.as_ref().map(|page| (position, page)))
+            .flat_map(move |(position, page)| {
+                let page_offset = (min_page_index + position as i32) << PAGE_LENGTH_SHIFT;
+                page.iter()
+                    .enumerate()
+                    .filter(|(_, count)| **count != 0.0)
+                    .map(move |(line_index, count)| (page_offset + line_index as i32, *count))
+            })
+    }
+}
+
+impl Default for PaginatedStore {
+    fn default() -> Self {
+        PaginatedStore::new()
+    }
+}
+
+impl Store for PaginatedStore {
+    fn add(&mut self, index: i32, count: f64) {
+        if count <= 0.0 {
+            return;
+        }
+        self.get_page_mut(PaginatedStore::page_index(index))[PaginatedStore::line_index(index)] +=
+            count;
+        self.total_count += count;
+        self.min_index = self.min_index.min(index);
+        self.max_index = self.max_index.max(index);
+    }
+
+    fn add_bin(&mut self, bin: (i32, f64)) {
+        if bin.1 == 0.0 {
+            return;
+        }
+        self.get_page_mut(PaginatedStore::page_index(bin.0))[PaginatedStore::line_index(bin.0)] +=
+            bin.1;
+        self.total_count += bin.1;
+        self.min_index = self.min_index.min(bin.0);
+        self.max_index = self.max_index.max(bin.0);
+    }
+
+    fn clear(&mut self) {
+        for page in self.pages.iter_mut().flatten() {
+            page.fill(0.0);
+        }
+        self.min_index = i32::MAX;
+        self.max_index = i32::MIN;
+        self.total_count = 0.0;
+    }
+
+    fn is_empty(&self) -> bool {
+        self.max_index < self.min_index
+    }
+
+    fn get_total_count(&self) -> f64 {
+        self.total_count
+    }
+
+    fn get_min_index(&self) -> i32 {
+        self.min_index
+    }
+
+    fn get_max_index(&self) -> i32 {
+        self.max_index
+    }
+
+    fn get_descending_stream(&self) -> Vec<(i32, f64)> {
+        self.bins().rev().collect()
+    }
+
+    fn get_ascending_stream(&self) -> Vec<(i32, f64)> {
+        self.bins().collect()
+    }
+
+    fn get_descending_iter(&self) -> StoreIter<'_> {
+        StoreIter::from_bins(self.bins().rev())
+    }
+
+    fn get_ascending_iter(&self) -> StoreIter<'_> {
+        StoreIter::from_bins(self.bins())
+    }
+
+    fn get_memory_footprint(&self) -> usize {
+        self.pages.capacity() * mem::size_of::<Option<Box<[f64; PAGE_LENGTH]>>>()
+            + self.pages.iter().flatten().count() * PAGE_LENGTH * mem::size_of::<f64>()
+    }
+}
+
+#[cfg(feature = "serde")]
+crate::serialization::impl_store_serde!(PaginatedStore);
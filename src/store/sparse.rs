@@ -0,0 +1,88 @@
+use super::*;
+use std::collections::BTreeMap;
+
+/// Keeps only the non-empty bins, ordered by index, so that the memory usage does not depend on
+/// the range of the indexes.
+#[derive(Clone)]
+pub struct SparseStore {
+    bins: BTreeMap<i32, f64>,
+}
+
+impl SparseStore {
+    pub fn new() -> Self {
+        SparseStore {
+            bins: BTreeMap::new(),
+        }
+    }
+}
+
+impl Default for SparseStore {
+    fn default() -> Self {
+        SparseStore::new()
+    }
+}
+
+impl Store for SparseStore {
+    fn add(&mut self, index: i32, count: f64) {
+        if count <= 0.0 {
+            return;
+        }
+        *self.bins.entry(index).or_insert(0.0) += count;
+    }
+
+    fn add_bin(&mut self, bin: (i32, f64)) {
+        if bin.1 == 0.0 {
+            return;
+        }
+        *self.bins.entry(bin.0).or_insert(0.0) += bin.1;
+    }
+
+    fn clear(&mut self) {
+        self.bins.clear();
+    }
+
+    fn is_empty(&self) -> bool {
+        self.bins.is_empty()
+    }
+
+    fn get_total_count(&self) -> f64 {
+        self.bins.values().sum()
+    }
+
+    fn get_min_index(&self) -> i32 {
+        match self.bins.first_key_value() {
+            Some((index, _)) => *index,
+            None => i32::MAX,
+        }
+    }
+
+    fn get_max_index(&self) -> i32 {
+        match self.bins.last_key_value() {
+            Some((index, _)) => *index,
+            None => i32::MIN,
+        }
+    }
+
+    fn get_descending_stream(&self) -> Vec<(i32, f64)> {
+        self.bins.iter().rev().map(|(i, c)| (*i, *c)).collect()
+    }
+
+    fn get_ascending_stream(&self) -> Vec<(i32, f64)> {
+        self.bins.iter().map(|(i, c)| (*i, *c)).collect()
+    }
+
+    fn get_descending_iter(&self) -> StoreIter<'_> {
+        StoreIter::from_bins(self.bins.iter().rev().map(|(i, c)| (*i, *c)))
+    }
+
+    fn get_ascending_iter(&self) -> StoreIter<'_> {
+        StoreIter::from_bins(self.bins.iter().map(|(i, c)| (*i, *c)))
+    }
+
+    fn get_memory_footprint(&self) -> usize {
+        sparse_memory_footprint(self.bins.len())
+    }
+}
+
+#[cfg(feature = "serde")]
+crate::serialization::impl_store_serde!(SparseStore);
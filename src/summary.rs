@@ -0,0 +1,290 @@
+use crate::error::Error;
+use crate::input::Input;
+use crate::output::Output;
+use crate::serde;
+use crate::sketch::Flag;
+
+/// The exact count, sum, minimum and maximum of the values added to a sketch. Once values have
+/// been subtracted, the minimum and maximum of the remaining ones are unknown and are NaN.
+#[derive(Clone, Copy, Debug)]
+pub struct SummaryStatistics {
+    count: f64,
+    sum: f64,
+    min: f64,
+    max: f64,
+}
+
+impl SummaryStatistics {
+    pub fn new() -> Self {
+        SummaryStatistics {
+            count: 0.0,
+            sum: 0.0,
+            min: f64::INFINITY,
+            max: f64::NEG_INFINITY,
+        }
+    }
+
+    #[cfg(test)]
+    pub fn with_values(count: f64, sum: f64, min: f64, max: f64) -> Self {
+        SummaryStatistics {
+            count,
+            sum,
+            min,
+            max,
+        }
+    }
+
+    pub fn add(&mut self, value: f64, count: f64) {
+        if count <= 0.0 {
+            return;
+        }
+        self.count += count;
+        self.sum += value * count;
+        if self.has_bounds() {
+            self.min = self.min.min(value);
+            self.max = self.max.max(value);
+        }
+    }
+
+    pub fn merge_with(&mut self, other: &SummaryStatistics) {
+        if other.count <= 0.0 {
+            return;
+        }
+        self.count += other.count;
+        self.sum += other.sum;
+        if self.has_bounds() && other.has_bounds() {
+            self.min = self.min.min(other.min);
+            self.max = self.max.max(other.max);
+        } else {
+            self.min = f64::NAN;
+            self.max = f64::NAN;
+        }
+    }
+
+    /// Removes the values of `other`. The minimum and maximum of the remaining values become
+    /// unknown, unless none remain.
+    pub fn subtract(&mut self, other: &SummaryStatistics) {
+        if other.count <= 0.0 {
+            return;
+        }
+        self.count -= other.count;
+        self.sum -= other.sum;
+        if self.count <= 0.0 {
+            self.clear();
+        } else {
+            self.min = f64::NAN;
+            self.max = f64::NAN;
+        }
+    }
+
+    /// Returns whether the minimum and maximum are known.
+    pub fn has_bounds(&self) -> bool {
+        !self.min.is_nan() && !self.max.is_nan()
+    }
+
+    pub fn scale(&mut self, factor: f64) {
+        if factor == 0.0 {
+            self.clear();
+            return;
+        }
+        self.count *= factor;
+        self.sum *= factor;
+    }
+
+    pub fn rescale_values(&mut self, multiplier: f64) {
+        self.sum *= multiplier;
+        self.min *= multiplier;
+        self.max *= multiplier;
+    }
+
+    pub fn clear(&mut self) {
+        *self = SummaryStatistics::new();
+    }
+
+    pub fn get_count(&self) -> f64 {
+        self.count
+    }
+
+    pub fn get_sum(&self) -> f64 {
+        self.sum
+    }
+
+    pub fn get_min(&self) -> f64 {
+        self.min
+    }
+
+    pub fn get_max(&self) -> f64 {
+        self.max
+    }
+
+    pub fn encode(&self, output: &mut Output) -> Result<(), Error> {
+        if self.count != 0.0 {
+            Flag::COUNT.encode(output)?;
+            serde::encode_var_double(output, self.count)?;
+            Flag::MIN.encode(output)?;
+            output.write_double_le(self.min)?;
+            Flag::MAX.encode(output)?;
+            output.write_double_le(self.max)?;
+        }
+        if self.sum != 0.0 {
+            Flag::SUM.encode(output)?;
+            output.write_double_le(self.sum)?;
+        }
+        Ok(())
+    }
+
+    pub fn encoded_len(&self) -> usize {
+        let mut len = 0;
+        if self.count != 0.0 {
+            len += 1 + serde::var_double_encoded_length(self.count) as usize;
+            len += 2 * (1 + 8);
+        }
+        if self.sum != 0.0 {
+            len += 1 + 8;
+        }
+        len
+    }
+
+    pub fn decode_flag(&mut self, input: &mut Input, flag: Flag) -> Result<(), Error> {
+        if flag == Flag::COUNT {
+            self.count = serde::decode_var_double(input)?;
+        } else if flag == Flag::SUM {
+            self.sum = input.read_double_le()?;
+        } else if flag == Flag::MIN {
+            self.min = input.read_double_le()?;
+        } else if flag == Flag::MAX {
+            self.max = input.read_double_le()?;
+        } else {
+            return Err(Error::UnknownFlag {
+                marker: flag.get_marker(),
+                section: "summary statistics",
+            });
+        }
+        Ok(())
+    }
+}
+
+impl PartialEq for SummaryStatistics {
+    fn eq(&self, other: &SummaryStatistics) -> bool {
+        let same_bound = |a: f64, b: f64| a == b || (a.is_nan() && b.is_nan());
+        self.count == other.count
+            && self.sum == other.sum
+            && same_bound(self.min, other.min)
+            && same_bound(self.max, other.max)
+    }
+}
+
+/// Empty statistics have infinite bounds and unknown bounds are NaN, which most self-describing
+/// formats cannot represent, so only finite bounds are written.
+#[cfg(feature = "serde")]
+#[derive(::serde::Serialize, ::serde::Deserialize)]
+#[serde(rename = "SummaryStatistics")]
+struct SummaryStatisticsFields {
+    count: f64,
+    sum: f64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    min: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    max: Option<f64>,
+}
+
+#[cfg(feature = "serde")]
+impl ::serde::Serialize for SummaryStatistics {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: ::serde::Serializer,
+    {
+        let fields = SummaryStatisticsFields {
+            count: self.count,
+            sum: self.sum,
+            min: Some(self.min).filter(|min| min.is_finite()),
+            max: Some(self.max).filter(|max| max.is_finite()),
+        };
+        ::serde::Serialize::serialize(&fields, serializer)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> ::serde::Deserialize<'de> for SummaryStatistics {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: ::serde::Deserializer<'de>,
+    {
+        let fields: SummaryStatisticsFields = ::serde::Deserialize::deserialize(deserializer)?;
+        let (min, max) = match (fields.min, fields.max) {
+            (Some(min), Some(max)) => (min, max),
+            _ if fields.count != 0.0 => (f64::NAN, f64::NAN),
+            _ => (f64::INFINITY, f64::NEG_INFINITY),
+        };
+        Ok(SummaryStatistics {
+            count: fields.count,
+            sum: fields.sum,
+            min,
+            max,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_summary_statistics_encode() {
+        let mut summary_statistics = SummaryStatistics::new();
+        summary_statistics.add(2.5, 3.0);
+        summary_statistics.add(-1.0, 0.5);
+
+        let mut output = Output::with_capacity(64);
+        summary_statistics.encode(&mut output).unwrap();
+        let bytes = output.trim();
+
+        let mut decoded = SummaryStatistics::new();
+        let mut input = Input::wrap(&bytes);
+        while input.has_remaining().unwrap() {
+            let flag = Flag::decode(&mut input).unwrap();
+            decoded.decode_flag(&mut input, flag).unwrap();
+        }
+        assert_eq!(summary_statistics, decoded);
+        assert_eq!(3.5, decoded.get_count());
+        assert_eq!(7.0, decoded.get_sum());
+        assert_eq!(-1.0, decoded.get_min());
+        assert_eq!(2.5, decoded.get_max());
+    }
+
+    #[test]
+    fn test_summary_statistics_subtract() {
+        let mut summary_statistics = SummaryStatistics::with_values(5.0, 20.0, 1.0, 9.0);
+        summary_statistics.subtract(&SummaryStatistics::with_values(2.0, 3.0, 1.0, 2.0));
+        assert_eq!(3.0, summary_statistics.get_count());
+        assert_eq!(17.0, summary_statistics.get_sum());
+        assert!(!summary_statistics.has_bounds());
+
+        summary_statistics.add(20.0, 1.0);
+        summary_statistics.merge_with(&SummaryStatistics::with_values(1.0, 0.5, 0.5, 0.5));
+        assert_eq!(
+            SummaryStatistics::with_values(5.0, 37.5, f64::NAN, f64::NAN),
+            summary_statistics
+        );
+
+        summary_statistics.subtract(&SummaryStatistics::with_values(5.0, 37.5, 0.5, 20.0));
+        assert_eq!(SummaryStatistics::new(), summary_statistics);
+    }
+
+    #[test]
+    fn test_summary_statistics_merge() {
+        let mut summary_statistics = SummaryStatistics::new();
+        summary_statistics.add(1.0, 1.0);
+        summary_statistics.merge_with(&SummaryStatistics::new());
+        assert_eq!(
+            SummaryStatistics::with_values(1.0, 1.0, 1.0, 1.0),
+            summary_statistics
+        );
+
+        summary_statistics.merge_with(&SummaryStatistics::with_values(2.0, 10.0, 4.0, 6.0));
+        assert_eq!(
+            SummaryStatistics::with_values(3.0, 11.0, 1.0, 6.0),
+            summary_statistics
+        );
+    }
+}
@@ -1,4 +1,8 @@
-use sketches_rust::DDSketch;
+use sketches_rust::{
+    CollapsingLowestDenseStore, ConcurrentDDSketch, DDSketch, DDSketchBuilder, DecodeOptions,
+    IndexMapping, IndexMappingLayout, PaginatedStore, SparseStore, StoreKind, SubtractionPolicy,
+    UnboundedSizeDenseStore,
+};
 
 #[test]
 #[should_panic]
@@ -117,7 +121,7 @@ fn test_sketch_merge_1() {
         sketch2.accept(i as f64);
     }
 
-    sketch1.merge_with(&mut sketch2).unwrap();
+    sketch1.merge_with(&sketch2).unwrap();
     assert_eq!(300.0, sketch1.get_count());
 }
 
@@ -135,13 +139,12 @@ fn test_sketch_merge_2() {
         sketch2.accept(i as f64);
     }
 
-    sketch1.merge_with(&mut sketch2).unwrap();
+    sketch1.merge_with(&sketch2).unwrap();
     assert_eq!(300.0, sketch1.get_count());
 }
 
 #[test]
-#[should_panic]
-fn test_sketch_merge_panic() {
+fn test_sketch_merge_different_mappings() {
     let mut sketch1 = DDSketch::collapsing_lowest_dense(1e-2, 50).unwrap();
     for i in -99..101 {
         sketch1.accept(i as f64);
@@ -152,7 +155,85 @@ fn test_sketch_merge_panic() {
         sketch2.accept(i as f64);
     }
 
-    sketch1.merge_with(&mut sketch2).unwrap();
+    sketch1.merge_with(&sketch2).unwrap();
+    assert!(f64::abs(sketch1.get_count() - 300.0) <= 1e-9);
+}
+
+#[test]
+fn test_sketch_merge_different_mappings_accuracy() {
+    let values: Vec<f64> = (1..5000).map(|i| f64::powf(1.003, i as f64)).collect();
+    let targets = [
+        DDSketch::unbounded_dense(1e-2).unwrap(),
+        DDSketch::logarithmic_unbounded_size_dense_store(3e-2).unwrap(),
+        DDSketch::sparse(5e-3).unwrap(),
+    ];
+    let sources = [
+        DDSketch::logarithmic_paginated(2e-2).unwrap(),
+        DDSketch::unbounded_dense(5e-3).unwrap(),
+        DDSketch::sparse(1e-2).unwrap(),
+    ];
+    for target in &targets {
+        for source in &sources {
+            let mut merged = target.clone();
+            let mut other = source.clone();
+            for (i, value) in values.iter().enumerate() {
+                if i % 2 == 0 {
+                    merged.accept(*value);
+                    merged.accept(-*value);
+                } else {
+                    other.accept(*value);
+                    other.accept(-*value);
+                }
+            }
+            merged.merge_with(&other).unwrap();
+            assert!(f64::abs(merged.get_count() - 2.0 * values.len() as f64) <= 1e-6);
+
+            let accuracy = 2.0 * source.get_index_mapping().relative_accuracy()
+                + target.get_index_mapping().relative_accuracy();
+            let mut sorted: Vec<f64> = values.iter().flat_map(|v| [-*v, *v]).collect();
+            sorted.sort_by(|a, b| a.total_cmp(b));
+            for quantile in [0.0, 0.01, 0.1, 0.3, 0.5, 0.7, 0.9, 0.99, 1.0] {
+                let expected = sorted[(quantile * (sorted.len() - 1) as f64) as usize];
+                let actual = merged.get_value_at_quantile(quantile).unwrap();
+                assert!(f64::abs(actual - expected) <= accuracy * f64::abs(expected) * 1.01);
+            }
+        }
+    }
+}
+
+#[test]
+fn test_sketch_convert_to_mapping() {
+    let mut sketch = DDSketch::logarithmic_collapsing_lowest_dense(2e-2, 2048)
+        .unwrap()
+        .with_exact_summary_statistics()
+        .unwrap();
+    for i in -500..1000 {
+        sketch.accept(i as f64 / 7.0);
+    }
+    assert_eq!(
+        sketch,
+        sketch.convert_to_mapping(sketch.get_index_mapping())
+    );
+
+    let index_mapping =
+        IndexMapping::with_relative_accuracy(IndexMappingLayout::LogCubic, 1e-2).unwrap();
+    let converted = sketch.convert_to_mapping(&index_mapping);
+    assert_eq!(&index_mapping, converted.get_index_mapping());
+    assert!(f64::abs(converted.get_count() - sketch.get_count()) <= 1e-9);
+    assert_eq!(sketch.get_sum(), converted.get_sum());
+    assert_eq!(sketch.get_min(), converted.get_min());
+    assert_eq!(sketch.get_max(), converted.get_max());
+
+    let mut direct = converted.clone();
+    direct.clear();
+    direct.merge_with(&sketch).unwrap();
+    assert_eq!(direct, converted);
+
+    for quantile in [0.01, 0.1, 0.25, 0.5, 0.75, 0.9, 0.99] {
+        let expected = sketch.get_value_at_quantile(quantile).unwrap();
+        let actual = converted.get_value_at_quantile(quantile).unwrap();
+        assert!(f64::abs(actual - expected) <= 6e-2 * f64::abs(expected));
+    }
 }
 
 #[test]
@@ -199,13 +280,12 @@ fn test_sketch_decode_4() {
         3, 0, 0, 0, 3, 0, 2, 0, 0, 3, 3, 2, 2, 3, 3, 2, 0, 0, 0, 0, 2, 0, 2, 2, 2, 4, 4, 132, 64,
         0, 4, 2, 0, 2, 2, 3, 132, 64, 4, 132, 64, 4, 2, 2, 0, 6, 4, 6, 132, 64, 2, 6,
     ];
-    let mut sketch = DDSketch::decode(&input).unwrap();
+    let sketch = DDSketch::decode(&input).unwrap();
     assert_eq!(sketch.get_count(), 100.0);
 }
 
 #[test]
-#[should_panic]
-fn test_sketch_decode_panic_1() {
+fn test_sketch_decode_different_mapping_1() {
     let input = vec![
         14, 100, 244, 7, 173, 131, 165, 240, 63, 0, 0, 0, 0, 0, 0, 0, 0, 5, 21, 0, 140, 48, 34,
         150, 241, 16, 20, 148, 191, 96, 14, 142, 62, 12, 139, 16, 10, 134, 96, 8, 3, 6, 2, 6, 2, 6,
@@ -213,11 +293,11 @@ fn test_sketch_decode_panic_1() {
     ];
     let mut sketch = DDSketch::collapsing_lowest_dense(1e-2, 50).unwrap();
     sketch.decode_and_merge_with(&input).unwrap();
+    assert!(f64::abs(sketch.get_count() - 4538.0) <= 1e-9);
 }
 
 #[test]
-#[should_panic]
-fn test_sketch_decode_panic_2() {
+fn test_sketch_decode_different_mapping_2() {
     let input = vec![
         2, 42, 120, 57, 5, 47, 167, 240, 63, 0, 0, 0, 0, 0, 0, 0, 0, 13, 50, 130, 1, 2, 136, 32, 0,
         3, 0, 0, 0, 3, 0, 2, 0, 0, 3, 3, 2, 2, 3, 3, 2, 0, 0, 0, 0, 2, 0, 2, 2, 2, 4, 4, 132, 64,
@@ -225,6 +305,7 @@ fn test_sketch_decode_panic_2() {
     ];
     let mut sketch = DDSketch::collapsing_highest_dense(2e-2, 50).unwrap();
     sketch.decode_and_merge_with(&input).unwrap();
+    assert!(f64::abs(sketch.get_count() - 100.0) <= 1e-9);
 }
 
 #[test]
@@ -238,7 +319,7 @@ fn test_sketch_encode() {
     println!("encode: {:?}", sketch1.encode().unwrap());
     let mut sketch2 = DDSketch::unbounded_dense(2e-2).unwrap();
     sketch2
-        .decode_and_merge_with(&sketch1.encode().unwrap())
+        .decode_and_merge_with(sketch1.encode().unwrap())
         .unwrap();
     assert_eq!(5.0, sketch2.get_count());
 }
@@ -258,3 +339,1863 @@ fn test_sketch_create() {
     let mut sketch6: DDSketch = DDSketch::logarithmic_unbounded_size_dense_store(2e-2).unwrap();
     sketch6.accept(1.0);
 }
+
+fn all_sketches(relative_accuracy: f64, max_num_bins: usize) -> Vec<DDSketch> {
+    vec![
+        DDSketch::collapsing_lowest_dense(relative_accuracy, max_num_bins).unwrap(),
+        DDSketch::collapsing_highest_dense(relative_accuracy, max_num_bins).unwrap(),
+        DDSketch::unbounded_dense(relative_accuracy).unwrap(),
+        DDSketch::logarithmic_collapsing_lowest_dense(relative_accuracy, max_num_bins).unwrap(),
+        DDSketch::logarithmic_collapsing_highest_dense(relative_accuracy, max_num_bins).unwrap(),
+        DDSketch::logarithmic_unbounded_size_dense_store(relative_accuracy).unwrap(),
+        DDSketch::paginated(relative_accuracy).unwrap(),
+        DDSketch::logarithmic_paginated(relative_accuracy).unwrap(),
+        DDSketch::sparse(relative_accuracy).unwrap(),
+        DDSketch::collapsing_lowest_sparse(relative_accuracy, max_num_bins).unwrap(),
+        DDSketch::logarithmic_sparse(relative_accuracy).unwrap(),
+        DDSketch::logarithmic_collapsing_lowest_sparse(relative_accuracy, max_num_bins).unwrap(),
+    ]
+}
+
+#[test]
+fn test_sketch_accept_with_count() {
+    let values = [-12.5, -1.0, 0.0, 0.5, 1.0, 3.0, 42.0, 1000.0];
+    let weighted = all_sketches(2e-2, 2048);
+    let repeated = all_sketches(2e-2, 2048);
+    for (mut weighted, mut repeated) in weighted.into_iter().zip(repeated) {
+        for (i, value) in values.iter().enumerate() {
+            let count = (i + 1) as f64;
+            weighted.accept_with_count(*value, count).unwrap();
+            for _ in 0..(i + 1) {
+                repeated.accept(*value);
+            }
+        }
+
+        assert_eq!(36.0, weighted.get_count());
+        assert_eq!(repeated.get_count(), weighted.get_count());
+        let expected_sum = repeated.get_sum().unwrap();
+        assert!(f64::abs(weighted.get_sum().unwrap() - expected_sum) <= 1e-9 * expected_sum);
+        for quantile in [0.0, 0.1, 0.25, 0.5, 0.75, 0.9, 0.99, 1.0] {
+            assert_eq!(
+                repeated.get_value_at_quantile(quantile),
+                weighted.get_value_at_quantile(quantile)
+            );
+        }
+    }
+}
+
+#[test]
+fn test_sketch_accept_with_fractional_and_large_count() {
+    for mut sketch in all_sketches(2e-2, 100) {
+        sketch.accept_with_count(1.0, 0.25).unwrap();
+        sketch.accept_with_count(2.0, 0.5).unwrap();
+        sketch.accept_with_count(0.0, 0.75).unwrap();
+        sketch.accept_with_count(-3.0, 1e12).unwrap();
+        sketch.accept_with_count(5.0, 0.0).unwrap();
+
+        assert_eq!(1e12 + 1.5, sketch.get_count());
+        assert!((f64::abs(sketch.get_value_at_quantile(0.5).unwrap() + 3.0) / 3.0) < 0.021);
+        assert!((f64::abs(sketch.get_max().unwrap() - 2.0) / 2.0) < 0.021);
+    }
+}
+
+#[test]
+fn test_sketch_accept_with_invalid_count() {
+    for mut sketch in all_sketches(2e-2, 100) {
+        assert!(sketch.accept_with_count(1.0, -1.0).is_err());
+        assert!(sketch.accept_with_count(1.0, f64::NAN).is_err());
+        assert!(sketch.accept_with_count(1.0, f64::INFINITY).is_err());
+        assert!(sketch.accept_with_count(1.0, f64::NEG_INFINITY).is_err());
+        assert!(sketch.is_empty());
+    }
+}
+
+#[test]
+fn test_sketch_exact_summary_statistics() {
+    for sketch in all_sketches(2e-2, 100) {
+        let mut sketch = sketch.with_exact_summary_statistics().unwrap();
+        assert!(sketch.has_exact_summary_statistics());
+        assert_eq!(None, sketch.get_sum());
+        assert_eq!(None, sketch.get_max());
+        sketch.accept(-3.7);
+        sketch.accept(0.0);
+        sketch.accept(1.3);
+        sketch.accept_with_count(97.1, 2.0).unwrap();
+
+        assert_eq!(5.0, sketch.get_count());
+        assert_eq!(Some(-3.7), sketch.get_min());
+        assert_eq!(Some(97.1), sketch.get_max());
+        assert_eq!(Some(-3.7 + 1.3 + 97.1 * 2.0), sketch.get_sum());
+        assert_eq!(Some((-3.7 + 1.3 + 97.1 * 2.0) / 5.0), sketch.get_average());
+
+        sketch.accept(f64::NAN);
+        sketch.accept_with_count(f64::NAN, 3.0).unwrap();
+        assert_eq!(5.0, sketch.get_count());
+        assert_eq!(Some(-3.7), sketch.get_min());
+        assert_eq!(Some(97.1), sketch.get_max());
+        assert_eq!(Some(-3.7 + 1.3 + 97.1 * 2.0), sketch.get_sum());
+
+        sketch.clear();
+        assert_eq!(None, sketch.get_min());
+        assert_eq!(None, sketch.get_sum());
+    }
+    for mut sketch in all_sketches(2e-2, 100) {
+        sketch.accept(1.0);
+        assert!(matches!(
+            sketch.with_exact_summary_statistics(),
+            Err(sketches_rust::Error::InvalidArgument(_))
+        ));
+    }
+}
+
+#[test]
+fn test_sketch_exact_summary_statistics_encode() {
+    let mut sketch1 = DDSketch::unbounded_dense(2e-2)
+        .unwrap()
+        .with_exact_summary_statistics()
+        .unwrap();
+    for i in -99..101 {
+        sketch1.accept(i as f64 + 0.1);
+    }
+
+    let sketch2 = DDSketch::decode(sketch1.encode().unwrap()).unwrap();
+    assert!(sketch2.has_exact_summary_statistics());
+    assert_eq!(sketch1.get_count(), sketch2.get_count());
+    assert_eq!(sketch1.get_sum(), sketch2.get_sum());
+    assert_eq!(Some(-98.9), sketch2.get_min());
+    assert_eq!(Some(100.1), sketch2.get_max());
+
+    let mut sketch3 = DDSketch::unbounded_dense(2e-2)
+        .unwrap()
+        .with_exact_summary_statistics()
+        .unwrap();
+    sketch3.accept(250.5);
+    sketch3
+        .decode_and_merge_with(sketch1.encode().unwrap())
+        .unwrap();
+    assert_eq!(201.0, sketch3.get_count());
+    assert_eq!(Some(-98.9), sketch3.get_min());
+    assert_eq!(Some(250.5), sketch3.get_max());
+    assert_eq!(Some(sketch1.get_sum().unwrap() + 250.5), sketch3.get_sum());
+
+    // Sketches without exact statistics ignore them.
+    let mut sketch4 = DDSketch::unbounded_dense(2e-2).unwrap();
+    sketch4
+        .decode_and_merge_with(sketch1.encode().unwrap())
+        .unwrap();
+    assert!(!sketch4.has_exact_summary_statistics());
+    assert_eq!(200.0, sketch4.get_count());
+    assert!((f64::abs(sketch4.get_max().unwrap() - 100.1) / 100.1) <= 2e-2);
+
+    // Payloads without exact statistics cannot feed a sketch that tracks them.
+    let mut sketch5 = DDSketch::unbounded_dense(2e-2)
+        .unwrap()
+        .with_exact_summary_statistics()
+        .unwrap();
+    assert!(sketch5
+        .decode_and_merge_with(sketch4.encode().unwrap())
+        .is_err());
+}
+
+#[test]
+fn test_sketch_exact_summary_statistics_merge() {
+    let mut sketch1 = DDSketch::collapsing_lowest_dense(2e-2, 50)
+        .unwrap()
+        .with_exact_summary_statistics()
+        .unwrap();
+    for i in -99..101 {
+        sketch1.accept(i as f64);
+    }
+
+    let mut sketch2 = DDSketch::collapsing_lowest_dense(2e-2, 50)
+        .unwrap()
+        .with_exact_summary_statistics()
+        .unwrap();
+    for i in 100..200 {
+        sketch2.accept(i as f64);
+    }
+
+    sketch1.merge_with(&sketch2).unwrap();
+    assert_eq!(300.0, sketch1.get_count());
+    assert_eq!(Some(-99.0), sketch1.get_min());
+    assert_eq!(Some(199.0), sketch1.get_max());
+    assert_eq!(Some(100.0 + 14950.0), sketch1.get_sum());
+
+    let mut sketch3 = DDSketch::collapsing_lowest_dense(2e-2, 50).unwrap();
+    sketch3.accept(1.0);
+    assert!(sketch1.merge_with(&sketch3).is_err());
+    assert!(sketch3.merge_with(&sketch1).is_ok());
+}
+
+#[test]
+fn test_sketch_decode_and_merge_into_collapsing_store() {
+    let mut wide = DDSketch::collapsing_lowest_dense(2e-2, 100).unwrap();
+    let mut unbounded = DDSketch::unbounded_dense(2e-2).unwrap();
+    for exponent in -300..300 {
+        wide.accept(10f64.powi(exponent));
+        unbounded.accept(10f64.powi(exponent));
+    }
+
+    // Bins encoded with the same mapping are decoded into the collapsing store itself, whose span
+    // stays within the limit, rather than into a temporary store spanning every encoded index.
+    let options = DecodeOptions::new().max_index_span(200);
+    let mut sketch = DDSketch::collapsing_lowest_dense(2e-2, 100).unwrap();
+    sketch.accept(1.0);
+    let mut merged = sketch.clone();
+    merged.merge_with(&wide).unwrap();
+    sketch
+        .decode_and_merge_with_options(unbounded.encode().unwrap(), &options)
+        .unwrap();
+    assert_eq!(merged, sketch);
+
+    // Bins that are re-binned are still decoded into a temporary store.
+    let mut other_mapping = DDSketch::logarithmic_collapsing_lowest_dense(2e-2, 100).unwrap();
+    assert!(other_mapping
+        .decode_and_merge_with_options(unbounded.encode().unwrap(), &options)
+        .is_err());
+}
+
+#[test]
+fn test_sketch_sparse_wide_range() {
+    let values = [1e-300, 2e-9, 0.35, 1.0, 250.0, 3e12, 7e250];
+    let mut sparse = DDSketch::sparse(2e-2).unwrap();
+    let mut dense = DDSketch::unbounded_dense(2e-2).unwrap();
+    for value in values {
+        sparse.accept(value);
+        sparse.accept(-value);
+        dense.accept(value);
+        dense.accept(-value);
+    }
+
+    assert_eq!(14.0, sparse.get_count());
+    for quantile in [0.0, 0.2, 0.5, 0.8, 1.0] {
+        assert_eq!(
+            dense.get_value_at_quantile(quantile),
+            sparse.get_value_at_quantile(quantile)
+        );
+    }
+    assert!((f64::abs(sparse.get_max().unwrap() - 7e250) / 7e250) <= 2e-2);
+    assert_eq!(dense.encode().unwrap(), sparse.encode().unwrap());
+
+    let mut decoded = DDSketch::sparse(2e-2).unwrap();
+    decoded
+        .decode_and_merge_with(sparse.encode().unwrap())
+        .unwrap();
+    assert_eq!(sparse.encode().unwrap(), decoded.encode().unwrap());
+}
+
+#[test]
+fn test_sketch_collapsing_lowest_sparse() {
+    let mut sketch = DDSketch::collapsing_lowest_sparse(2e-2, 10).unwrap();
+    for i in 1..1001 {
+        sketch.accept(i as f64);
+    }
+    sketch.accept(1e200);
+
+    assert_eq!(1001.0, sketch.get_count());
+    assert!((f64::abs(sketch.get_max().unwrap() - 1e200) / 1e200) <= 2e-2);
+    assert!((f64::abs(sketch.get_value_at_quantile(0.999).unwrap() - 1000.0) / 1000.0) <= 2e-2);
+}
+
+#[test]
+#[should_panic]
+fn test_sketch_collapsing_lowest_sparse_panic() {
+    DDSketch::collapsing_lowest_sparse(2e-2, 0).unwrap();
+}
+
+#[test]
+fn test_sketch_paginated() {
+    let mut paginated = DDSketch::paginated(2e-2).unwrap();
+    let mut dense = DDSketch::unbounded_dense(2e-2).unwrap();
+    for i in -500..5000 {
+        let value = (i as f64) * 1.7;
+        paginated.accept(value);
+        dense.accept(value);
+    }
+    paginated.accept(1e-200);
+    dense.accept(1e-200);
+    paginated.accept(1e200);
+    dense.accept(1e200);
+
+    assert_eq!(dense.get_count(), paginated.get_count());
+    for quantile in [0.0, 0.01, 0.25, 0.5, 0.75, 0.99, 1.0] {
+        assert_eq!(
+            dense.get_value_at_quantile(quantile),
+            paginated.get_value_at_quantile(quantile)
+        );
+    }
+    assert_eq!(dense.encode().unwrap(), paginated.encode().unwrap());
+
+    let mut decoded = DDSketch::paginated(2e-2).unwrap();
+    decoded
+        .decode_and_merge_with(paginated.encode().unwrap())
+        .unwrap();
+    assert_eq!(paginated.encode().unwrap(), decoded.encode().unwrap());
+
+    let mut merged = DDSketch::paginated(2e-2).unwrap();
+    merged.merge_with(&dense).unwrap();
+    assert_eq!(dense.encode().unwrap(), merged.encode().unwrap());
+}
+
+#[test]
+fn test_sketch_decode_interpolated_layouts() {
+    // Sketches with a LogLinear (1), LogQuadratic (2) and LogQuartic (4) index mapping, gamma
+    // 1.02 and no index offset, holding the values 1.0, 2.0 and 3.0.
+    for layout in [1u8, 2, 4] {
+        let mut input = vec![0b10 | (layout << 2)];
+        input.extend(1.02f64.to_le_bytes());
+        input.extend(0.0f64.to_le_bytes());
+        let mapping_length = input.len();
+        // IndexDeltasAndCounts positive store with three bins of count 1 at indexes 0, 40, 60.
+        input.extend([0b01 | (1 << 2), 3, 0, 2, 80, 2, 40, 2]);
+
+        let sketch = DDSketch::decode(&input).unwrap();
+        assert_eq!(3.0, sketch.get_count());
+        assert_eq!(
+            input[..mapping_length],
+            sketch.encode().unwrap()[..mapping_length]
+        );
+    }
+}
+
+// The reference bytes are what sketches-go's `DDSketch.ToProto` yields for `NewDefaultDDSketch(0.02)`
+// holding the same values, marshalled by the Go protobuf runtime. They were derived by hand from
+// that code, not captured from a run of it: a dense store is written as contiguousBinCounts, from
+// its minimum to its maximum index, and proto3 omits the fields that hold zero values.
+#[cfg(feature = "protobuf")]
+#[test]
+fn test_sketch_to_protobuf() {
+    let mut sketch = DDSketch::logarithmic_unbounded_size_dense_store(2e-2).unwrap();
+    for value in [1.0, 2.0, 3.0, 4.0, 5.0, -1.0, 0.0] {
+        sketch.accept(value);
+    }
+    // mapping { gamma: 1.02 / 0.98 }
+    let mut reference = vec![10, 9, 9, 42, 120, 57, 5, 47, 167, 240, 63];
+    // positiveValues { contiguousBinCounts: [1, 0 x 16, 1, 0 x 9, 1, 0 x 6, 1, 0 x 5, 1] }
+    reference.extend_from_slice(&[18, 203, 2, 18, 200, 2]);
+    for index in 0..=40 {
+        let count: f64 = if [0, 17, 27, 34, 40].contains(&index) {
+            1.0
+        } else {
+            0.0
+        };
+        reference.extend_from_slice(&count.to_le_bytes());
+    }
+    // negativeValues { contiguousBinCounts: [1] } zeroCount: 1
+    reference.extend_from_slice(&[26, 10, 18, 8, 0, 0, 0, 0, 0, 0, 240, 63]);
+    reference.extend_from_slice(&[33, 0, 0, 0, 0, 0, 0, 240, 63]);
+
+    let decoded = DDSketch::from_protobuf(&reference).unwrap();
+    assert_eq!(sketch, decoded);
+    assert_eq!(7.0, decoded.get_count());
+
+    let encoded = sketch.to_protobuf().unwrap();
+    assert!(encoded.len() < reference.len());
+    assert_eq!(sketch, DDSketch::from_protobuf(&encoded).unwrap());
+}
+
+#[cfg(feature = "protobuf")]
+#[test]
+fn test_sketch_from_protobuf() {
+    // mapping { gamma: 1.5, indexOffset: 0, interpolation: CUBIC }
+    // positiveValues { contiguousBinCounts: [1, 0, 2], contiguousBinIndexOffset: -2 }
+    // zeroCount: 3
+    let input = vec![
+        10, 20, 9, 0, 0, 0, 0, 0, 0, 248, 63, 17, 0, 0, 0, 0, 0, 0, 0, 0, 24, 3, 18, 28, 18, 24, 0,
+        0, 0, 0, 0, 0, 240, 63, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 64, 24, 3, 33, 0, 0,
+        0, 0, 0, 0, 8, 64,
+    ];
+    let sketch = DDSketch::from_protobuf(&input).unwrap();
+    assert_eq!(6.0, sketch.get_count());
+    assert_eq!(0.0, sketch.get_value_at_quantile(0.4).unwrap());
+    let max = sketch.get_value_at_quantile(1.0).unwrap();
+    assert!(max > 1.0 && max < 1.5);
+
+    let decoded = DDSketch::from_protobuf(sketch.to_protobuf().unwrap()).unwrap();
+    assert_eq!(sketch.encode().unwrap(), decoded.encode().unwrap());
+    assert_eq!(6.0, decoded.get_count());
+}
+
+#[cfg(feature = "protobuf")]
+#[test]
+fn test_sketch_protobuf_round_trip() {
+    for mut sketch in all_sketches(2e-2, 1000) {
+        for i in -500..1000 {
+            sketch.accept(i as f64 * 0.37);
+        }
+        let decoded = DDSketch::from_protobuf(sketch.to_protobuf().unwrap()).unwrap();
+        assert_eq!(sketch.get_count(), decoded.get_count());
+        for quantile in [0.0, 0.1, 0.5, 0.9, 0.99, 1.0] {
+            assert_eq!(
+                sketch.get_value_at_quantile(quantile),
+                decoded.get_value_at_quantile(quantile)
+            );
+        }
+    }
+}
+
+#[cfg(feature = "protobuf")]
+#[test]
+fn test_sketch_protobuf_hostile() {
+    use sketches_rust::Error;
+
+    // mapping { gamma: 1.02 }
+    let mapping = [10, 9, 9, 82, 184, 30, 133, 235, 81, 240, 63];
+    let strict = DecodeOptions::new()
+        .max_num_bins(1000)
+        .max_index_span(10_000)
+        .reject_invalid_counts(true);
+
+    // positiveValues { contiguousBinCounts: [1, 1], contiguousBinIndexOffset: 2^31 - 1 }
+    let mut overflow = mapping.to_vec();
+    overflow.extend_from_slice(&[18, 24, 18, 16, 0, 0, 0, 0, 0, 0, 240, 63]);
+    overflow.extend_from_slice(&[0, 0, 0, 0, 0, 0, 240, 63, 24, 254, 255, 255, 255, 15]);
+    assert!(matches!(
+        decode_error_cause(DDSketch::from_protobuf(&overflow).unwrap_err()),
+        Error::IndexOverflow(index) if index == i32::MAX as i64 + 1
+    ));
+
+    // positiveValues { binCounts: { -2^31: 1 }, binCounts: { 2^31 - 1: 1 } }
+    let mut wide = mapping.to_vec();
+    wide.extend_from_slice(&[18, 34, 10, 15, 8, 255, 255, 255, 255, 15, 17]);
+    wide.extend_from_slice(&[
+        0, 0, 0, 0, 0, 0, 240, 63, 10, 15, 8, 254, 255, 255, 255, 15, 17,
+    ]);
+    wide.extend_from_slice(&[0, 0, 0, 0, 0, 0, 240, 63]);
+    assert!(matches!(
+        decode_error_cause(DDSketch::from_protobuf_with_options(&wide, &strict).unwrap_err()),
+        Error::LimitExceeded {
+            limit: "index span",
+            max: 10_000
+        }
+    ));
+
+    // positiveValues { contiguousBinCounts: [0 x 1001] }
+    let mut many_bins = mapping.to_vec();
+    many_bins.extend_from_slice(&[18, 203, 62, 18, 200, 62]);
+    many_bins.resize(many_bins.len() + 8 * 1001, 0);
+    assert_eq!(
+        0.0,
+        DDSketch::from_protobuf(&many_bins).unwrap().get_count()
+    );
+    assert!(matches!(
+        decode_error_cause(DDSketch::from_protobuf_with_options(&many_bins, &strict).unwrap_err()),
+        Error::LimitExceeded {
+            limit: "number of bins",
+            max: 1000
+        }
+    ));
+
+    // positiveValues { binCounts: { 0: NaN } } and positiveValues { binCounts: { 0: -1 } }
+    for count in [[0, 0, 0, 0, 0, 0, 248, 127], [0, 0, 0, 0, 0, 0, 240, 191]] {
+        let mut invalid_count = mapping.to_vec();
+        invalid_count.extend_from_slice(&[18, 13, 10, 11, 8, 0, 17]);
+        invalid_count.extend_from_slice(&count);
+        assert!(DDSketch::from_protobuf(&invalid_count).is_ok());
+        let error = DDSketch::from_protobuf_with_options(&invalid_count, &strict).unwrap_err();
+        assert!(matches!(error, Error::Decode { position: 11, .. }));
+        assert!(matches!(decode_error_cause(error), Error::InvalidCount(_)));
+    }
+
+    // zeroCount: -1
+    let mut negative_zero_count = mapping.to_vec();
+    negative_zero_count.extend_from_slice(&[33, 0, 0, 0, 0, 0, 0, 240, 191]);
+    assert!(matches!(
+        decode_error_cause(
+            DDSketch::from_protobuf_with_options(&negative_zero_count, &strict).unwrap_err()
+        ),
+        Error::InvalidCount(count) if count == -1.0
+    ));
+}
+
+#[cfg(feature = "protobuf")]
+#[test]
+fn test_sketch_protobuf_invalid() {
+    // A LogQuartic mapping has no protobuf interpolation value.
+    let mut input = vec![0b10 | (4 << 2)];
+    input.extend(1.02f64.to_le_bytes());
+    input.extend(0.0f64.to_le_bytes());
+    assert!(DDSketch::decode(&input).unwrap().to_protobuf().is_err());
+
+    // Missing mapping, truncated message and unknown interpolation.
+    assert!(DDSketch::from_protobuf(vec![33, 0, 0, 0, 0, 0, 0, 240, 63]).is_err());
+    assert!(DDSketch::from_protobuf(vec![10, 18, 9, 42, 120]).is_err());
+    assert!(DDSketch::from_protobuf(vec![10, 11, 9, 0, 0, 0, 0, 0, 0, 248, 63, 24, 9]).is_err());
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn test_sketch_serde_json() {
+    let mut sketch = DDSketch::logarithmic_unbounded_size_dense_store(2e-2).unwrap();
+    for value in [1.0, 2.0, 2.0, -1.0, 0.0] {
+        sketch.accept(value);
+    }
+    let json = serde_json::to_string(&sketch).unwrap();
+    assert_eq!(
+        r#"{"mapping":{"layout":"LOG","gamma":1.0408163265306123,"index_offset":0.0},"zero_count":1.0,"positive_bins":{"0":1.0,"17":2.0},"negative_bins":{"0":1.0}}"#,
+        json
+    );
+
+    let decoded: DDSketch = serde_json::from_str(&json).unwrap();
+    assert_eq!(sketch.encode().unwrap(), decoded.encode().unwrap());
+    assert_eq!(5.0, decoded.get_count());
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn test_sketch_serde_round_trip() {
+    let sketches = all_sketches(2e-2, 1000)
+        .into_iter()
+        .chain([DDSketch::unbounded_dense(2e-2)
+            .unwrap()
+            .with_exact_summary_statistics()
+            .unwrap()]);
+    for mut sketch in sketches {
+        for i in -500..1000 {
+            sketch.accept(i as f64 * 0.37);
+        }
+
+        let json = serde_json::to_string(&sketch).unwrap();
+        let from_json: DDSketch = serde_json::from_str(&json).unwrap();
+        let compact = rmp_serde::to_vec(&sketch).unwrap();
+        let from_compact: DDSketch = rmp_serde::from_slice(&compact).unwrap();
+
+        for decoded in [&from_json, &from_compact] {
+            assert_eq!(
+                sketch.has_exact_summary_statistics(),
+                decoded.has_exact_summary_statistics()
+            );
+            assert_eq!(sketch.get_count(), decoded.get_count());
+            assert_eq!(sketch.get_sum(), decoded.get_sum());
+            for quantile in [0.0, 0.1, 0.5, 0.9, 0.99, 1.0] {
+                assert_eq!(
+                    sketch.get_value_at_quantile(quantile),
+                    decoded.get_value_at_quantile(quantile)
+                );
+            }
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn test_sketch_serde_compact_is_encoded_bytes() {
+    let mut sketch = DDSketch::unbounded_dense(2e-2).unwrap();
+    for value in [1.0, 2.0, 3.0] {
+        sketch.accept(value);
+    }
+    let encoded = sketch.encode().unwrap();
+    let compact = rmp_serde::to_vec(&sketch).unwrap();
+    // MessagePack bin 8 header followed by the encoded sketch.
+    assert_eq!([0xC4, encoded.len() as u8], compact[..2]);
+    assert_eq!(encoded, compact[2..]);
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn test_sketch_serde_invalid() {
+    let mapping = r#""mapping":{"layout":"LOG","gamma":1.02,"index_offset":0.0}"#;
+    for json in [
+        format!(r#"{{{},"zero_count":-1.0,"positive_bins":{{}},"negative_bins":{{}}}}"#, mapping),
+        format!(r#"{{{},"zero_count":0.0,"positive_bins":{{"1":-2.0}},"negative_bins":{{}}}}"#, mapping),
+        r#"{"mapping":{"layout":"LOG","gamma":0.5,"index_offset":0.0},"zero_count":0.0,"positive_bins":{},"negative_bins":{}}"#.to_string(),
+        r#"{"mapping":{"layout":"EXP","gamma":1.02,"index_offset":0.0},"zero_count":0.0,"positive_bins":{},"negative_bins":{}}"#.to_string(),
+    ] {
+        assert!(serde_json::from_str::<DDSketch>(&json).is_err());
+    }
+    assert!(rmp_serde::from_slice::<DDSketch>(&[0xC4, 2, 0xFF, 0xFF]).is_err());
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn test_sketch_serde_with_options() {
+    use serde::de::DeserializeSeed;
+    use sketches_rust::DDSketchSeed;
+
+    let mut sketch = DDSketch::unbounded_dense(2e-2).unwrap();
+    for i in 1..=100 {
+        sketch.accept(i as f64 * 1e3);
+    }
+    sketch.accept(1e-3);
+    let json = serde_json::to_string(&sketch).unwrap();
+    let compact = rmp_serde::to_vec(&sketch).unwrap();
+    let from_json = |options: &DecodeOptions| {
+        DDSketchSeed::new(options).deserialize(&mut serde_json::Deserializer::from_str(&json))
+    };
+    let from_compact = |options: &DecodeOptions| {
+        DDSketchSeed::new(options).deserialize(&mut rmp_serde::Deserializer::new(&compact[..]))
+    };
+
+    let lenient = DecodeOptions::new();
+    assert_eq!(sketch, from_json(&lenient).unwrap());
+    assert_eq!(sketch, from_compact(&lenient).unwrap());
+    for options in [
+        DecodeOptions::new().max_num_bins(50),
+        DecodeOptions::new().max_index_span(200),
+    ] {
+        assert!(from_json(&options).is_err());
+        assert!(from_compact(&options).is_err());
+    }
+    assert!(from_compact(&DecodeOptions::new().max_len(compact.len() / 2)).is_err());
+
+    let duplicate = r#"{"mapping":{"layout":"LOG","gamma":1.02,"index_offset":0.0},"zero_count":1.0,"zero_count":1.0,"positive_bins":{},"negative_bins":{}}"#;
+    assert!(serde_json::from_str::<DDSketch>(duplicate).is_err());
+    let unknown = r#"{"mapping":{"layout":"LOG","gamma":1.02,"index_offset":0.0},"zero_count":1.0,"positive_bins":{},"negative_bins":{},"comment":[1]}"#;
+    assert_eq!(
+        1.0,
+        serde_json::from_str::<DDSketch>(unknown)
+            .unwrap()
+            .get_count()
+    );
+}
+
+#[test]
+fn test_sketch_clone() {
+    for mut sketch in all_sketches(2e-2, 100) {
+        for i in -50..100 {
+            sketch.accept(i as f64);
+        }
+        let encoded = sketch.encode().unwrap();
+
+        let mut snapshot = sketch.clone();
+        sketch.clear();
+        assert!(sketch.is_empty());
+        assert_eq!(150.0, snapshot.get_count());
+        assert_eq!(encoded, snapshot.encode().unwrap());
+
+        sketch.accept(1.0);
+        snapshot.accept(1.0);
+        assert_eq!(1.0, sketch.get_count());
+        assert_eq!(151.0, snapshot.get_count());
+    }
+}
+
+#[test]
+fn test_sketch_eq() {
+    let mut dense = DDSketch::unbounded_dense(2e-2).unwrap();
+    let mut sparse = DDSketch::sparse(2e-2).unwrap();
+    let mut paginated = DDSketch::paginated(2e-2).unwrap();
+    assert_eq!(dense, sparse);
+    for value in [-3.0, 0.0, 1.0, 2.0, 2.0, 1e6] {
+        dense.accept(value);
+        sparse.accept(value);
+        paginated.accept(value);
+    }
+    assert_eq!(dense, sparse);
+    assert_eq!(dense, paginated);
+    assert_eq!(dense, dense.clone());
+
+    let mut other = dense.clone();
+    other.accept(0.0);
+    assert_ne!(dense, other);
+    let mut other = dense.clone();
+    other.accept(-3.0);
+    assert_ne!(dense, other);
+
+    let logarithmic = DDSketch::logarithmic_unbounded_size_dense_store(2e-2).unwrap();
+    assert_ne!(DDSketch::unbounded_dense(2e-2).unwrap(), logarithmic);
+
+    let exact = DDSketch::unbounded_dense(2e-2)
+        .unwrap()
+        .with_exact_summary_statistics()
+        .unwrap();
+    assert_ne!(DDSketch::unbounded_dense(2e-2).unwrap(), exact);
+}
+
+#[test]
+fn test_sketch_debug() {
+    let mut sketch = DDSketch::logarithmic_unbounded_size_dense_store(2e-2).unwrap();
+    for value in [1.0, 2.0, 2.0, 0.0] {
+        sketch.accept(value);
+    }
+    assert_eq!(
+        "DDSketch { index_mapping: LogarithmicMapping { gamma: 1.0408163265306123, \
+         index_offset: 0.0, relative_accuracy: 0.02000000000000002 }, zero_count: 1.0, \
+         positive_value_store: Store { num_bins: 2, min_index: 0, max_index: 17, \
+         total_count: 3.0 }, negative_value_store: Store { num_bins: 0 }, \
+         summary_statistics: None }",
+        format!("{:?}", sketch)
+    );
+}
+
+#[test]
+fn test_concurrent_sketch() {
+    for sketch in all_sketches(2e-2, 1000) {
+        let sketch = sketch.with_exact_summary_statistics().unwrap();
+        let mut expected = sketch.clone();
+        let concurrent = ConcurrentDDSketch::with_num_stripes(sketch, 4).unwrap();
+        std::thread::scope(|scope| {
+            for thread in 0..8 {
+                let concurrent = &concurrent;
+                scope.spawn(move || {
+                    for i in 0..1000 {
+                        concurrent.accept((thread * 1000 + i - 4000) as f64);
+                    }
+                    concurrent.accept_with_count(0.5, 2.0).unwrap();
+                });
+            }
+        });
+        for i in -4000..4000 {
+            expected.accept(i as f64);
+        }
+        expected.accept_with_count(0.5, 16.0).unwrap();
+
+        let snapshot = concurrent.snapshot().unwrap();
+        assert_eq!(expected, snapshot);
+        assert_eq!(8016.0, snapshot.get_count());
+        assert_eq!(Some(-4000.0), snapshot.get_min());
+        assert_eq!(Some(3999.0), snapshot.get_max());
+
+        concurrent.clear();
+        assert!(concurrent.snapshot().unwrap().is_empty());
+    }
+}
+
+#[test]
+fn test_concurrent_sketch_keeps_initial_values() {
+    let mut sketch = DDSketch::unbounded_dense(2e-2).unwrap();
+    sketch.accept(1.0);
+    let concurrent = ConcurrentDDSketch::new(sketch.clone());
+    assert!(concurrent.num_stripes() >= 1);
+    assert_eq!(sketch, concurrent.snapshot().unwrap());
+
+    concurrent.accept(2.0);
+    sketch.accept(2.0);
+    assert_eq!(sketch, concurrent.snapshot().unwrap());
+    assert!(concurrent.accept_with_count(1.0, -1.0).is_err());
+}
+
+#[test]
+fn test_concurrent_sketch_invalid_num_stripes() {
+    let sketch = DDSketch::unbounded_dense(2e-2).unwrap();
+    assert!(ConcurrentDDSketch::with_num_stripes(sketch, 0).is_err());
+}
+
+#[test]
+fn test_sketch_query_through_shared_references() {
+    let sketches: Vec<DDSketch> = all_sketches(2e-2, 100)
+        .into_iter()
+        .map(|mut sketch| {
+            for value in [-2.0, 0.0, 1.0, 2.0, 3.0] {
+                sketch.accept(value);
+            }
+            sketch
+        })
+        .collect();
+    let counts: Vec<f64> = sketches.iter().map(DDSketch::get_count).collect();
+    assert_eq!(vec![5.0; sketches.len()], counts);
+
+    for sketch in &sketches {
+        assert!(sketch.get_sum().unwrap() > 3.9 && sketch.get_sum().unwrap() < 4.1);
+        assert!(sketch.get_average().unwrap() > 0.79 && sketch.get_average().unwrap() < 0.81);
+        assert!(sketch.get_min().unwrap() < -1.96);
+        assert!(sketch.get_max().unwrap() > 2.94);
+        assert_eq!(Some(0.0), sketch.get_value_at_quantile(0.4));
+    }
+
+    let shared = std::sync::Arc::new(std::sync::RwLock::new(sketches[0].clone()));
+    std::thread::scope(|scope| {
+        for _ in 0..4 {
+            let shared = std::sync::Arc::clone(&shared);
+            scope.spawn(move || {
+                let sketch = shared.read().unwrap();
+                assert_eq!(5.0, sketch.get_count());
+                assert_eq!(Some(0.0), sketch.get_value_at_quantile(0.4));
+            });
+        }
+    });
+}
+
+#[test]
+fn test_sketch_get_values_at_quantiles() {
+    let quantiles = [0.99, 0.5, 0.0, 1.0, 0.75, 0.5, 0.9, 0.95, 0.999, 0.25, 0.1];
+    for mut sketch in all_sketches(2e-2, 100) {
+        for i in -300..1000 {
+            sketch.accept(i as f64 * 0.73);
+        }
+        sketch.accept_with_count(0.0, 17.0).unwrap();
+
+        let values = sketch.get_values_at_quantiles(&quantiles).unwrap();
+        assert_eq!(quantiles.len(), values.len());
+        for (quantile, value) in quantiles.iter().zip(values) {
+            assert_eq!(sketch.get_value_at_quantile(*quantile).unwrap(), value);
+        }
+        assert_eq!(
+            Vec::<f64>::new(),
+            sketch.get_values_at_quantiles(&[]).unwrap()
+        );
+    }
+
+    let mut sketch = DDSketch::unbounded_dense(2e-2).unwrap();
+    sketch.accept(-1.0);
+    sketch.accept(2.0);
+    assert_eq!(
+        vec![
+            sketch.get_value_at_quantile(1.0).unwrap(),
+            sketch.get_value_at_quantile(0.0).unwrap()
+        ],
+        sketch.get_values_at_quantiles(&[1.0, 0.0]).unwrap()
+    );
+}
+
+#[test]
+fn test_sketch_get_values_at_quantiles_matches_single() {
+    let quantiles: Vec<f64> = (0..=100).map(|i| i as f64 / 100.0).collect();
+    for mut sketch in all_sketches(2e-2, 100) {
+        // The counts add up to 1e16 after rounding, which does not exceed the rank of the
+        // maximum, 1e16 - 1 rounded to 1e16, so the maximum falls back to the last bin.
+        sketch.accept_with_count(1.0, 1e16).unwrap();
+        sketch.accept(5.0);
+        sketch.accept(9.0);
+        let values = sketch.get_values_at_quantiles(&quantiles).unwrap();
+        for (quantile, value) in quantiles.iter().zip(&values) {
+            assert_eq!(Some(*value), sketch.get_value_at_quantile(*quantile));
+        }
+        let max = sketch.get_value_at_quantile(1.0).unwrap();
+        assert!(max > 8.0 && max < 10.0);
+
+        for i in -300..300 {
+            sketch.accept(i as f64 * 1.7);
+        }
+        let values = sketch.get_values_at_quantiles(&quantiles).unwrap();
+        for (quantile, value) in quantiles.iter().zip(&values) {
+            assert_eq!(Some(*value), sketch.get_value_at_quantile(*quantile));
+        }
+    }
+}
+
+#[test]
+fn test_sketch_get_values_at_quantiles_invalid() {
+    use sketches_rust::Error;
+
+    let mut sketch = DDSketch::unbounded_dense(2e-2).unwrap();
+    assert!(matches!(
+        sketch.get_values_at_quantiles(&[0.5]),
+        Err(Error::EmptySketch)
+    ));
+    assert_eq!(None, sketch.get_value_at_quantile(0.5));
+    assert!(matches!(
+        sketch.get_values_at_quantiles(&[0.5, 1.1]),
+        Err(Error::InvalidQuantile { quantile, position: 1 }) if quantile == 1.1
+    ));
+    sketch.accept(1.0);
+    assert!(matches!(
+        sketch.get_values_at_quantiles(&[0.5, 1.1]),
+        Err(Error::InvalidQuantile { quantile, position: 1 }) if quantile == 1.1
+    ));
+    assert!(matches!(
+        sketch.get_values_at_quantiles(&[-0.1]),
+        Err(Error::InvalidQuantile { quantile, position: 0 }) if quantile == -0.1
+    ));
+    assert!(matches!(
+        sketch.get_values_at_quantiles(&[0.0, 0.5, f64::NAN]),
+        Err(Error::InvalidQuantile { quantile, position: 2 }) if quantile.is_nan()
+    ));
+    assert_eq!(None, sketch.get_value_at_quantile(f64::NAN));
+    assert_eq!(1, sketch.get_values_at_quantiles(&[0.5]).unwrap().len());
+}
+
+#[test]
+fn test_sketch_get_rank() {
+    let relative_accuracy = 2e-2;
+    let factor = (1.0 + relative_accuracy) / (1.0 - relative_accuracy);
+    let values: Vec<f64> = (-500..=1000).map(|i| i as f64 * 0.5).collect();
+    let exact_rank = |threshold: f64| values.iter().filter(|v| **v <= threshold).count() as f64;
+
+    for mut sketch in all_sketches(relative_accuracy, 2000) {
+        for value in &values {
+            sketch.accept(*value);
+        }
+        for threshold in [
+            -300.0, -250.0, -100.2, -3.0, -0.5, -1e-300, 0.0, 0.3, 1.0, 250.0, 499.9,
+        ] {
+            let rank = sketch.get_rank(threshold).unwrap();
+            let upper = if threshold < 0.0 {
+                threshold / factor
+            } else {
+                threshold * factor
+            };
+            assert!(rank >= exact_rank(threshold), "{} {}", threshold, rank);
+            assert!(rank <= exact_rank(upper), "{} {}", threshold, rank);
+        }
+
+        assert_eq!(Some(0.0), sketch.get_rank(-1e300));
+        assert_eq!(Some(0.0), sketch.get_rank(f64::NEG_INFINITY));
+        assert_eq!(Some(1501.0), sketch.get_rank(1e300));
+        assert_eq!(Some(1501.0), sketch.get_rank(f64::INFINITY));
+        assert_eq!(Some(501.0), sketch.get_rank(0.0));
+        assert_eq!(None, sketch.get_rank(f64::NAN));
+
+        assert_eq!(Some(1.0), sketch.get_cdf(f64::INFINITY));
+        assert_eq!(Some(501.0 / 1501.0), sketch.get_cdf(0.0));
+        assert_eq!(None, sketch.get_cdf(f64::NAN));
+    }
+}
+
+#[test]
+fn test_sketch_get_cdf_slo() {
+    let mut sketch = DDSketch::collapsing_lowest_dense(1e-2, 2048).unwrap();
+    for latency in 1..=1000 {
+        sketch.accept(latency as f64);
+    }
+    let cdf = sketch.get_cdf(250.0).unwrap();
+    assert!((0.25..=0.2551).contains(&cdf));
+    assert_eq!(None, DDSketch::sparse(1e-2).unwrap().get_cdf(250.0));
+    assert_eq!(Some(0.0), DDSketch::sparse(1e-2).unwrap().get_rank(250.0));
+}
+
+#[test]
+fn test_sketch_get_count_between() {
+    let relative_accuracy = 2e-2;
+    let factor = (1.0 + relative_accuracy) / (1.0 - relative_accuracy);
+    let widen_lower = |lower: f64| {
+        if lower > 0.0 {
+            lower / factor
+        } else {
+            lower * factor
+        }
+    };
+    let widen_upper = |upper: f64| {
+        if upper > 0.0 {
+            upper * factor
+        } else {
+            upper / factor
+        }
+    };
+
+    // A uniform, an exponential and a symmetric heavy-tailed distribution, with zeros.
+    let distributions: Vec<Vec<f64>> = vec![
+        (-1000..=1000).map(|i| i as f64 * 0.25).collect(),
+        (0..2000).map(|i| (i as f64 * 0.01).exp()).collect(),
+        (1..1000)
+            .flat_map(|i| {
+                let value = 1000.0 / i as f64;
+                [value, -value, 0.0]
+            })
+            .collect(),
+    ];
+    let ranges = [
+        (100.0, 500.0),
+        (-200.0, -10.0),
+        (-10.0, 10.0),
+        (0.0, 0.0),
+        (0.0, 3.5),
+        (-3.5, 0.0),
+        (1.0, 1.0),
+        (f64::NEG_INFINITY, -5.0),
+        (5.0, f64::INFINITY),
+        (f64::NEG_INFINITY, f64::INFINITY),
+    ];
+
+    for values in &distributions {
+        let exact_count = |lower: f64, upper: f64| {
+            values
+                .iter()
+                .filter(|v| lower <= **v && **v <= upper)
+                .count() as f64
+        };
+        for mut sketch in all_sketches(relative_accuracy, 4000) {
+            for value in values {
+                sketch.accept(*value);
+            }
+            for (lower, upper) in ranges {
+                let count = sketch.get_count_between(lower, upper).unwrap();
+                assert!(count >= exact_count(lower, upper), "{} {}", lower, upper);
+                assert!(
+                    count <= exact_count(widen_lower(lower), widen_upper(upper)),
+                    "{} {}",
+                    lower,
+                    upper
+                );
+            }
+            for threshold in [-300.0, -1.0, 0.0, 2.0, 700.0] {
+                assert_eq!(
+                    sketch.get_rank(threshold),
+                    sketch.get_count_between(f64::NEG_INFINITY, threshold)
+                );
+            }
+            assert_eq!(
+                Some(sketch.get_count()),
+                sketch.get_count_between(f64::NEG_INFINITY, f64::INFINITY)
+            );
+        }
+    }
+}
+
+#[test]
+fn test_sketch_get_count_between_invalid() {
+    let mut sketch = DDSketch::unbounded_dense(2e-2).unwrap();
+    assert_eq!(Some(0.0), sketch.get_count_between(1.0, 2.0));
+    sketch.accept(1.5);
+    assert_eq!(Some(1.0), sketch.get_count_between(1.0, 2.0));
+    assert_eq!(None, sketch.get_count_between(2.0, 1.0));
+    assert_eq!(None, sketch.get_count_between(f64::NAN, 1.0));
+    assert_eq!(None, sketch.get_count_between(1.0, f64::NAN));
+}
+
+#[test]
+fn test_sketch_subtract() {
+    let previous_values: Vec<f64> = (-300..700).map(|i| i as f64 * 0.61).collect();
+    let delta_values: Vec<f64> = (0..500).map(|i| (i % 37) as f64 * 2.3 - 20.0).collect();
+    for empty in all_sketches(2e-2, 2000) {
+        let mut previous = empty.clone();
+        let mut delta = empty.clone();
+        for value in &previous_values {
+            previous.accept(*value);
+        }
+        for value in &delta_values {
+            delta.accept(*value);
+        }
+        let mut current = previous.clone();
+        current.merge_with(&delta).unwrap();
+
+        let mut subtracted = current.clone();
+        subtracted
+            .subtract(&previous, SubtractionPolicy::Error)
+            .unwrap();
+        assert_eq!(delta, subtracted);
+        assert_eq!(delta.get_count(), subtracted.get_count());
+        assert_eq!(delta.encode().unwrap(), subtracted.encode().unwrap());
+
+        let mut subtracted = current.clone();
+        subtracted
+            .subtract(&current, SubtractionPolicy::Error)
+            .unwrap();
+        assert!(subtracted.is_empty());
+    }
+}
+
+#[test]
+fn test_sketch_subtract_negative_counts() {
+    let mut sketch = DDSketch::unbounded_dense(2e-2).unwrap();
+    let mut other = DDSketch::unbounded_dense(2e-2).unwrap();
+    for value in [-1.0, 0.0, 1.0, 1.0, 5.0] {
+        sketch.accept(value);
+    }
+    for value in [-1.0, -1.0, 0.0, 0.0, 1.0, 7.0] {
+        other.accept(value);
+    }
+
+    let before = sketch.clone();
+    assert!(sketch.subtract(&other, SubtractionPolicy::Error).is_err());
+    assert_eq!(before, sketch);
+
+    sketch.subtract(&other, SubtractionPolicy::Clamp).unwrap();
+    let mut expected = DDSketch::unbounded_dense(2e-2).unwrap();
+    expected.accept(1.0);
+    expected.accept(5.0);
+    assert_eq!(expected, sketch);
+}
+
+#[test]
+fn test_sketch_subtract_invalid() {
+    let mut sketch = DDSketch::unbounded_dense(2e-2).unwrap();
+    let other = DDSketch::logarithmic_unbounded_size_dense_store(2e-2).unwrap();
+    assert!(sketch.subtract(&other, SubtractionPolicy::Clamp).is_err());
+
+    let mut exact = DDSketch::unbounded_dense(2e-2)
+        .unwrap()
+        .with_exact_summary_statistics()
+        .unwrap();
+    let mut other = DDSketch::unbounded_dense(2e-2).unwrap();
+    other.accept(1.0);
+    assert!(exact.subtract(&other, SubtractionPolicy::Clamp).is_err());
+}
+
+#[test]
+fn test_sketch_subtract_exact_summary_statistics() {
+    let exact = || {
+        DDSketch::unbounded_dense(2e-2)
+            .unwrap()
+            .with_exact_summary_statistics()
+            .unwrap()
+    };
+    let mut sketch = exact();
+    let mut other = exact();
+    for value in [1.0, 2.0, 4.0, 8.0] {
+        sketch.accept(value);
+    }
+    other.accept(1.0);
+    other.accept(8.0);
+
+    let mut subtracted = sketch.clone();
+    subtracted
+        .subtract(
+            &DDSketch::unbounded_dense(2e-2).unwrap(),
+            SubtractionPolicy::Error,
+        )
+        .unwrap();
+    assert_eq!(sketch, subtracted);
+
+    subtracted
+        .subtract(&other, SubtractionPolicy::Error)
+        .unwrap();
+    assert_eq!(2.0, subtracted.get_count());
+    assert_eq!(Some(6.0), subtracted.get_sum());
+    // The exact bounds of the remaining values are unknown, so they are estimated from the bins.
+    let mut estimated = DDSketch::unbounded_dense(2e-2).unwrap();
+    estimated.accept(2.0);
+    estimated.accept(4.0);
+    assert_eq!(estimated.get_min(), subtracted.get_min());
+    assert_eq!(estimated.get_max(), subtracted.get_max());
+
+    let decoded = DDSketch::decode(subtracted.encode().unwrap()).unwrap();
+    assert_eq!(subtracted.get_sum(), decoded.get_sum());
+    assert_eq!(subtracted.get_min(), decoded.get_min());
+    assert_eq!(subtracted.get_max(), decoded.get_max());
+
+    let mut too_many = sketch.clone();
+    too_many.accept(16.0);
+    let mut cleared = sketch.clone();
+    assert!(cleared
+        .subtract(&too_many, SubtractionPolicy::Error)
+        .is_err());
+    assert_eq!(sketch, cleared);
+    cleared
+        .subtract(&too_many, SubtractionPolicy::Clamp)
+        .unwrap();
+    assert!(cleared.is_empty());
+    cleared.accept(3.0);
+    assert_eq!(Some(3.0), cleared.get_min());
+    assert_eq!(Some(3.0), cleared.get_max());
+}
+
+#[test]
+fn test_sketch_scale_counts() {
+    let sketches = all_sketches(2e-2, 1000)
+        .into_iter()
+        .chain([DDSketch::sparse(2e-2)
+            .unwrap()
+            .with_exact_summary_statistics()
+            .unwrap()]);
+    for empty in sketches {
+        let mut sketch = empty.clone();
+        let mut expected = empty.clone();
+        for i in -200..300 {
+            sketch.accept(i as f64 * 0.5);
+            expected.accept_with_count(i as f64 * 0.5, 8.0).unwrap();
+        }
+
+        sketch.scale_counts(8.0).unwrap();
+        assert_eq!(expected, sketch);
+        assert_eq!(4000.0, sketch.get_count());
+        assert_eq!(expected.get_sum(), sketch.get_sum());
+        assert_eq!(expected.get_min(), sketch.get_min());
+        assert_eq!(expected.get_max(), sketch.get_max());
+
+        let decoded = DDSketch::decode(sketch.encode().unwrap()).unwrap();
+        assert_eq!(sketch, decoded);
+        assert_eq!(sketch.encode().unwrap(), decoded.encode().unwrap());
+
+        sketch.scale_counts(0.25).unwrap();
+        assert_eq!(1000.0, sketch.get_count());
+
+        sketch.scale_counts(0.0).unwrap();
+        assert!(sketch.is_empty());
+        assert_eq!(None, sketch.get_max());
+    }
+}
+
+#[test]
+fn test_sketch_scale_counts_invalid() {
+    let mut sketch = DDSketch::unbounded_dense(2e-2).unwrap();
+    sketch.accept(1.0);
+    for factor in [-1.0, f64::NAN, f64::INFINITY, f64::NEG_INFINITY] {
+        assert!(sketch.scale_counts(factor).is_err());
+    }
+    assert_eq!(1.0, sketch.get_count());
+}
+
+#[test]
+fn test_sketch_rescale_values() {
+    let relative_accuracy = 1e-2;
+    let nanoseconds: Vec<f64> = (1..2000).map(|i| (i as f64).powi(2) * 1e3).collect();
+    let quantiles = [0.0, 0.1, 0.25, 0.5, 0.75, 0.9, 0.99, 1.0];
+    for empty in all_sketches(relative_accuracy, 2000) {
+        let mut sketch = empty.clone();
+        let mut milliseconds = empty.clone();
+        for value in &nanoseconds {
+            sketch.accept(*value);
+            sketch.accept(-*value);
+            milliseconds.accept(*value * 1e-6);
+            milliseconds.accept(-*value * 1e-6);
+        }
+        sketch.accept(0.0);
+        milliseconds.accept(0.0);
+
+        let rescaled = sketch.rescale_values(1e-6).unwrap();
+        assert_eq!(sketch.get_count(), rescaled.get_count());
+        let bound = 2.0 * relative_accuracy + relative_accuracy * relative_accuracy + 1e-12;
+        for quantile in quantiles {
+            let expected = sketch.get_value_at_quantile(quantile).unwrap() * 1e-6;
+            let actual = rescaled.get_value_at_quantile(quantile).unwrap();
+            assert!((actual - expected).abs() <= bound * expected.abs());
+            let direct = milliseconds.get_value_at_quantile(quantile).unwrap();
+            assert!((actual - direct).abs() <= 2.0 * bound * direct.abs());
+        }
+    }
+}
+
+#[test]
+fn test_sketch_rescale_values_exact_shift() {
+    let gamma: f64 = 1.02 / 0.98;
+    let mut sketch = DDSketch::logarithmic_unbounded_size_dense_store(2e-2)
+        .unwrap()
+        .with_exact_summary_statistics()
+        .unwrap();
+    for i in 1..500 {
+        sketch.accept(i as f64);
+        sketch.accept(-(i as f64) / 3.0);
+    }
+    let multiplier = gamma.powi(-5);
+    let rescaled = sketch.rescale_values(multiplier).unwrap();
+    for quantile in [0.0, 0.2, 0.5, 0.8, 1.0] {
+        let expected = sketch.get_value_at_quantile(quantile).unwrap() * multiplier;
+        let actual = rescaled.get_value_at_quantile(quantile).unwrap();
+        assert!((actual - expected).abs() <= 1e-9 * expected.abs());
+    }
+    assert_eq!(
+        sketch.get_sum().unwrap() * multiplier,
+        rescaled.get_sum().unwrap()
+    );
+    assert_eq!(Some(499.0 * multiplier), rescaled.get_max());
+    assert!(rescaled.has_exact_summary_statistics());
+}
+
+#[test]
+fn test_sketch_rescale_values_out_of_range() {
+    let mut sketch = DDSketch::unbounded_dense(2e-2).unwrap();
+    sketch.accept(1e-300);
+    sketch.accept(1e300);
+    let rescaled = sketch.rescale_values(1e-20).unwrap();
+    assert_eq!(2.0, rescaled.get_count());
+    assert_eq!(Some(0.0), rescaled.get_value_at_quantile(0.0));
+
+    for multiplier in [0.0, -1.0, f64::NAN, f64::INFINITY] {
+        assert!(sketch.rescale_values(multiplier).is_err());
+    }
+}
+
+#[test]
+fn test_sketch_builder() {
+    let mut sketch = DDSketch::builder()
+        .index_layout(IndexMappingLayout::LOG)
+        .relative_accuracy(2e-2)
+        .positive_store(StoreKind::CollapsingLowestDense { max_num_bins: 100 })
+        .negative_store(StoreKind::CollapsingHighestDense { max_num_bins: 10 })
+        .exact_summary_statistics(true)
+        .build()
+        .unwrap();
+    assert_eq!(IndexMappingLayout::LOG, sketch.get_index_mapping().layout());
+    assert!(sketch.has_exact_summary_statistics());
+    for i in 1..1000 {
+        sketch.accept(i as f64);
+        sketch.accept(-(i as f64));
+    }
+    assert_eq!(Some(-999.0), sketch.get_min());
+    assert_eq!(Some(999.0), sketch.get_max());
+    // The negative store only keeps its 10 lowest bins, so large negative values are collapsed
+    // toward zero while the positive values are kept accurately.
+    let q25 = sketch.get_value_at_quantile(0.25).unwrap();
+    assert!(q25 > -2.0);
+    let q99 = sketch.get_value_at_quantile(0.99).unwrap();
+    assert!(f64::abs(q99 - 979.0) <= 2e-2 * 979.0);
+
+    let mut default = DDSketchBuilder::new()
+        .relative_accuracy(2e-2)
+        .build()
+        .unwrap();
+    let mut unbounded = DDSketch::unbounded_dense(2e-2).unwrap();
+    for i in -100..100 {
+        default.accept(i as f64);
+        unbounded.accept(i as f64);
+    }
+    assert_eq!(unbounded, default);
+    assert!(!default.has_exact_summary_statistics());
+}
+
+#[test]
+fn test_sketch_builder_gamma_offset() {
+    let mut sketch = DDSketch::logarithmic_sparse(1e-2).unwrap();
+    sketch.accept(42.0);
+    let index_mapping = sketch.get_index_mapping();
+    let mut other = DDSketch::builder()
+        .index_layout(index_mapping.layout())
+        .gamma_offset(index_mapping.gamma(), index_mapping.index_offset())
+        .stores(StoreKind::Paginated)
+        .build()
+        .unwrap();
+    assert_eq!(index_mapping, other.get_index_mapping());
+    other.accept(42.0);
+    assert_eq!(sketch, other);
+}
+
+#[test]
+fn test_sketch_builder_indexed_values() {
+    let mut sketch = DDSketch::builder()
+        .relative_accuracy(1e-2)
+        .min_indexed_value(1e-3)
+        .max_indexed_value(1e3)
+        .build()
+        .unwrap();
+    sketch.accept(1e-4);
+    sketch.accept(-1e-4);
+    sketch.accept(1.0);
+    sketch.accept(1e4);
+    sketch.accept(-1e4);
+    assert_eq!(3.0, sketch.get_count());
+    assert_eq!(Some(0.0), sketch.get_value_at_quantile(0.0));
+    assert_eq!(Some(0.0), sketch.get_value_at_quantile(0.5));
+}
+
+#[test]
+fn test_sketch_builder_indexed_values_convert_to_mapping() {
+    let mut sketch = DDSketch::builder()
+        .relative_accuracy(1e-2)
+        .min_indexed_value(1e-3)
+        .max_indexed_value(1e3)
+        .build()
+        .unwrap();
+    sketch.accept(1.0);
+
+    let index_mapping =
+        IndexMapping::with_relative_accuracy(IndexMappingLayout::LOG, 2e-2).unwrap();
+    let mut converted = sketch.convert_to_mapping(&index_mapping);
+    assert_eq!(&index_mapping, converted.get_index_mapping());
+    converted.accept(1e-4);
+    converted.accept(1e4);
+    converted.accept(-1e4);
+    assert_eq!(2.0, converted.get_count());
+    assert_eq!(Some(0.0), converted.get_value_at_quantile(0.0));
+}
+
+#[test]
+fn test_sketch_builder_invalid() {
+    let builder = DDSketchBuilder::new();
+    assert!(builder.build().is_err());
+    assert!(builder
+        .clone()
+        .relative_accuracy(1e-2)
+        .gamma_offset(1.02, 0.0)
+        .build()
+        .is_err());
+    assert!(builder.clone().relative_accuracy(1.5).build().is_err());
+    assert!(builder.clone().gamma_offset(0.9, 0.0).build().is_err());
+    assert!(builder
+        .clone()
+        .gamma_offset(1.02, f64::NAN)
+        .build()
+        .is_err());
+    let builder = builder.relative_accuracy(1e-2);
+    assert!(builder
+        .clone()
+        .stores(StoreKind::CollapsingLowestSparse { max_num_bins: 0 })
+        .build()
+        .is_err());
+    assert!(builder
+        .clone()
+        .negative_store(StoreKind::CollapsingHighestDense { max_num_bins: 0 })
+        .build()
+        .is_err());
+    assert!(builder.clone().min_indexed_value(-1.0).build().is_err());
+    assert!(builder.clone().min_indexed_value(f64::NAN).build().is_err());
+    assert!(builder.clone().max_indexed_value(0.0).build().is_err());
+    assert!(builder
+        .clone()
+        .min_indexed_value(10.0)
+        .max_indexed_value(1.0)
+        .build()
+        .is_err());
+    assert!(builder.build().is_ok());
+}
+
+#[test]
+fn test_sketch_generic_stores() {
+    let builder = DDSketchBuilder::new()
+        .relative_accuracy(2e-2)
+        .exact_summary_statistics(true);
+    let mut generic = builder
+        .build_with_stores(
+            CollapsingLowestDenseStore::with_capacity(100).unwrap(),
+            SparseStore::new(),
+        )
+        .unwrap();
+    let mut boxed = builder
+        .clone()
+        .positive_store(StoreKind::CollapsingLowestDense { max_num_bins: 100 })
+        .negative_store(StoreKind::Sparse)
+        .build()
+        .unwrap();
+    for i in -1000..1000 {
+        generic.accept(i as f64 / 3.0);
+        boxed.accept(i as f64 / 3.0);
+    }
+    assert_eq!(boxed, generic);
+    assert_eq!(generic.encode().unwrap(), boxed.encode().unwrap());
+    for quantile in [0.0, 0.1, 0.5, 0.9, 1.0] {
+        assert_eq!(
+            boxed.get_value_at_quantile(quantile),
+            generic.get_value_at_quantile(quantile)
+        );
+    }
+
+    let mut merged = DDSketchBuilder::new()
+        .relative_accuracy(2e-2)
+        .exact_summary_statistics(true)
+        .build_with_stores(PaginatedStore::new(), UnboundedSizeDenseStore::new())
+        .unwrap();
+    merged.merge_with(&generic).unwrap();
+    merged.merge_with(&boxed).unwrap();
+    boxed.merge_with(&generic).unwrap();
+    assert_eq!(boxed, merged);
+
+    let mut decoded = generic.clone();
+    decoded.clear();
+    decoded
+        .decode_and_merge_with(generic.encode().unwrap())
+        .unwrap();
+    assert_eq!(generic, decoded);
+}
+
+#[test]
+fn test_concurrent_sketch_generic_stores() {
+    let sketch = DDSketchBuilder::new()
+        .relative_accuracy(2e-2)
+        .build_with_stores(
+            UnboundedSizeDenseStore::new(),
+            UnboundedSizeDenseStore::new(),
+        )
+        .unwrap();
+    let concurrent = ConcurrentDDSketch::with_num_stripes(sketch, 4).unwrap();
+    std::thread::scope(|scope| {
+        for _ in 0..4 {
+            scope.spawn(|| {
+                for i in 0..100 {
+                    concurrent.accept(i as f64);
+                }
+            });
+        }
+    });
+    assert_eq!(400.0, concurrent.snapshot().unwrap().get_count());
+}
+
+#[test]
+fn test_sketch_memory_footprint() {
+    let mut bounded = DDSketch::collapsing_lowest_dense(1e-2, 100).unwrap();
+    let mut unbounded = DDSketch::unbounded_dense(1e-2).unwrap();
+    let empty_footprint = unbounded.get_memory_footprint();
+    for i in 1..10_000 {
+        bounded.accept(i as f64);
+        unbounded.accept(i as f64);
+    }
+    assert!(unbounded.get_memory_footprint() > empty_footprint);
+    assert!(bounded.get_memory_footprint() < unbounded.get_memory_footprint());
+}
+
+/// Hands out at most `chunk` bytes per read, so that decoding has to refill mid-value.
+struct ChunkedReader<'a> {
+    bytes: &'a [u8],
+    chunk: usize,
+}
+
+impl std::io::Read for ChunkedReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let len = self.chunk.min(buf.len()).min(self.bytes.len());
+        buf[..len].copy_from_slice(&self.bytes[..len]);
+        self.bytes = &self.bytes[len..];
+        Ok(len)
+    }
+}
+
+struct FailingIo;
+
+impl std::io::Read for FailingIo {
+    fn read(&mut self, _: &mut [u8]) -> std::io::Result<usize> {
+        Err(std::io::Error::new(
+            std::io::ErrorKind::ConnectionReset,
+            "reset",
+        ))
+    }
+}
+
+impl std::io::Write for FailingIo {
+    fn write(&mut self, _: &[u8]) -> std::io::Result<usize> {
+        Err(std::io::Error::new(
+            std::io::ErrorKind::BrokenPipe,
+            "broken",
+        ))
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn test_sketch_encode_to_decode_from() {
+    let mut sketch = DDSketchBuilder::new()
+        .relative_accuracy(0.001)
+        .stores(StoreKind::Sparse)
+        .exact_summary_statistics(true)
+        .build()
+        .unwrap();
+    for i in 0..20000 {
+        sketch.accept(1.001f64.powi(i % 10000) * if i % 2 == 0 { 1.0 } else { -1.0 });
+    }
+    sketch.accept(0.0);
+
+    let mut streamed = Vec::new();
+    sketch.encode_to(&mut streamed).unwrap();
+    let bytes = sketch.encode().unwrap();
+    assert!(bytes.len() > 8 * 1024);
+    assert_eq!(bytes, streamed);
+
+    for chunk in [1, 7, 4096, usize::MAX] {
+        let reader = ChunkedReader {
+            bytes: &bytes,
+            chunk,
+        };
+        let decoded = DDSketch::decode_from(reader).unwrap();
+        assert_eq!(DDSketch::decode(&bytes).unwrap(), decoded);
+    }
+
+    let mut merged = DDSketch::unbounded_dense(0.01).unwrap();
+    merged.accept(-1.0);
+    let mut expected = merged.clone();
+    merged
+        .decode_and_merge_from(std::io::Cursor::new(&bytes))
+        .unwrap();
+    expected.decode_and_merge_with(&bytes).unwrap();
+    assert_eq!(expected, merged);
+}
+
+#[test]
+fn test_sketch_stream_io_errors() {
+    let mut sketch = DDSketch::unbounded_dense(0.01).unwrap();
+    sketch.accept(1.0);
+
+    match sketch.encode_to(FailingIo) {
+        Err(sketches_rust::Error::IoError(error)) => {
+            assert_eq!(std::io::ErrorKind::BrokenPipe, error.kind())
+        }
+        other => panic!("unexpected result: {:?}", other),
+    }
+    match DDSketch::decode_from(FailingIo) {
+        Err(sketches_rust::Error::IoError(error)) => {
+            assert_eq!(std::io::ErrorKind::ConnectionReset, error.kind())
+        }
+        other => panic!("unexpected result: {:?}", other),
+    }
+
+    let bytes = sketch.encode().unwrap();
+    let truncated = &bytes[..bytes.len() - 1];
+    match DDSketch::decode_from(truncated) {
+        Err(sketches_rust::Error::Decode { source, .. }) => match *source {
+            sketches_rust::Error::TruncatedInput { position } => {
+                assert_eq!(truncated.len(), position)
+            }
+            other => panic!("unexpected source: {:?}", other),
+        },
+        other => panic!("unexpected result: {:?}", other),
+    }
+}
+
+#[test]
+fn test_sketch_decode_borrowed_slice() {
+    let mut sketch = DDSketch::unbounded_dense(0.01).unwrap();
+    for value in [-3.0, 0.0, 1.0, 2.0, 1e6] {
+        sketch.accept(value);
+    }
+    let encoded = sketch.encode().unwrap();
+    let mut payload = vec![0xff; 3];
+    payload.extend_from_slice(&encoded);
+    payload.extend_from_slice(&[0xff; 5]);
+
+    let slice: &[u8] = &payload[3..3 + encoded.len()];
+    assert_eq!(sketch, DDSketch::decode(slice).unwrap());
+    let mut merged = DDSketch::unbounded_dense(0.01).unwrap();
+    merged.decode_and_merge_with(slice).unwrap();
+    merged.decode_and_merge_with(&encoded).unwrap();
+    assert_eq!(2.0 * sketch.get_count(), merged.get_count());
+}
+
+#[cfg(feature = "bytes")]
+#[test]
+fn test_sketch_decode_bytes() {
+    use bytes::Buf;
+
+    let mut sketch = DDSketch::sparse(0.01).unwrap();
+    for value in [-3.0, 0.0, 1.0, 2.0, 1e6] {
+        sketch.accept(value);
+    }
+    let encoded = bytes::Bytes::from(sketch.encode().unwrap());
+    assert_eq!(sketch, DDSketch::decode(&encoded).unwrap());
+    assert_eq!(sketch, DDSketch::decode_from_buf(encoded.clone()).unwrap());
+
+    let (head, tail) = encoded.split_at(encoded.len() / 2);
+    let chained = head.chain(tail);
+    assert_eq!(sketch, DDSketch::decode_from_buf(chained).unwrap());
+
+    let mut merged = DDSketch::sparse(0.01).unwrap();
+    merged.decode_and_merge_from_buf(encoded.clone()).unwrap();
+    merged.decode_and_merge_from_buf(head.chain(tail)).unwrap();
+    assert_eq!(2.0 * sketch.get_count(), merged.get_count());
+}
+
+#[test]
+fn test_sketch_encoded_len() {
+    let mut sketches = [
+        DDSketch::unbounded_dense(0.01).unwrap(),
+        DDSketch::sparse(0.01).unwrap(),
+        DDSketch::paginated(0.01).unwrap(),
+        DDSketch::collapsing_lowest_dense(0.01, 64).unwrap(),
+        DDSketchBuilder::new()
+            .relative_accuracy(0.01)
+            .exact_summary_statistics(true)
+            .build()
+            .unwrap(),
+    ];
+    for sketch in sketches.iter_mut() {
+        assert_eq!(sketch.encode().unwrap().len(), sketch.encoded_len());
+        for value in [-1e6, -2.0, 0.0, 0.5, 1.0, 3.0, 1e9] {
+            sketch.accept(value);
+        }
+        assert_eq!(sketch.encode().unwrap().len(), sketch.encoded_len());
+    }
+}
+
+#[test]
+fn test_sketch_encode_into() {
+    let mut sketches = [
+        DDSketch::unbounded_dense(0.01).unwrap(),
+        DDSketch::sparse(0.02).unwrap(),
+    ];
+    for (i, sketch) in sketches.iter_mut().enumerate() {
+        for value in 0..100 {
+            sketch.accept(value as f64 * (i + 1) as f64);
+        }
+    }
+
+    let mut arena = vec![0; sketches.iter().map(|sketch| sketch.encoded_len()).sum()];
+    let mut offsets = vec![0];
+    for sketch in &sketches {
+        let start = *offsets.last().unwrap();
+        let len = sketch.encode_into(&mut arena[start..]).unwrap();
+        assert_eq!(sketch.encoded_len(), len);
+        offsets.push(start + len);
+    }
+    assert_eq!(arena.len(), *offsets.last().unwrap());
+    for (i, sketch) in sketches.iter().enumerate() {
+        let bytes = &arena[offsets[i]..offsets[i + 1]];
+        assert_eq!(sketch.encode().unwrap(), bytes);
+        assert_eq!(*sketch, DDSketch::decode(bytes).unwrap());
+    }
+
+    let needed = sketches[0].encoded_len();
+    let mut too_small = vec![0; needed - 1];
+    assert!(matches!(
+        sketches[0].encode_into(&mut too_small),
+        Err(sketches_rust::Error::BufferTooSmall { needed: n, available })
+            if n == needed && available == needed - 1
+    ));
+    assert!(too_small.iter().all(|byte| *byte == 0));
+}
+
+#[test]
+fn test_sketch_decode_errors() {
+    use sketches_rust::Error;
+    use std::error::Error as _;
+
+    let mut sketch = DDSketch::unbounded_dense(0.01).unwrap();
+    sketch.accept(1.0);
+    let bytes = sketch.encode().unwrap();
+    // The index mapping takes the first 17 bytes, then comes the positive store.
+    let store_position = 17;
+
+    let truncated = &bytes[..bytes.len() - 1];
+    let error = DDSketch::decode(truncated).unwrap_err();
+    match &error {
+        Error::Decode { position, source } => {
+            assert_eq!(store_position, *position);
+            assert!(matches!(
+                **source,
+                Error::TruncatedInput { position } if position == truncated.len()
+            ));
+        }
+        other => panic!("unexpected error: {:?}", other),
+    }
+    assert!(matches!(
+        error.source().unwrap().downcast_ref::<Error>(),
+        Some(Error::TruncatedInput { .. })
+    ));
+    assert_eq!(
+        format!(
+            "Decode error at byte 17: Truncated input: ended at byte {}",
+            truncated.len()
+        ),
+        error.to_string()
+    );
+
+    // A positive store flag with an unused bin encoding.
+    let mut unknown_flag = bytes.clone();
+    unknown_flag[store_position] = 0b1_0001;
+    match DDSketch::decode(&unknown_flag).unwrap_err() {
+        Error::Decode { position, source } => {
+            assert_eq!(store_position, position);
+            assert!(matches!(
+                *source,
+                Error::UnknownFlag {
+                    marker: 0b1_0001,
+                    section: "bin encoding"
+                }
+            ));
+        }
+        other => panic!("unexpected error: {:?}", other),
+    }
+    let mut unknown_layout = bytes.clone();
+    unknown_layout[0] = 0b1_1110;
+    match DDSketch::decode(&unknown_layout).unwrap_err() {
+        Error::Decode { position, source } => {
+            assert_eq!(0, position);
+            assert!(matches!(
+                *source,
+                Error::UnknownFlag {
+                    marker: 0b1_1110,
+                    section: "index mapping"
+                }
+            ));
+        }
+        other => panic!("unexpected error: {:?}", other),
+    }
+
+    // A positive store with a single bin at an index beyond i32.
+    let mut overflow = bytes[..store_position].to_vec();
+    overflow.extend_from_slice(&[0b1001, 1, 0xfe, 0xff, 0xff, 0xff, 0x1f, 2]);
+    match DDSketch::decode(&overflow).unwrap_err() {
+        Error::Decode { source, .. } => {
+            assert!(matches!(*source, Error::IndexOverflow(4294967295)))
+        }
+        other => panic!("unexpected error: {:?}", other),
+    }
+}
+
+#[test]
+fn test_sketch_argument_errors() {
+    use sketches_rust::Error;
+
+    assert!(matches!(
+        DDSketch::unbounded_dense(1.5),
+        Err(Error::InvalidRelativeAccuracy(relative_accuracy)) if relative_accuracy == 1.5
+    ));
+
+    let mut sketch = DDSketch::unbounded_dense(0.01).unwrap();
+    let other = DDSketch::unbounded_dense(0.02).unwrap();
+    match sketch.subtract(&other, SubtractionPolicy::Clamp) {
+        Err(Error::MappingMismatch { expected, actual }) => {
+            assert_eq!(sketch.get_index_mapping(), &expected);
+            assert_eq!(other.get_index_mapping(), &actual);
+        }
+        other => panic!("unexpected result: {:?}", other),
+    }
+}
+
+/// Returns what went wrong within a section of the input, if the error is about one.
+fn decode_error_cause(error: sketches_rust::Error) -> sketches_rust::Error {
+    match error {
+        sketches_rust::Error::Decode { source, .. } => *source,
+        other => other,
+    }
+}
+
+#[test]
+fn test_sketch_decode_with_options() {
+    use sketches_rust::Error;
+
+    let mut sketch = DDSketch::unbounded_dense(0.01).unwrap();
+    for value in [0.0, 1.0, 2.0, 3.0] {
+        sketch.accept(value);
+    }
+    let bytes = sketch.encode().unwrap();
+    let mapping = &bytes[..17];
+    let strict = DecodeOptions::new()
+        .max_num_bins(1000)
+        .max_index_span(10_000)
+        .max_len(1024)
+        .reject_invalid_counts(true)
+        .reject_duplicate_index_mappings(true);
+    assert_eq!(
+        sketch,
+        DDSketch::decode_with_options(&bytes, &strict).unwrap()
+    );
+    assert_eq!(
+        sketch,
+        DDSketch::decode_from_with_options(bytes.as_slice(), &strict).unwrap()
+    );
+
+    // Contiguous counts declaring 2^40 bins.
+    let mut many_bins = mapping.to_vec();
+    many_bins.extend_from_slice(&[0b1101, 128, 128, 128, 128, 128, 32, 0, 2, 2]);
+    assert!(matches!(
+        decode_error_cause(DDSketch::decode_with_options(&many_bins, &strict).unwrap_err()),
+        Error::LimitExceeded {
+            limit: "number of bins",
+            max: 1000
+        }
+    ));
+
+    // Two bins, 10^8 indices apart.
+    let mut wide = mapping.to_vec();
+    wide.extend_from_slice(&[0b1001, 2, 0, 128, 132, 175, 95]);
+    assert!(matches!(
+        decode_error_cause(DDSketch::decode_with_options(&wide, &strict).unwrap_err()),
+        Error::LimitExceeded {
+            limit: "index span",
+            max: 10_000
+        }
+    ));
+
+    // A bin with a count of -1.
+    let mut negative_count = mapping.to_vec();
+    negative_count.extend_from_slice(&[0b0101, 1, 0, 130, 128, 128, 128, 128, 128, 128, 128, 48]);
+    assert_eq!(0.0, DDSketch::decode(&negative_count).unwrap().get_count());
+    assert!(matches!(
+        decode_error_cause(DDSketch::decode_with_options(&negative_count, &strict).unwrap_err()),
+        Error::InvalidCount(count) if count == -1.0
+    ));
+
+    let mut duplicate_mapping = mapping.to_vec();
+    duplicate_mapping.extend_from_slice(&bytes);
+    assert_eq!(sketch, DDSketch::decode(&duplicate_mapping).unwrap());
+    assert!(matches!(
+        decode_error_cause(DDSketch::decode_with_options(&duplicate_mapping, &strict).unwrap_err()),
+        Error::DuplicateSection {
+            section: "index mapping"
+        }
+    ));
+
+    let short = DecodeOptions::new().max_len(bytes.len() - 1);
+    assert!(matches!(
+        DDSketch::decode_with_options(&bytes, &short),
+        Err(Error::LimitExceeded {
+            limit: "input length",
+            ..
+        })
+    ));
+    assert!(matches!(
+        DDSketch::decode_from_with_options(bytes.as_slice(), &short).map_err(decode_error_cause),
+        Err(Error::LimitExceeded {
+            limit: "input length",
+            ..
+        })
+    ));
+
+    let mut merged = sketch.clone();
+    assert!(merged
+        .decode_and_merge_with_options(&negative_count, &strict)
+        .is_err());
+    assert_eq!(sketch, merged);
+    merged
+        .decode_and_merge_with_options(&bytes, &strict)
+        .unwrap();
+    assert_eq!(2.0 * sketch.get_count(), merged.get_count());
+}